@@ -1,8 +1,37 @@
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::{parse_macro_input, ItemEnum};
+use syn::{parse_macro_input, ItemEnum, LitStr, Variant};
 
-#[proc_macro_derive(FromArticle)]
+/// Spellings from `#[article(aliases = "a, b")]` that also mean `variant`.
+fn aliases(variant: &Variant) -> syn::Result<Vec<String>> {
+    let mut aliases = Vec::new();
+
+    for attr in variant
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("article"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if !meta.path.is_ident("aliases") {
+                return Err(meta.error("expected `aliases`"));
+            }
+
+            let list: LitStr = meta.value()?.parse()?;
+            aliases.extend(
+                list.value()
+                    .split(',')
+                    .map(|alias| alias.trim().to_owned())
+                    .filter(|alias| !alias.is_empty()),
+            );
+
+            Ok(())
+        })?;
+    }
+
+    Ok(aliases)
+}
+
+#[proc_macro_derive(FromArticle, attributes(article))]
 pub fn my_macro(input: TokenStream) -> TokenStream {
     // Parse the input tokens into a syntax tree
     let input = parse_macro_input!(input as ItemEnum);
@@ -13,43 +42,53 @@ pub fn my_macro(input: TokenStream) -> TokenStream {
         .iter()
         .map(|variant| {
             let var_ident = variant.ident.clone();
-            let class_name = variant.ident.to_string();
-            let class_name_up = class_name.to_ascii_uppercase();
-            let class_name_lower = class_name.to_ascii_lowercase();
+            let names = std::iter::once(variant.ident.to_string())
+                .chain(aliases(variant)?)
+                .flat_map(|name| [name.to_ascii_uppercase(), name.to_ascii_lowercase(), name]);
 
-            quote! {
-                if let Some(dist) = s.find(#class_name) {
-                    if curr_dist > dist {
-                        curr_dist = dist;
-                        curr_class = Some(#ident::#var_ident);
-                    }
+            Ok(quote! {
+                let dist = [#(find_word(#names)),*]
+                    .into_iter()
+                    .flatten()
+                    .min();
+                if let Some(dist) = dist {
+                    found.push((#ident::#var_ident, dist));
                 }
-                if let Some(dist) = s.find(#class_name_up) {
-                    if curr_dist > dist {
-                        curr_dist = dist;
-                        curr_class = Some(#ident::#var_ident);
-                    }
-                }
-                if let Some(dist) = s.find(#class_name_lower) {
-                    if curr_dist > dist {
-                        curr_dist = dist;
-                        curr_class = Some(#ident::#var_ident);
-                    }
-                }
-            }
+            })
         })
-        .collect::<Vec<_>>();
+        .collect::<syn::Result<Vec<_>>>();
+    let checks = match checks {
+        Ok(checks) => checks,
+        Err(err) => return err.to_compile_error().into(),
+    };
 
     // Build the output, possibly using quasi-quotation
     let expanded = quote! {
         impl #ident {
             fn from_article(s: &str) -> Option<Self> {
-                let mut curr_dist = usize::MAX;
-                let mut curr_class = None;
+                Self::from_article_all(s).into_iter().next().map(|(class, _)| class)
+            }
+
+            /// Every variant found in `s` with the byte offset of its first match, closest first.
+            fn from_article_all(s: &str) -> Vec<(Self, usize)> {
+                let mut found = Vec::new();
+
+                // Only whole words count, so "Dark" doesn't match inside "darkness"
+                let find_word = |word: &str| {
+                    s.match_indices(word).map(|(idx, _)| idx).find(|&idx| {
+                        !s[..idx].chars().next_back().is_some_and(char::is_alphabetic)
+                            && !s[idx + word.len()..]
+                                .chars()
+                                .next()
+                                .is_some_and(char::is_alphabetic)
+                    })
+                };
 
                 #(#checks)*
 
-                curr_class
+                // The sort is stable, so on a tie the variant declared first still wins
+                found.sort_by_key(|(_, dist)| *dist);
+                found
             }
         }
     };
@@ -76,18 +115,63 @@ pub fn as_text(input: TokenStream) -> TokenStream {
             }
         })
         .collect::<Vec<_>>();
+    let reverse_matches = input
+        .variants
+        .iter()
+        .map(|variant| {
+            let var_ident = variant.ident.clone();
+            let class_name_up = variant.ident.to_string().to_ascii_uppercase();
+
+            quote! {
+                #class_name_up => Some(#ident::#var_ident)
+            }
+        })
+        .collect::<Vec<_>>();
+    let texts = input
+        .variants
+        .iter()
+        .map(|variant| variant.ident.to_string().to_ascii_uppercase());
 
     // Build the output, possibly using quasi-quotation
     let expanded = quote! {
         impl #ident {
-            fn as_text(&self) -> &str {
+            /// What `as_text` returns for each variant.
+            const TEXTS: &'static [&'static str] = &[#(#texts),*];
+
+            fn as_text(&self) -> &'static str {
                 match self {
                     #(#matches),*
                 }
             }
+
+            /// The variant whose `as_text` is exactly `s`, ignoring case.
+            fn from_text(s: &str) -> Option<Self> {
+                match s.trim().to_ascii_uppercase().as_str() {
+                    #(#reverse_matches,)*
+                    _ => None,
+                }
+            }
         }
     };
 
     // Hand the output tokens back to the compiler
     TokenStream::from(expanded)
 }
+
+/// Implements `Display` with the text from the `AsText` derive, which has to be derived as well.
+#[proc_macro_derive(AsTextDisplay)]
+pub fn as_text_display(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as ItemEnum);
+
+    let ident = input.ident;
+
+    let expanded = quote! {
+        impl std::fmt::Display for #ident {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(self.as_text())
+            }
+        }
+    };
+
+    TokenStream::from(expanded)
+}
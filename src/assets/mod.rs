@@ -0,0 +1,38 @@
+pub const SCP_LOGO: &str = "assets/SCP.png";
+
+pub const CLASS_ICONS: &[&str] = &[
+    "assets/containment/Safe.png",
+    "assets/containment/Euclid.png",
+    "assets/containment/Keter.png",
+    "assets/containment/Neutralized.png",
+    "assets/containment/Pending.png",
+    "assets/containment/Explained.png",
+    "assets/containment/Esoteric.png",
+    "assets/secondary/Apollyon.png",
+    "assets/secondary/Archon.png",
+    "assets/secondary/Cernunnos.png",
+    "assets/secondary/Decommissioned.png",
+    "assets/secondary/Hiemal.png",
+    "assets/secondary/Tiamat.png",
+    "assets/secondary/Ticonderoga.png",
+    "assets/secondary/Thaumiel.png",
+    "assets/secondary/Uncontained.png",
+    "assets/disruption/Dark.png",
+    "assets/disruption/Vlam.png",
+    "assets/disruption/Keneq.png",
+    "assets/disruption/Ekhi.png",
+    "assets/disruption/Amida.png",
+    "assets/risk/Notice.png",
+    "assets/risk/Caution.png",
+    "assets/risk/Warning.png",
+    "assets/risk/Danger.png",
+    "assets/risk/Critical.png",
+];
+
+/// Checks that every image asset the videos use exists and decodes, returning the ones that don't.
+pub fn verify() -> Vec<(&'static str, image::ImageError)> {
+    std::iter::once(SCP_LOGO)
+        .chain(CLASS_ICONS.iter().copied())
+        .filter_map(|path| image::open(path).err().map(|err| (path, err)))
+        .collect()
+}
@@ -4,7 +4,7 @@ use std::fs;
 //     api::{Video, VideoSnippet, VideoStatus},
 //     hyper, hyper_rustls, oauth2, YouTube,
 // };
-use hoti_rs::{scp::SCP, ContentSource};
+use hoti_rs::{manifest::VideoManifest, scp::SCP, ContentSource};
 use reqwest_middleware::ClientBuilder;
 
 #[tokio::main]
@@ -58,13 +58,26 @@ async fn main() -> anyhow::Result<()> {
             break;
         };
 
-        let title = scp.title(reqwest.clone()).await.unwrap_or("Unknown".into());
+        let title = match scp.title(reqwest.clone()).await {
+            Ok(Some(title)) => title,
+            Ok(None) => "Unknown".into(),
+            Err(err) => {
+                println!("Warning: couldn't get the title of {}: {err:?}", scp.name());
+                "Unknown".into()
+            }
+        };
 
         println!("{name}: {title} | Summarized");
         println!(
             "#shorts #scp #mystery #fiction #horror #summary\nFull SCP: {}",
             scp.url()
         );
+        if let Ok(manifest) = VideoManifest::read(format!("{}.json", scp.name())) {
+            let timestamps = manifest.description_timestamps();
+            if !timestamps.is_empty() {
+                println!("\n{timestamps}");
+            }
+        }
         println!("")
 
         // As the method needs a request, you would usually fill it with the desired information
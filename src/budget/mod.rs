@@ -0,0 +1,60 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Caps how many retries a single SCP may use across every API it calls, so one bad SCP can't
+/// make an unbounded number of requests.
+#[derive(Debug)]
+pub struct RetryBudget {
+    remaining: AtomicU32,
+}
+
+impl RetryBudget {
+    pub fn new(retries: u32) -> Self {
+        RetryBudget {
+            remaining: AtomicU32::new(retries),
+        }
+    }
+
+    /// Takes one retry out of the budget, failing with [`BudgetExhausted`] once it is used up.
+    pub fn spend(&self) -> Result<(), BudgetExhausted> {
+        self.remaining
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |remaining| {
+                remaining.checked_sub(1)
+            })
+            .map(|_| ())
+            .map_err(|_| BudgetExhausted)
+    }
+
+    pub fn remaining(&self) -> u32 {
+        self.remaining.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug)]
+pub struct BudgetExhausted;
+
+impl std::fmt::Display for BudgetExhausted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Retry budget is used up")
+    }
+}
+
+impl std::error::Error for BudgetExhausted {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn budget_runs_out() {
+        let budget = RetryBudget::new(2);
+
+        assert!(budget.spend().is_ok());
+        assert!(budget.spend().is_ok());
+        assert_eq!(budget.remaining(), 0);
+
+        // Once it is used up it stays used up
+        assert!(budget.spend().is_err());
+        assert!(budget.spend().is_err());
+        assert_eq!(budget.remaining(), 0);
+    }
+}
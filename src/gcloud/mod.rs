@@ -1,11 +1,43 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
 use reqwest::header::{HeaderMap, AUTHORIZATION};
+use tokio::process::Command;
 
 pub mod text_to_speech;
 
 #[derive(Clone)]
-pub struct Client(reqwest::Client);
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    /// The `gcloud` CLI that new access tokens come from
+    gcloud: PathBuf,
+}
 
 impl Client {
+    pub const DEFAULT_BASE_URL: &str = "https://texttospeech.googleapis.com";
+
+    /// Wraps an already configured client, e.g. one without auth for talking to a mock server.
+    pub fn from_client(http: reqwest::Client) -> Self {
+        Client {
+            http,
+            base_url: Self::DEFAULT_BASE_URL.into(),
+            gcloud: "gcloud".into(),
+        }
+    }
+
+    /// Sends requests to `base_url` instead of Google.
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    /// Runs the `gcloud` CLI at `program` for new access tokens instead of the one on the `PATH`.
+    pub fn with_gcloud(mut self, program: impl Into<PathBuf>) -> Self {
+        self.gcloud = program.into();
+        self
+    }
+
     pub fn from_env() -> anyhow::Result<Self> {
         let project = std::env::var("GOOGLE_PROJECT")?;
         let bearer_token = std::env::var("GOOGLE_BEARER_TOKEN")?;
@@ -18,22 +50,59 @@ impl Client {
             .default_headers(headers)
             .build()?;
 
-        Ok(Client(client))
+        Ok(Client::from_client(client))
+    }
+
+    /// Gets a new access token from the `gcloud` CLI and uses it from now on.
+    pub async fn refresh_bearer_token(&mut self) -> anyhow::Result<()> {
+        let output = Command::new(&self.gcloud)
+            .arg("auth")
+            .arg("print-access-token")
+            .output()
+            .await
+            .context("Failed to run the gcloud CLI for a new access token, is it installed?")?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "The gcloud CLI failed to print an access token ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        let token = String::from_utf8(output.stdout)
+            .context("The gcloud CLI printed an access token that isn't UTF-8")?;
+
+        self.remake_with_bearer_token(token)
     }
 
     pub fn remake_with_bearer_token(&mut self, token: String) -> anyhow::Result<()> {
         let token = token.trim();
-        println!("{token}");
         let project = std::env::var("GOOGLE_PROJECT")?;
 
         let mut headers = HeaderMap::default();
         headers.insert("x-goog-user-project", project.parse()?);
         headers.insert(AUTHORIZATION, format!("Bearer {token}").parse()?);
 
-        self.0 = reqwest::Client::builder()
+        self.http = reqwest::Client::builder()
             .default_headers(headers)
             .build()?;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn a_failing_gcloud_is_an_error() {
+        for program in ["false", "/nonexistent/gcloud"] {
+            let mut client = Client::from_client(reqwest::Client::new()).with_gcloud(program);
+
+            let err = client.refresh_bearer_token().await.unwrap_err();
+            assert!(format!("{err:#}").contains("gcloud CLI"), "{err:#}");
+        }
+    }
+}
@@ -1,7 +1,6 @@
-use std::marker::PhantomData;
-
 use base64::Engine;
-use tokio::process::Command;
+
+use crate::budget::RetryBudget;
 
 use super::Client;
 
@@ -12,16 +11,16 @@ pub enum SynthesisInput {
     Ssml(String),
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct VoiceSelectionParams<'s> {
-    language_code: &'s str,
-    name: &'s str,
-    ssml_gender: SsmlVoiceGender,
-    custom_voice: Option<CustomVoiceParams<'s>>,
+    pub language_code: &'s str,
+    pub name: &'s str,
+    pub ssml_gender: SsmlVoiceGender,
+    pub custom_voice: Option<CustomVoiceParams<'s>>,
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum SsmlVoiceGender {
     SsmlVoiceGenderUnspecified,
@@ -30,14 +29,14 @@ pub enum SsmlVoiceGender {
     Neutral,
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CustomVoiceParams<'s> {
-    model: &'s str,
-    reported_usage: ReportedUsage,
+    pub model: &'s str,
+    pub reported_usage: ReportedUsage,
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum ReportedUsage {
     ReportedUsageUnspecified,
@@ -45,18 +44,28 @@ pub enum ReportedUsage {
     Offline,
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AudioConfig {
-    audio_encoding: AudioEncoding,
-    speaking_rate: f64,
-    pitch: f64,
-    volume_gain_db: f64,
-    sample_rate_hertz: u64,
+    pub audio_encoding: AudioEncoding,
+    pub speaking_rate: f64,
+    pub pitch: f64,
+    pub volume_gain_db: f64,
+    pub sample_rate_hertz: u64,
     // effects_profile_id: [&'s str; N],
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+impl AudioConfig {
+    /// The fastest narration still sounds natural at, the API itself allows up to 4.0.
+    pub const MAX_SPEAKING_RATE: f64 = 1.6;
+
+    pub fn with_speaking_rate(mut self, speaking_rate: f64) -> Self {
+        self.speaking_rate = speaking_rate.min(Self::MAX_SPEAKING_RATE);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum AudioEncoding {
     AudioEncodingUnspecified,
@@ -68,37 +77,138 @@ pub enum AudioEncoding {
     Alaw,
 }
 
+impl AudioEncoding {
+    /// The sample rates this encoding can be synthesized at, `None` if it takes any rate the voice
+    /// does.
+    pub fn sample_rates(self) -> Option<&'static [u64]> {
+        match self {
+            AudioEncoding::AudioEncodingUnspecified | AudioEncoding::Linear16 => None,
+            AudioEncoding::Mp3 | AudioEncoding::Mp3_64Kbps => {
+                Some(&[8000, 11025, 12000, 16000, 22050, 24000, 32000, 44100, 48000])
+            }
+            AudioEncoding::OggOpus => Some(&[8000, 12000, 16000, 24000, 48000]),
+            AudioEncoding::Mulaw | AudioEncoding::Alaw => Some(&[8000]),
+        }
+    }
+}
+
+// Voice types by the part of their name after the language, with the highest sample rate they are
+// synthesized at. Anything above that is only upsampled so it is treated as a mistake.
+const VOICE_TYPES: &[(&str, u64)] = &[
+    ("Standard", 24000),
+    ("Wavenet", 24000),
+    ("Neural2", 24000),
+    ("News", 24000),
+    ("Studio", 24000),
+    ("Polyglot", 24000),
+    ("Journey", 24000),
+];
+
+/// Checks that `voice` can be synthesized with `audio_config` before anything is sent, since the
+/// API only answers a mismatch with an error the retries can't fix.
+pub fn validate_voice(
+    voice: &VoiceSelectionParams,
+    audio_config: &AudioConfig,
+) -> anyhow::Result<()> {
+    let AudioConfig {
+        audio_encoding,
+        sample_rate_hertz,
+        ..
+    } = *audio_config;
+
+    if matches!(audio_encoding, AudioEncoding::AudioEncodingUnspecified) {
+        anyhow::bail!("No audio encoding was picked for the voice {}", voice.name);
+    }
+
+    if let Some(rates) = audio_encoding.sample_rates() {
+        if !rates.contains(&sample_rate_hertz) {
+            anyhow::bail!(
+                "{audio_encoding:?} audio can't be {sample_rate_hertz} Hz, it has to be one of {rates:?} Hz"
+            );
+        }
+    }
+
+    if !voice.name.starts_with(voice.language_code) {
+        anyhow::bail!(
+            "The voice {} doesn't speak the language {}",
+            voice.name,
+            voice.language_code
+        );
+    }
+
+    let voice_type = voice
+        .name
+        .strip_prefix(voice.language_code)
+        .and_then(|rest| rest.trim_start_matches('-').split('-').next())
+        .unwrap_or_default();
+    if let Some((_, max_rate)) = VOICE_TYPES.iter().find(|(name, _)| *name == voice_type) {
+        if sample_rate_hertz > *max_rate {
+            anyhow::bail!(
+                "The {voice_type} voice {} only goes up to {max_rate} Hz, {sample_rate_hertz} Hz was requested",
+                voice.name
+            );
+        }
+    }
+
+    Ok(())
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub struct SynthesisPayload<'s, L: Language> {
+pub struct SynthesisPayload<'s> {
     input: SynthesisInput,
     #[serde(borrow)]
     voice: VoiceSelectionParams<'s>,
     audio_config: AudioConfig,
-    #[serde(skip)]
-    _phantom: PhantomData<fn() -> L>,
 }
 
-impl<'s, L: Language> SynthesisPayload<'s, L> {
-    pub const URL: &str = "https://texttospeech.googleapis.com/v1beta1/text:synthesize";
+impl<'s> SynthesisPayload<'s> {
+    pub const PATH: &str = "/v1beta1/text:synthesize";
 
-    pub fn from_text(text: String) -> SynthesisPayload<'static, L> {
+    pub fn from_text(
+        text: String,
+        voice: VoiceSelectionParams<'s>,
+        audio_config: AudioConfig,
+    ) -> SynthesisPayload<'s> {
         SynthesisPayload {
             input: SynthesisInput::Text(text),
-            voice: L::VOICE,
-            audio_config: L::AUDIO,
-            _phantom: PhantomData::default(),
+            voice,
+            audio_config,
         }
     }
 
-    pub async fn synthesize(client: &mut Client, text: L) -> Vec<u8> {
+    /// Synthesizes `text` with the voice of its language.
+    pub async fn synthesize<L: Language>(
+        client: &mut Client,
+        text: L,
+        budget: &RetryBudget,
+    ) -> anyhow::Result<Vec<u8>> {
+        SynthesisPayload::synthesize_with_voice(
+            client,
+            text.inner_string(),
+            L::VOICE,
+            L::AUDIO,
+            budget,
+        )
+        .await
+    }
+
+    /// Synthesizes `text` with any voice, whatever language the text is in.
+    pub async fn synthesize_with_voice(
+        client: &mut Client,
+        text: String,
+        voice: VoiceSelectionParams<'s>,
+        audio_config: AudioConfig,
+        budget: &RetryBudget,
+    ) -> anyhow::Result<Vec<u8>> {
         #[derive(serde::Deserialize)]
         #[serde(rename_all = "camelCase")]
         pub struct Response {
             audio_content: String,
         }
 
-        let text = text.inner_string();
+        validate_voice(&voice, &audio_config)?;
+
         let mut parts: Vec<String> = Vec::default();
 
         for part in text.split(".") {
@@ -117,17 +227,17 @@ impl<'s, L: Language> SynthesisPayload<'s, L> {
         let mut audio_content = Vec::default();
 
         for part in parts {
-            let payload = Self::from_text(part);
+            let payload = Self::from_text(part, voice.clone(), audio_config.clone());
             let mut attempt = 0;
 
             let response = loop {
                 if attempt > 10 {
-                    panic!("Failed to create a valid text-to-speech client");
+                    anyhow::bail!("Failed to create a valid text-to-speech client");
                 }
 
                 let response = client
-                    .0
-                    .post(Self::URL)
+                    .http
+                    .post(format!("{}{}", client.base_url, Self::PATH))
                     .json(&payload)
                     .send()
                     .await
@@ -141,42 +251,66 @@ impl<'s, L: Language> SynthesisPayload<'s, L> {
                     Ok(response) => break response,
                     Err(err) => {
                         println!("Got error while trying to do text-to-speech: {err:?}");
+                        budget.spend()?;
 
-                        let output = Command::new("gcloud")
-                            .arg("auth")
-                            .arg("print-access-token")
-                            .output()
-                            .await
-                            .unwrap();
-                        client
-                            .remake_with_bearer_token(String::from_utf8(output.stdout).unwrap())
-                            .unwrap();
+                        client.refresh_bearer_token().await?;
                     }
                 }
             };
 
             let mut output: Vec<u8> = (0..response.audio_content.len()).map(|_| 0).collect();
-            base64::prelude::BASE64_STANDARD
+            let len = base64::prelude::BASE64_STANDARD
                 .decode_slice(&response.audio_content, &mut output)
                 .unwrap();
+            output.truncate(len);
 
             audio_content.extend(output);
         }
 
-        audio_content
+        Ok(audio_content)
     }
 }
 
 pub trait Language {
+    /// The name of the language in English, e.g. "Hindi"
+    const NAME: &'static str;
     const VOICE: VoiceSelectionParams<'static>;
     const AUDIO: AudioConfig;
 
     fn inner_string(self) -> String;
 }
 
+/// The settings of a [`Language`], for picking one at runtime.
+#[derive(Debug, Clone)]
+pub struct LanguageSettings {
+    pub name: &'static str,
+    pub voice: VoiceSelectionParams<'static>,
+    pub audio: AudioConfig,
+}
+
+impl LanguageSettings {
+    pub fn of<L: Language>() -> Self {
+        LanguageSettings {
+            name: L::NAME,
+            voice: L::VOICE,
+            audio: L::AUDIO,
+        }
+    }
+
+    /// The language for an ISO 639-1 code like "en" or "hi", `None` if there is no voice for it.
+    pub fn for_code(code: &str) -> Option<Self> {
+        match code {
+            "en" => Some(Self::of::<EnString>()),
+            "hi" => Some(Self::of::<HiString>()),
+            _ => None,
+        }
+    }
+}
+
 pub struct EnString(pub String);
 
 impl Language for EnString {
+    const NAME: &'static str = "English";
     const VOICE: VoiceSelectionParams<'static> = VoiceSelectionParams {
         language_code: "en-US",
         name: "en-US-Studio-M",
@@ -200,6 +334,7 @@ impl Language for EnString {
 pub struct HiString(pub String);
 
 impl Language for HiString {
+    const NAME: &'static str = "Hindi";
     const VOICE: VoiceSelectionParams<'static> = VoiceSelectionParams {
         language_code: "hi-IN",
         name: "hi-IN-Neural2-B",
@@ -219,3 +354,83 @@ impl Language for HiString {
         self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn synthesizes_against_a_mock_server() {
+        use wiremock::{
+            matchers::{body_partial_json, method, path},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path(SynthesisPayload::PATH))
+            .and(body_partial_json(serde_json::json!({
+                "input": { "text": "Hello there" },
+                "voice": { "name": "en-US-Studio-M" },
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "audioContent": base64::prelude::BASE64_STANDARD.encode("ID3 audio"),
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut client = Client::from_client(reqwest::Client::new()).with_base_url(server.uri());
+        let audio = SynthesisPayload::synthesize(
+            &mut client,
+            EnString("Hello there".into()),
+            &RetryBudget::new(0),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(audio, b"ID3 audio");
+    }
+
+    #[tokio::test]
+    async fn incompatible_voices_are_rejected_before_sending() {
+        use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&server)
+            .await;
+        let mut client = Client::from_client(reqwest::Client::new()).with_base_url(server.uri());
+
+        let mulaw = AudioConfig {
+            audio_encoding: AudioEncoding::Mulaw,
+            ..EnString::AUDIO
+        };
+        let too_fine = AudioConfig {
+            sample_rate_hertz: 48000,
+            ..EnString::AUDIO
+        };
+        let wrong_language = VoiceSelectionParams {
+            language_code: "hi-IN",
+            ..EnString::VOICE
+        };
+
+        for (voice, audio_config) in [
+            (EnString::VOICE, mulaw),
+            (EnString::VOICE, too_fine),
+            (wrong_language, EnString::AUDIO),
+        ] {
+            let synthesized = SynthesisPayload::synthesize_with_voice(
+                &mut client,
+                "Hello there".into(),
+                voice,
+                audio_config,
+                &RetryBudget::new(0),
+            )
+            .await;
+            assert!(synthesized.is_err());
+        }
+    }
+}
@@ -1,9 +1,14 @@
 #![feature(async_fn_in_trait)]
 
-use async_openai::config::OpenAIConfig;
+use async_openai::config::Config;
+use reqwest::header::HeaderMap;
 use reqwest_middleware::ClientWithMiddleware;
 
+pub mod assets;
+pub mod budget;
 pub mod gcloud;
+pub mod manifest;
+pub mod pause;
 pub mod scp;
 pub mod video_gen;
 
@@ -23,3 +28,69 @@ pub trait ContentSource {
 
     fn iter() -> anyhow::Result<Self::ContentIter>;
 }
+
+/// OpenAI settings for `async_openai::Client`. async-openai's own `OpenAIConfig` builds every URL
+/// from the default API base, ignoring `with_api_base`, this one sends requests to the base it is
+/// given.
+#[derive(Debug, Clone, Default)]
+pub struct OpenAIConfig(async_openai::config::OpenAIConfig);
+
+impl OpenAIConfig {
+    /// The default API base, with the API key from the OPENAI_API_KEY environment variable.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_api_key(self, api_key: impl Into<String>) -> Self {
+        OpenAIConfig(self.0.with_api_key(api_key))
+    }
+
+    pub fn with_api_base(self, api_base: impl Into<String>) -> Self {
+        OpenAIConfig(self.0.with_api_base(api_base))
+    }
+}
+
+impl Config for OpenAIConfig {
+    fn headers(&self) -> HeaderMap {
+        self.0.headers()
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.0.api_base())
+    }
+
+    fn query(&self) -> Vec<(&str, &str)> {
+        self.0.query()
+    }
+
+    fn api_base(&self) -> &str {
+        self.0.api_base()
+    }
+
+    fn api_key(&self) -> &str {
+        self.0.api_key()
+    }
+}
+
+/// A 64-bit FNV-1a hasher. Unlike `DefaultHasher` its hashes never change between builds, so they
+/// can be written to disk and compared later.
+#[derive(Debug, Clone, Copy)]
+pub struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Fnv1a(0xcbf29ce484222325)
+    }
+}
+
+impl std::hash::Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
@@ -1,22 +1,46 @@
 #![feature(async_fn_in_trait)]
 
-use std::fs;
+use std::{fs, io::Write};
 
+use anyhow::Context;
+use futures_util::StreamExt;
+use hoti_rs::budget::{BudgetExhausted, RetryBudget};
 use hoti_rs::gcloud;
-use hoti_rs::scp::SCP;
+use hoti_rs::pause::{wait_while_paused, PauseSignal};
+use hoti_rs::scp::{
+    ClassCategory, ClassLegend, Classification, Overrides, Refusal, StubArticle, SCP,
+};
 use hoti_rs::video_gen;
-use hoti_rs::{gcloud::text_to_speech::EnString, ContentSource};
-use reqwest_middleware::ClientBuilder;
+use hoti_rs::OpenAIConfig;
+use hoti_rs::{
+    gcloud::text_to_speech::{EnString, Language, SsmlVoiceGender, VoiceSelectionParams},
+    ContentSource,
+};
+use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
 use taffy::{
     prelude::{Rect, Size},
-    style::{Dimension, LengthPercentageAuto, Style},
+    style::{Dimension, LengthPercentageAuto, Position, Style},
 };
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenvy::dotenv().expect(".env file is missing!");
 
-    let openai = async_openai::Client::new();
+    let broken_assets = hoti_rs::assets::verify();
+    if !broken_assets.is_empty() {
+        for (path, err) in &broken_assets {
+            println!("Asset {path} can't be used: {err}");
+        }
+
+        anyhow::bail!("{} assets are missing or broken", broken_assets.len());
+    }
+
+    // OPENAI_API_BASE and GOOGLE_TTS_BASE_URL point the clients at another server, like a mock
+    let openai = match std::env::var("OPENAI_API_BASE") {
+        Ok(api_base) => OpenAIConfig::new().with_api_base(api_base),
+        Err(_) => OpenAIConfig::new(),
+    };
+    let openai = async_openai::Client::with_config(openai);
 
     let retry_policy =
         reqwest_retry::policies::ExponentialBackoff::builder().build_with_max_retries(5);
@@ -27,192 +51,1413 @@ async fn main() -> anyhow::Result<()> {
         .build();
 
     let mut client = hoti_rs::gcloud::Client::from_env()?;
+    if let Ok(base_url) = std::env::var("GOOGLE_TTS_BASE_URL") {
+        client = client.with_base_url(base_url);
+    }
+
+    let config = Config::from_env()?;
+
+    let first_idx = config.first_idx;
+    let (min_article_chars, refresh_completions) =
+        (config.min_article_chars, config.refresh_completions);
+    let scps = SCP::iter()?.skip(first_idx).map(move |mut scp| {
+        scp.set_min_article_chars(min_article_chars);
+        scp.set_refresh_completions(refresh_completions);
+        scp
+    });
+    let mut scps =
+        std::pin::pin!(
+            hoti_rs::scp::prefetch_articles(scps, reqwest.clone(), config.prefetch).enumerate()
+        );
 
-    for (idx, mut scp) in SCP::iter()?.enumerate().skip(102) {
+    while let Some((idx, mut scp)) = scps.next().await {
+        if let Some(pause_file) = &config.pause_file {
+            let pause_file = std::path::Path::new(pause_file);
+            if pause_file.is_paused() {
+                println!("Paused, remove {} to resume", pause_file.display());
+                let paused_for =
+                    wait_while_paused(pause_file, std::time::Duration::from_secs(5)).await;
+                println!("Resuming after {paused_for:?}");
+            }
+        }
+
+        let idx = first_idx + idx;
         let start = std::time::Instant::now();
+        let budget = RetryBudget::new(config.retry_budget);
 
         println!("Idx: {idx} - Generating: {}", scp.name());
 
-        let title = scp.title(reqwest.clone()).await.unwrap_or("Unknown".into());
-        let classification = scp.classification(reqwest.clone()).await?;
+        match produce(&mut scp, &config, &openai, &reqwest, &mut client, &budget).await {
+            Ok(()) => {}
+            Err(err) if err.is::<BudgetExhausted>() => {
+                println!("Skipping {}, it used up its retry budget", scp.name());
+                continue;
+            }
+            Err(err) if err.is::<StubArticle>() || err.is::<Refusal>() => {
+                println!("Skipping {}: {err}", scp.name());
+                continue;
+            }
+            Err(err) => return Err(err),
+        }
 
-        println!("Title: {title}");
-        println!("Classification: {classification:?}");
+        println!(
+            "Made video for {} and it took {:?}",
+            scp.name(),
+            start.elapsed()
+        );
+        println!("----------------------------------------------------------\n");
+    }
 
-        let dialogue = scp.dialogue(&openai, reqwest.clone()).await?;
-        let mut image_description = scp.image_description(&openai, reqwest.clone()).await?;
+    Ok(())
+}
 
-        println!("Generating Audio For Dialogue:\n{dialogue}");
-        println!("Image Description: {:#?}", image_description);
+/// Settings for the whole batch from the `HOTI_*` environment variables. They are read once at
+/// startup, so a bad value stops the batch before any video is made.
+struct Config {
+    first_idx: usize,
+    retry_budget: u32,
+    min_article_chars: usize,
+    prefetch: usize,
+    refresh_completions: bool,
+    pause_file: Option<String>,
+    safe_mode: bool,
+    sections: Option<Vec<String>>,
+    class_window: Option<usize>,
+    omit_missing_classes: bool,
+    beats: Option<usize>,
+    max_duration: Option<std::time::Duration>,
+    keep_intermediate: bool,
+    frame_rate: u32,
+    preset: video_gen::VideoPreset,
+    frame_hashes: Option<String>,
+    overlay: Option<OverlayConfig>,
+    subtitle_contrast: bool,
+    legend_secs: Option<u32>,
+    scroll: bool,
+    image_style: Option<String>,
+    no_images: bool,
+    image_count: Option<u8>,
+    image_concurrency: usize,
+    /// The contents of the SRT file, not its path
+    srt: Option<String>,
+    rolling_captions: Option<usize>,
+    pop_in: Option<(f32, u32)>,
+    poster: bool,
+    backdrop: bool,
+    encode: video_gen::EncodeConfig,
+    codec: video_gen::VideoCodec,
+    raw_out: Option<String>,
+    music: Option<String>,
+    ducking: video_gen::Ducking,
+    preview: Option<u32>,
+    languages: Option<Vec<String>>,
+}
 
-        let mut path = std::env::temp_dir();
-        path.push(format!("{}-output.mp3", scp.name()));
+/// What the info overlay in the corner of the video shows.
+struct OverlayConfig {
+    name: bool,
+    time: bool,
+}
 
-        let contents = gcloud::text_to_speech::SynthesisPayload::synthesize(
-            &mut client,
-            EnString(dialogue.clone()),
+impl Config {
+    fn from_env() -> anyhow::Result<Self> {
+        // HOTI_FPS is the frame rate of the video
+        let frame_rate = env_parse("HOTI_FPS")?.unwrap_or(60);
+        if !video_gen::VideoFrameIter::FRAME_RATES.contains(&frame_rate) {
+            anyhow::bail!(
+                "HOTI_FPS is {frame_rate}, it has to be between {} and {}",
+                video_gen::VideoFrameIter::FRAME_RATES.start(),
+                video_gen::VideoFrameIter::FRAME_RATES.end()
+            );
+        }
+
+        // HOTI_CRF, HOTI_PRESET and HOTI_BITRATE (in kbit/s) tune the quality and size of the
+        // encoded video
+        let encode = video_gen::EncodeConfig {
+            crf: env_parse("HOTI_CRF")?,
+            preset: std::env::var("HOTI_PRESET").ok(),
+            bitrate_kbps: env_parse("HOTI_BITRATE")?,
+        };
+        encode.validate()?;
+
+        // HOTI_RAW_OUT sends raw frames to a file or named pipe for an external encoder instead of
+        // encoding them here. Stdout isn't allowed since the progress logs go there too.
+        let raw_out = std::env::var("HOTI_RAW_OUT").ok();
+        if raw_out.as_deref() == Some("-") {
+            anyhow::bail!("HOTI_RAW_OUT can't be stdout, use a named pipe from mkfifo instead");
+        }
+
+        Ok(Config {
+            // HOTI_FIRST_IDX is where in the index the batch starts
+            first_idx: env_parse("HOTI_FIRST_IDX")?.unwrap_or(102),
+            // HOTI_RETRY_BUDGET is how many retries a single SCP gets before it is skipped
+            retry_budget: env_parse("HOTI_RETRY_BUDGET")?.unwrap_or(30),
+            // HOTI_MIN_ARTICLE_CHARS is how long an article has to be to not be skipped as a stub
+            min_article_chars: env_parse("HOTI_MIN_ARTICLE_CHARS")?
+                .unwrap_or(SCP::MIN_ARTICLE_CHARS),
+            // HOTI_PREFETCH is how many articles are downloaded ahead of the SCP being made
+            prefetch: env_parse("HOTI_PREFETCH")?.unwrap_or(4),
+            // HOTI_REFRESH_GPT asks GPT again instead of reusing the dialogue and image
+            // descriptions it gave for the same prompts before
+            refresh_completions: std::env::var("HOTI_REFRESH_GPT").is_ok(),
+            // HOTI_PAUSE_FILE pauses the batch between videos for as long as that file exists
+            pause_file: std::env::var("HOTI_PAUSE_FILE").ok(),
+            // HOTI_SAFE_MODE makes the video without any paid APIs: the narration is the start of
+            // the article, the audio is silent and the image is the one from the article
+            safe_mode: std::env::var("HOTI_SAFE_MODE").is_ok(),
+            // HOTI_SECTIONS is a comma separated list of the article sections to narrate
+            sections: env_list("HOTI_SECTIONS"),
+            // HOTI_CLASS_WINDOW is how many bytes from the start of the article are searched for
+            // the classes when the article has no classification bar
+            class_window: env_parse("HOTI_CLASS_WINDOW")?,
+            // HOTI_OMIT_MISSING_CLASSES leaves out the "???" tags of classes the article doesn't
+            // have
+            omit_missing_classes: std::env::var("HOTI_OMIT_MISSING_CLASSES").is_ok(),
+            // HOTI_BEATS splits the narration into that many parts, each with its own image
+            beats: env_parse("HOTI_BEATS")?,
+            // HOTI_MAX_SECS keeps the narration under that many seconds, first by asking for a
+            // shorter summary and then by speaking faster
+            max_duration: env_parse("HOTI_MAX_SECS")?.map(std::time::Duration::from_secs_f64),
+            // HOTI_KEEP_INTERMEDIATE keeps the narration MP3 around after the video is made
+            keep_intermediate: std::env::var("HOTI_KEEP_INTERMEDIATE").is_ok(),
+            frame_rate,
+            // HOTI_ASPECT is shorts (the default), landscape or square
+            preset: match std::env::var("HOTI_ASPECT").as_deref() {
+                Ok("shorts") | Err(_) => video_gen::VideoPreset::Shorts,
+                Ok("landscape") => video_gen::VideoPreset::Landscape,
+                Ok("square") => video_gen::VideoPreset::Square,
+                Ok(aspect) => {
+                    anyhow::bail!("Unknown aspect {aspect}, expected shorts, landscape or square")
+                }
+            },
+            // HOTI_FRAME_HASHES writes "<frame> <hash>" for every frame to that file, to compare
+            // runs
+            frame_hashes: std::env::var("HOTI_FRAME_HASHES").ok(),
+            // HOTI_OVERLAY is a comma separated list of "name" and "time" to keep in the corner of
+            // the video
+            overlay: env_list("HOTI_OVERLAY").map(|parts| OverlayConfig {
+                name: parts.iter().any(|part| part == "name"),
+                time: parts.iter().any(|part| part == "time"),
+            }),
+            // HOTI_SUBTITLE_CONTRAST switches the subtitles between black and white to stand out
+            // from the image behind them
+            subtitle_contrast: std::env::var("HOTI_SUBTITLE_CONTRAST").is_ok(),
+            // HOTI_LEGEND_SECS explains the classes in a lower third for that many seconds
+            legend_secs: env_parse("HOTI_LEGEND_SECS")?,
+            // HOTI_SCROLL scrolls the whole article up the screen over the video in place of the
+            // images
+            scroll: std::env::var("HOTI_SCROLL").is_ok(),
+            // HOTI_IMAGE_STYLE is added to every image prompt, like "dark cinematic, desaturated"
+            image_style: std::env::var("HOTI_IMAGE_STYLE").ok(),
+            // HOTI_NO_IMAGES skips image generation, the logo stays up for the whole video
+            no_images: std::env::var("HOTI_NO_IMAGES").is_ok(),
+            // HOTI_IMAGE_COUNT overrides the one image every 5 seconds default
+            image_count: env_parse("HOTI_IMAGE_COUNT")?,
+            // HOTI_IMAGE_CONCURRENCY is how many image requests are sent at once
+            image_concurrency: env_parse("HOTI_IMAGE_CONCURRENCY")?.unwrap_or(1),
+            // HOTI_SRT is an SRT file whose cues are shown as they are instead of the generated
+            // ones
+            srt: match std::env::var("HOTI_SRT") {
+                Ok(path) => Some(
+                    fs::read_to_string(&path)
+                        .with_context(|| format!("Failed to read HOTI_SRT {path}"))?,
+                ),
+                Err(_) => None,
+            },
+            // HOTI_ROLLING_CAPTIONS keeps that many recent captions on screen at once, or as many
+            // as fit in the subtitle box
+            rolling_captions: env_parse("HOTI_ROLLING_CAPTIONS")?,
+            // HOTI_POP_IN is "<amount>,<frames>", each cue grows from 1 - amount of its size over
+            // that many frames
+            pop_in: match std::env::var("HOTI_POP_IN") {
+                Ok(pop_in) => {
+                    let (amount, frames) = pop_in
+                        .split_once(',')
+                        .ok_or_else(|| anyhow::anyhow!("HOTI_POP_IN should look like 0.2,8"))?;
+                    Some((amount.trim().parse()?, frames.trim().parse()?))
+                }
+                Err(_) => None,
+            },
+            // HOTI_POSTER also renders a still poster to use as the thumbnail
+            poster: std::env::var("HOTI_POSTER").is_ok(),
+            // HOTI_BACKDROP puts a dark, blurred copy of the first image behind the title and tags
+            backdrop: std::env::var("HOTI_BACKDROP").is_ok(),
+            encode,
+            // HOTI_CODEC=vp9 writes a WebM instead of an MP4
+            codec: match std::env::var("HOTI_CODEC").as_deref() {
+                Ok("vp9") => video_gen::VideoCodec::Vp9,
+                Ok("h264") | Err(_) => video_gen::VideoCodec::H264,
+                Ok(codec) => anyhow::bail!("Unknown codec {codec}, expected h264 or vp9"),
+            },
+            raw_out,
+            // HOTI_MUSIC is an audio file played under the narration
+            music: std::env::var("HOTI_MUSIC").ok(),
+            // HOTI_DUCKING is "<dB>,<attack secs>,<release secs>", how much quieter the music gets
+            // while the narration speaks and how fast
+            ducking: match env_list("HOTI_DUCKING").as_deref() {
+                Some([duck_db, attack_secs, release_secs]) => video_gen::Ducking {
+                    duck_db: duck_db.parse()?,
+                    attack_secs: attack_secs.parse()?,
+                    release_secs: release_secs.parse()?,
+                },
+                Some(_) => anyhow::bail!("HOTI_DUCKING should look like 12,0.2,0.6"),
+                None => video_gen::Ducking::default(),
+            },
+            // HOTI_PREVIEW encodes a video that many times smaller first and asks before encoding
+            // the full one
+            preview: env_parse("HOTI_PREVIEW")?,
+            // HOTI_LANGUAGES is a comma separated list of language codes, like "hi", to also make
+            // the video in
+            languages: env_list("HOTI_LANGUAGES"),
+        })
+    }
+}
+
+/// The environment variable `name` parsed as a `T`, `None` when it isn't set.
+fn env_parse<T>(name: &str) -> anyhow::Result<Option<T>>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .with_context(|| format!("{name} is {value:?}, which isn't valid")),
+        Err(_) => Ok(None),
+    }
+}
+
+/// The comma separated environment variable `name` split into its trimmed parts.
+fn env_list(name: &str) -> Option<Vec<String>> {
+    std::env::var(name)
+        .ok()
+        .map(|list| list.split(',').map(|part| part.trim().to_owned()).collect())
+}
+
+/// What the narration says, and the title and classes shown alongside it.
+struct Script {
+    title: String,
+    classification: Classification,
+    /// The narration split into parts that each get their own image, see `HOTI_BEATS`
+    beats: Option<Vec<hoti_rs::scp::Beat>>,
+    dialogue: String,
+}
+
+/// What the UI of a video is built from besides its [`Script`].
+struct Scene {
+    font: video_gen::ui::FontHandle,
+    theme: video_gen::ui::Theme,
+    /// Shown in place of the images until the first one is up, and for the whole video without
+    /// them
+    image: video_gen::ui::ImageHandle,
+    /// The whole article, when it scrolls up the screen
+    article: Option<String>,
+}
+
+async fn produce(
+    scp: &mut SCP,
+    config: &Config,
+    openai: &async_openai::Client<OpenAIConfig>,
+    reqwest: &ClientWithMiddleware,
+    client: &mut gcloud::Client,
+    budget: &RetryBudget,
+) -> anyhow::Result<()> {
+    let started_at = std::time::SystemTime::now();
+
+    let overrides = scp.overrides()?;
+
+    if let Some(sections) = &config.sections {
+        scp.narrate_sections(sections.clone());
+    }
+
+    let mut script = write_script(scp, config, &overrides, openai, reqwest).await?;
+    let contents = narrate(
+        scp,
+        &mut script,
+        config,
+        &overrides,
+        openai,
+        reqwest,
+        client,
+        budget,
+    )
+    .await?;
+    let audio = match &contents {
+        Some(contents) => Some(
+            video_gen::TempAudio::write(scp.name(), contents)?.with_keep(config.keep_intermediate),
+        ),
+        None => None,
+    };
+
+    // Without narration the subtitles are paced at roughly speaking speed instead
+    let duration = match contents {
+        Some(contents) => video_gen::Mp3::new(contents).duration(),
+        None => std::time::Duration::from_secs_f64(
+            script.dialogue.split_whitespace().count() as f64 / 2.5 + 5.0,
+        ),
+    };
+
+    let mut video =
+        video_gen::VideoFrameIter::new(config.preset.size(), config.frame_rate, duration)?
+            .with_max_duration(std::time::Duration::from_secs(5 * 60));
+    let chapters = beat_chapters(&script, &video);
+
+    let default_font = video_gen::load_default_font()?;
+    let font = video.ui.add_font("default", default_font.clone());
+    let theme = video_gen::ui::Theme::default();
+
+    let scp_logo = video
+        .ui
+        .add(image::open(hoti_rs::assets::SCP_LOGO).unwrap().to_rgba8());
+    let article_image = if config.safe_mode {
+        match scp.article_image(reqwest.clone()).await {
+            Ok(Some(image)) => Some(video.ui.add(image)),
+            Ok(None) => None,
+            Err(err) => {
+                println!("Failed to fetch the article image, using the logo: {err:?}");
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let scene = Scene {
+        font,
+        theme,
+        image: article_image.unwrap_or(scp_logo),
+        article: match config.scroll {
+            true => Some(scp.article(reqwest.clone()).await?),
+            false => None,
+        },
+    };
+
+    let mut video = lay_out(
+        video,
+        config,
+        scp.name(),
+        &script,
+        &scene,
+        config.frame_hashes.clone(),
+    )?;
+    let img_mgr = make_images(
+        scp, &script, config, &overrides, openai, reqwest, budget, scp_logo, &mut video,
+    )
+    .await?;
+    let sub_mgr = make_subtitles(&script, config, &video)?;
+
+    if let Some(path) = &config.music {
+        let speech = sub_mgr.speech(video.frame_rate(), video.total_frames());
+        video = video.with_music(video_gen::Music {
+            path: path.clone(),
+            ducking: config.ducking,
+            speech,
+        });
+    }
+
+    video.updaters.push(Box::new(sub_mgr));
+    let thumbnail = if config.poster {
+        let image = img_mgr
+            .as_ref()
+            .and_then(|img_mgr| img_mgr.first_image())
+            .or(article_image)
+            .unwrap_or(scp_logo);
+
+        let poster = video_gen::produce_poster(
+            scp.name(),
+            &script.title,
+            &script.classification,
+            video.ui.image(&image).clone(),
+            &video_gen::PosterConfig::new(glam::UVec2 { x: 1280, y: 1280 }, default_font)
+                .with_theme(theme),
+        )?;
+
+        let path = format!("{}-poster.png", scp.name());
+        poster.save(&path)?;
+
+        Some(path)
+    } else {
+        None
+    };
+
+    if config.backdrop {
+        let image = img_mgr
+            .as_ref()
+            .and_then(|img_mgr| img_mgr.first_image())
+            .or(article_image);
+
+        if let Some(image) = image {
+            let backdrop = video_gen::ui::backdrop(video.ui.image(&image), 8.0, 0.35);
+            video.ui.background_image = Some(video.ui.add(backdrop));
+        }
+    }
+
+    // Other languages are made from the UI before any frame is rendered
+    let base_ui = video.ui.clone();
+    let base_images = img_mgr.clone();
+    let total_frames = video.total_frames();
+
+    if let Some(img_mgr) = img_mgr {
+        video.updaters.push(Box::new(img_mgr));
+    }
+
+    let duration_secs = video.total_frames() as f64 / video.frame_rate() as f64;
+    let output = encode(video, config, scp.name(), audio.as_ref(), chapters.clone()).await?;
+
+    hoti_rs::manifest::VideoManifest {
+        name: scp.name().into(),
+        title: script.title.clone(),
+        classification: (&script.classification).into(),
+        output,
+        duration_secs,
+        thumbnail,
+        source_url: scp.url().into(),
+        started_at: hoti_rs::manifest::unix_timestamp(started_at),
+        finished_at: hoti_rs::manifest::unix_timestamp(std::time::SystemTime::now()),
+        chapters,
+    }
+    .write(format!("{}.json", scp.name()))?;
+
+    // Other languages reuse the images and only get their own narration and subtitles
+    let languages = match &config.languages {
+        Some(languages) if !config.safe_mode => languages,
+        _ => return Ok(()),
+    };
+
+    for code in languages {
+        let Some(language) = gcloud::text_to_speech::LanguageSettings::for_code(code) else {
+            println!("Warning: there is no voice for the language {code}, skipping it");
+            continue;
+        };
+
+        let translated = hoti_rs::scp::translate(openai, &script.dialogue, language.name).await?;
+        println!(
+            "Generating {} Audio For Dialogue:\n{translated}",
+            language.name
+        );
+
+        let contents = gcloud::text_to_speech::SynthesisPayload::synthesize_with_voice(
+            client,
+            translated.clone(),
+            language.voice,
+            language.audio,
+            budget,
         )
-        .await;
-        fs::write(&path, contents.clone())?;
+        .await?;
+        let audio = video_gen::TempAudio::write(&format!("{}-{code}", scp.name()), &contents)?
+            .with_keep(config.keep_intermediate);
 
-        // let contents = fs::read(&path).unwrap();
+        let mut video = language_video(
+            config,
+            scp.name(),
+            &script,
+            &scene,
+            &base_ui,
+            code,
+            video_gen::Mp3::new(contents).duration(),
+        )?;
 
-        let mut video = video_gen::VideoFrameIter::new(
-            glam::UVec2 { x: 1080, y: 1920 },
-            60,
-            video_gen::Mp3::new(contents.clone()).duration(),
+        let sub_mgr = video_gen::subtitle::SubtitleManager::new(translated, video.total_frames());
+        if let Some(path) = &config.music {
+            let speech = sub_mgr.speech(video.frame_rate(), video.total_frames());
+            video = video.with_music(video_gen::Music {
+                path: path.clone(),
+                ducking: config.ducking,
+                speech,
+            });
+        }
+        video.updaters.push(Box::new(sub_mgr));
+        if let Some(img_mgr) = &base_images {
+            let img_mgr = img_mgr.clone().retimed(total_frames, video.total_frames());
+            video.updaters.push(Box::new(img_mgr));
+        }
+
+        let output = format!("{}-{code}.{}", scp.name(), config.codec.extension());
+        println!(
+            "Starting to encode the {} video for: {}",
+            language.name,
+            scp.name()
         );
 
-        let font = rusttype::Font::try_from_vec(
-            include_bytes!("/usr/share/fonts/noto/NotoSansMono-ExtraBold.ttf").to_vec(),
+        video
+            .encode(
+                config.codec,
+                Some(audio.path().to_str().unwrap()),
+                &output,
+                &config.encode,
+                Some(encode_progress()),
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Gets the title and classes of `scp` and writes the narration, from the overrides where they
+/// have one.
+async fn write_script(
+    scp: &mut SCP,
+    config: &Config,
+    overrides: &Overrides,
+    openai: &async_openai::Client<OpenAIConfig>,
+    reqwest: &ClientWithMiddleware,
+) -> anyhow::Result<Script> {
+    let title = match overrides.title.clone() {
+        Some(title) => title,
+        None => match scp.title(reqwest.clone()).await {
+            Ok(Some(title)) => title,
+            Ok(None) => "Unknown".into(),
+            Err(err) => {
+                println!("Warning: couldn't get the title of {}: {err:?}", scp.name());
+                "Unknown".into()
+            }
+        },
+    };
+    let classification = match config.class_window {
+        Some(window) => {
+            scp.classification_with_window(reqwest.clone(), window)
+                .await?
+        }
+        None => scp.classification(reqwest.clone()).await?,
+    }
+    .with_overrides(&overrides.classification)?
+    .with_omit_missing(config.omit_missing_classes);
+
+    println!("Title: {title}");
+    println!("Classification: {classification:?}");
+
+    let beats = match config.beats {
+        Some(count) if overrides.dialogue.is_none() && !config.safe_mode => {
+            Some(scp.dialogue_beats(openai, reqwest.clone(), count).await?)
+        }
+        _ => None,
+    };
+
+    let dialogue = match (&beats, overrides.dialogue.clone()) {
+        (Some(beats), _) => beats
+            .iter()
+            .map(|beat| beat.narration.as_str())
+            .collect::<Vec<_>>()
+            .join(" "),
+        (None, Some(dialogue)) => dialogue,
+        (None, None) if config.safe_mode => scp.summary_from_article(reqwest.clone(), 5).await?,
+        (None, None) => scp.dialogue(openai, reqwest.clone()).await?,
+    };
+
+    Ok(Script {
+        title,
+        classification,
+        beats,
+        dialogue,
+    })
+}
+
+/// Speaks the dialogue of `script`, shortening it or speaking faster until it fits in
+/// `HOTI_MAX_SECS`. Returns the MP3, `None` in safe mode.
+#[allow(clippy::too_many_arguments)]
+async fn narrate(
+    scp: &mut SCP,
+    script: &mut Script,
+    config: &Config,
+    overrides: &Overrides,
+    openai: &async_openai::Client<OpenAIConfig>,
+    reqwest: &ClientWithMiddleware,
+    client: &mut gcloud::Client,
+    budget: &RetryBudget,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    if config.safe_mode {
+        return Ok(None);
+    }
+
+    let language_code = overrides
+        .voice
+        .as_ref()
+        .map(|voice| voice.splitn(3, '-').take(2).collect::<Vec<_>>().join("-"));
+    let voice = match (&overrides.voice, &language_code) {
+        (Some(name), Some(language_code)) => VoiceSelectionParams {
+            language_code,
+            name,
+            ssml_gender: SsmlVoiceGender::SsmlVoiceGenderUnspecified,
+            custom_voice: None,
+        },
+        _ => EnString::VOICE,
+    };
+
+    let mut audio_config = EnString::AUDIO;
+    let mut shortenings = 0;
+
+    loop {
+        println!("Generating Audio For Dialogue:\n{}", script.dialogue);
+
+        let contents = gcloud::text_to_speech::SynthesisPayload::synthesize_with_voice(
+            client,
+            script.dialogue.clone(),
+            voice.clone(),
+            audio_config.clone(),
+            budget,
         )
-        .unwrap();
+        .await?;
 
-        let scp_logo = video
-            .ui
-            .add(image::open("assets/SCP.png").unwrap().to_rgba8());
-        video.ui.children = vec![
-            video_gen::ui::StyledNode {
-                node: video_gen::ui::Node::Container(vec![
-                    video_gen::ui::StyledNode {
-                        node: video_gen::ui::Node::TextCentered {
-                            text: scp.name().into(),
-                            font: font.clone(),
-                            scale: rusttype::Scale { x: 120.0, y: 120.0 },
-                            line_height: 120,
-                            color: [255, 255, 255, 255].into(),
-                        },
-                        style: Style {
-                            size: Size {
-                                width: Dimension::Auto,
-                                height: Dimension::Points(120.0),
-                            },
-                            ..Default::default()
-                        },
+        let duration = video_gen::Mp3::new(contents.clone()).duration();
+        let Some(max_duration) = config
+            .max_duration
+            .filter(|max_duration| duration > *max_duration)
+        else {
+            return Ok(Some(contents));
+        };
+        let ratio = duration.as_secs_f64() / max_duration.as_secs_f64();
+
+        // Beats and overridden dialogue can't be rewritten, so those are only sped up
+        if script.beats.is_none() && overrides.dialogue.is_none() && shortenings < 2 {
+            shortenings += 1;
+
+            let max_words =
+                (script.dialogue.split_whitespace().count() as f64 / ratio * 0.9) as usize;
+            println!("Narration is {duration:?} long, shortening it to {max_words} words");
+
+            script.dialogue = scp
+                .dialogue_with_max_words(openai, reqwest.clone(), max_words)
+                .await?;
+        } else if audio_config.speaking_rate
+            < gcloud::text_to_speech::AudioConfig::MAX_SPEAKING_RATE
+        {
+            let speaking_rate = audio_config.speaking_rate * ratio;
+            audio_config = audio_config.with_speaking_rate(speaking_rate);
+            println!(
+                "Narration is {duration:?} long, speaking at {} instead",
+                audio_config.speaking_rate
+            );
+        } else {
+            println!("Narration is still {duration:?} long, it can't be sped up any more");
+            return Ok(Some(contents));
+        }
+    }
+}
+
+/// A chapter per beat, titled with the first few words of its narration.
+fn beat_chapters(
+    script: &Script,
+    video: &video_gen::VideoFrameIter,
+) -> Vec<hoti_rs::manifest::Chapter> {
+    let Some(beats) = &script.beats else {
+        return Vec::default();
+    };
+
+    let narrations: Vec<_> = beats.iter().map(|beat| beat.narration.clone()).collect();
+    let start_frames = video_gen::subtitle::beat_start_frames(&narrations, video.total_frames());
+
+    narrations
+        .iter()
+        .zip(start_frames)
+        .map(|(narration, start_frame)| hoti_rs::manifest::Chapter {
+            start_secs: start_frame as f64 / video.frame_rate() as f64,
+            title: narration
+                .split_whitespace()
+                .take(5)
+                .collect::<Vec<_>>()
+                .join(" "),
+        })
+        .collect()
+}
+
+/// Puts the title, image, tags and subtitle box of `name` on `video`, along with the overlay,
+/// legend and scrolling article when they are turned on. The hash of every frame is written to
+/// `frame_hashes`.
+fn lay_out(
+    mut video: video_gen::VideoFrameIter,
+    config: &Config,
+    name: &str,
+    script: &Script,
+    scene: &Scene,
+    frame_hashes: Option<String>,
+) -> anyhow::Result<video_gen::VideoFrameIter> {
+    let Scene {
+        font, theme, image, ..
+    } = *scene;
+    let layout = config.preset.layout();
+
+    if let Some(path) = frame_hashes {
+        let mut out = std::io::BufWriter::new(fs::File::create(&path)?);
+        video = video.with_frame_hashes(move |idx, hash| {
+            if let Err(err) = writeln!(out, "{idx} {hash:016x}") {
+                println!("Warning: couldn't write the hash of frame {idx} to {path}: {err}");
+            }
+        });
+    }
+
+    if let Some(overlay) = &config.overlay {
+        video = video.with_info_overlay(font, overlay.name.then(|| name.to_owned()), overlay.time);
+    }
+
+    video.ui.children = vec![
+        video_gen::ui::StyledNode {
+            node: video_gen::ui::Node::Container(vec![
+                video_gen::ui::StyledNode {
+                    node: video_gen::ui::Node::Text {
+                        text: name.into(),
+                        font,
+                        scale: rusttype::Scale { x: 120.0, y: 120.0 },
+                        line_height: 120,
+                        color: theme.text,
+                        word_break: video_gen::ui::WordBreak::Never,
+                        align: video_gen::ui::TextAlign::Center,
+                        outline: None,
+                        auto_contrast: false,
                     },
-                    video_gen::ui::StyledNode {
-                        node: video_gen::ui::Node::TextCentered {
-                            text: title.to_ascii_uppercase(),
-                            font: font.clone(),
-                            scale: rusttype::Scale { x: 120.0, y: 120.0 },
-                            line_height: 120,
-                            color: [255, 255, 255, 255].into(),
-                        },
-                        style: Style {
-                            size: Size {
-                                width: Dimension::Auto,
-                                height: Dimension::Points(120.0),
-                            },
-                            ..Default::default()
+                    style: Style {
+                        size: Size {
+                            width: Dimension::Auto,
+                            height: Dimension::Points(120.0),
                         },
+                        ..Default::default()
                     },
-                ]),
-                style: Style {
-                    flex_direction: taffy::style::FlexDirection::Column,
-                    size: Size {
-                        width: Dimension::Auto,
-                        height: Dimension::Auto,
+                    opacity: 1.0,
+                    z_index: None,
+                },
+                video_gen::ui::StyledNode {
+                    node: video_gen::ui::Node::Text {
+                        text: script.title.to_ascii_uppercase(),
+                        font,
+                        scale: rusttype::Scale { x: 120.0, y: 120.0 },
+                        line_height: 120,
+                        color: theme.text,
+                        word_break: video_gen::ui::WordBreak::Never,
+                        align: video_gen::ui::TextAlign::Center,
+                        outline: None,
+                        auto_contrast: false,
                     },
-                    margin: Rect {
-                        left: LengthPercentageAuto::Points(0.0),
-                        right: LengthPercentageAuto::Points(0.0),
-                        top: LengthPercentageAuto::Points(100.0),
-                        bottom: LengthPercentageAuto::Points(100.0),
+                    style: Style {
+                        size: Size {
+                            width: Dimension::Auto,
+                            height: Dimension::Points(120.0),
+                        },
+                        ..Default::default()
                     },
-                    ..Default::default()
+                    opacity: 1.0,
+                    z_index: None,
+                },
+            ]),
+            style: Style {
+                flex_direction: taffy::style::FlexDirection::Column,
+                size: Size {
+                    width: Dimension::Auto,
+                    height: Dimension::Auto,
                 },
+                margin: Rect {
+                    left: LengthPercentageAuto::Points(0.0),
+                    right: LengthPercentageAuto::Points(0.0),
+                    top: LengthPercentageAuto::Points(layout.title_margin),
+                    bottom: LengthPercentageAuto::Points(layout.title_margin),
+                },
+                ..Default::default()
             },
-            video_gen::ui::StyledNode {
-                node: video_gen::ui::Node::Image(scp_logo),
-                style: Style {
-                    size: Size {
-                        width: Dimension::Points(800.0),
-                        height: Dimension::Points(800.0),
-                    },
-                    margin: Rect {
-                        left: LengthPercentageAuto::Auto,
-                        right: LengthPercentageAuto::Auto,
-                        top: LengthPercentageAuto::Points(0.0),
-                        bottom: LengthPercentageAuto::Auto,
-                    },
-                    ..Default::default()
+            opacity: 1.0,
+            z_index: None,
+        },
+        video_gen::ui::StyledNode {
+            node: video_gen::ui::Node::Image {
+                image,
+                fit: video_gen::ui::ImageFit::Fill,
+                shadow: None,
+            },
+            style: Style {
+                size: Size {
+                    width: Dimension::Points(layout.image_size),
+                    height: Dimension::Points(layout.image_size),
                 },
+                margin: Rect {
+                    left: LengthPercentageAuto::Auto,
+                    right: LengthPercentageAuto::Auto,
+                    top: LengthPercentageAuto::Points(0.0),
+                    bottom: LengthPercentageAuto::Auto,
+                },
+                ..Default::default()
+            },
+            opacity: 1.0,
+            z_index: None,
+        },
+        script.classification.ui(font, &theme, &mut video.ui),
+        video_gen::ui::StyledNode {
+            node: video_gen::ui::Node::Text {
+                text: String::default(),
+                font,
+                scale: rusttype::Scale { x: 60.0, y: 60.0 },
+                line_height: 80,
+                color: theme.subtitle,
+                word_break: video_gen::ui::WordBreak::Hyphenate,
+                align: video_gen::ui::TextAlign::Center,
+                outline: Some(([0, 0, 0, 255].into(), 3)),
+                auto_contrast: config.subtitle_contrast,
             },
-            classification.ui(font.clone(), &mut video.ui),
-            video_gen::ui::StyledNode {
-                node: video_gen::ui::Node::TextCentered {
-                    text: String::default(),
-                    font: font,
-                    scale: rusttype::Scale { x: 60.0, y: 60.0 },
-                    line_height: 80,
-                    color: [255, 255, 255, 255].into(),
+            style: Style {
+                size: Size {
+                    width: Dimension::Auto,
+                    height: Dimension::Points(layout.subtitle_height),
                 },
-                style: Style {
-                    size: Size {
-                        width: Dimension::Auto,
-                        height: Dimension::Points(420.0),
-                    },
-                    margin: Rect {
-                        left: LengthPercentageAuto::Points(100.0),
-                        right: LengthPercentageAuto::Points(100.0),
-                        top: LengthPercentageAuto::Points(0.0),
-                        bottom: LengthPercentageAuto::Points(0.0),
-                    },
-                    ..Default::default()
+                margin: Rect {
+                    left: LengthPercentageAuto::Points(layout.side_margin),
+                    right: LengthPercentageAuto::Points(layout.side_margin),
+                    top: LengthPercentageAuto::Points(0.0),
+                    bottom: LengthPercentageAuto::Points(0.0),
                 },
+                ..Default::default()
             },
-        ];
-        video.ui.background_color = [24, 24, 24, 255].into();
+            opacity: 1.0,
+            // Drawn over the image and tags wherever the layout puts it
+            z_index: Some(10),
+        },
+    ];
+    video.ui.background_color = theme.background;
 
-        let sub_mgr = video_gen::subtitle::SubtitleManager::new(dialogue, video.total_frames());
+    if let Some(secs) = config.legend_secs {
+        let frames = 0..(secs * video.frame_rate()).min(video.total_frames());
+
+        video
+            .ui
+            .children
+            .push(Classification::legend_ui(font, &theme));
+        video.updaters.push(Box::new(ClassLegend::new(
+            video.ui.children.len() - 1,
+            &script.classification,
+            &[
+                ClassCategory::Primary,
+                ClassCategory::Containment,
+                ClassCategory::Secondary,
+                ClassCategory::Disruption,
+                ClassCategory::Risk,
+            ],
+            frames,
+        )));
+    }
 
-        println!("Fetching images for the video for: {}", scp.name());
+    if let Some(article) = scene.article.clone() {
+        let width = video.size().x as f32 - 2.0 * layout.side_margin;
 
-        let mut attempt = 0;
-        let img_mgr = loop {
-            if attempt > 10 {
-                panic!("Failed to fetch images.")
+        video.ui.children[1].opacity = 0.0;
+        video.ui.children.push(video_gen::ui::StyledNode {
+            node: video_gen::ui::Node::Text {
+                text: article,
+                font,
+                scale: rusttype::Scale { x: 44.0, y: 44.0 },
+                line_height: 56,
+                color: theme.text,
+                word_break: video_gen::ui::WordBreak::Hyphenate,
+                align: video_gen::ui::TextAlign::Left,
+                outline: Some(([0, 0, 0, 255].into(), 2)),
+                auto_contrast: false,
+            },
+            style: Style {
+                position: Position::Absolute,
+                size: Size {
+                    width: Dimension::Points(width),
+                    height: Dimension::Auto,
+                },
+                inset: Rect {
+                    left: LengthPercentageAuto::Points(layout.side_margin),
+                    right: LengthPercentageAuto::Auto,
+                    top: LengthPercentageAuto::Points(video.size().y as f32),
+                    bottom: LengthPercentageAuto::Auto,
+                },
+                ..Default::default()
+            },
+            opacity: 1.0,
+            z_index: Some(5),
+        });
+
+        let scroll = video_gen::scroll::ScrollingText::new(
+            &video.ui,
+            video.ui.children.len() - 1,
+            width,
+            video.size().y as f32,
+            video.total_frames(),
+        )?;
+        video.updaters.push(Box::new(scroll));
+    }
+
+    for overflow in video.ui.check_overflow(video.size())? {
+        println!("Warning: {overflow}");
+    }
+
+    Ok(video)
+}
+
+/// The `code` language version of a video that is `duration` long, laid out like the original
+/// with the images already added to `base_ui`. Its frame hashes go next to the original's.
+#[allow(clippy::too_many_arguments)]
+fn language_video(
+    config: &Config,
+    name: &str,
+    script: &Script,
+    scene: &Scene,
+    base_ui: &video_gen::ui::VideoUI,
+    code: &str,
+    duration: std::time::Duration,
+) -> anyhow::Result<video_gen::VideoFrameIter> {
+    let mut video =
+        video_gen::VideoFrameIter::new(config.preset.size(), config.frame_rate, duration)?
+            .with_max_duration(std::time::Duration::from_secs(5 * 60));
+    video.ui = base_ui.clone();
+
+    let frame_hashes = config.frame_hashes.as_deref().map(|path| {
+        let path = std::path::Path::new(path);
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let file_name = match path.extension() {
+            Some(extension) => format!("{stem}-{code}.{}", extension.to_string_lossy()),
+            None => format!("{stem}-{code}"),
+        };
+
+        path.with_file_name(file_name)
+            .to_string_lossy()
+            .into_owned()
+    });
+
+    lay_out(video, config, name, script, scene, frame_hashes)
+}
+
+/// Generates the images shown over the narration, `None` when the video has none.
+#[allow(clippy::too_many_arguments)]
+async fn make_images(
+    scp: &mut SCP,
+    script: &Script,
+    config: &Config,
+    overrides: &Overrides,
+    openai: &async_openai::Client<OpenAIConfig>,
+    reqwest: &ClientWithMiddleware,
+    budget: &RetryBudget,
+    fallback: video_gen::ui::ImageHandle,
+    video: &mut video_gen::VideoFrameIter,
+) -> anyhow::Result<Option<video_gen::image_manager::ImageManager>> {
+    if config.no_images || config.safe_mode {
+        return Ok(None);
+    }
+
+    println!("Fetching images for the video for: {}", scp.name());
+
+    let schedule =
+        video_gen::image_manager::ImageSchedule::new(video.frame_rate(), video.duration())
+            .with_style(config.image_style.clone())
+            .with_canvas(video.size())
+            .with_client(reqwest.clone());
+
+    if let Some(beats) = &script.beats {
+        println!("Beats: {beats:#?}");
+
+        let (narrations, image_descriptions): (Vec<_>, Vec<_>) = beats
+            .iter()
+            .map(|beat| (beat.narration.clone(), beat.image_description.clone()))
+            .unzip();
+
+        let start_frames =
+            video_gen::subtitle::beat_start_frames(&narrations, video.total_frames());
+
+        let img_mgr = video_gen::image_manager::ImageManager::from_beats(
+            image_descriptions,
+            &start_frames,
+            openai,
+            &schedule,
+            fallback,
+            &mut video.ui,
+            budget,
+        )
+        .await?;
+
+        return Ok(Some(img_mgr));
+    }
+
+    let mut image_description = match overrides.image_description.clone() {
+        Some(image_description) => image_description,
+        None => scp.image_description(openai, reqwest.clone()).await?,
+    };
+    println!("Image Description: {:#?}", image_description);
+
+    let mut attempt = 0;
+    loop {
+        if attempt > 10 {
+            panic!("Failed to fetch images.")
+        }
+
+        attempt += 1;
+
+        match video_gen::image_manager::ImageManager::new(
+            image_description,
+            openai,
+            schedule
+                .clone()
+                .with_count(config.image_count)
+                .with_concurrency(config.image_concurrency),
+            fallback,
+            &mut video.ui,
+            budget,
+        )
+        .await
+        {
+            Ok(img_mgr) => return Ok(Some(img_mgr)),
+            Err(err) => {
+                println!("\nError Generating Images: {err:?}");
+                budget.spend()?;
+                // The cached description is the one that just failed
+                scp.set_refresh_completions(true);
+                image_description = scp.image_description(openai, reqwest.clone()).await?;
+                println!("Trying to use new description: {}", image_description);
+            }
+        }
+    }
+}
+
+/// The subtitles for the narration, from `HOTI_SRT` when it is set.
+fn make_subtitles(
+    script: &Script,
+    config: &Config,
+    video: &video_gen::VideoFrameIter,
+) -> anyhow::Result<video_gen::subtitle::SubtitleManager> {
+    let layout = config.preset.layout();
+
+    let sub_mgr = match (&config.srt, &script.beats) {
+        (Some(srt), _) => video_gen::subtitle::SubtitleManager::from_srt(srt, video.frame_rate())?,
+        // Each beat's subtitles line up with its image
+        (None, Some(beats)) if !config.no_images => {
+            let narrations: Vec<_> = beats.iter().map(|beat| beat.narration.clone()).collect();
+            video_gen::subtitle::SubtitleManager::from_beats(&narrations, video.total_frames())
+        }
+        (None, _) => {
+            video_gen::subtitle::SubtitleManager::new(script.dialogue.clone(), video.total_frames())
+        }
+    };
+
+    let sub_mgr = match config.rolling_captions {
+        Some(count) => {
+            let fitting = sub_mgr.rolling_cues_that_fit(
+                count,
+                &video.ui,
+                &video.ui.children[3],
+                video.size().x as f32 - 2.0 * layout.side_margin,
+                layout.subtitle_height,
+            );
+            if fitting < count {
+                println!("Warning: only {fitting} captions fit on screen at once, not {count}");
             }
 
-            attempt += 1;
+            sub_mgr.with_rolling_cues(fitting)
+        }
+        None => sub_mgr,
+    };
+
+    Ok(match config.pop_in {
+        Some((amount, frames)) => sub_mgr.with_pop_in(amount, frames),
+        None => sub_mgr,
+    })
+}
+
+/// Encodes `video` with the narration in `audio`, or writes its raw frames to `HOTI_RAW_OUT`.
+/// Returns where the video went, `None` for raw frames and previews that weren't approved.
+async fn encode(
+    video: video_gen::VideoFrameIter,
+    config: &Config,
+    name: &str,
+    audio: Option<&video_gen::TempAudio>,
+    chapters: Vec<hoti_rs::manifest::Chapter>,
+) -> anyhow::Result<Option<String>> {
+    if let Some(raw_out) = &config.raw_out {
+        println!("Writing raw frames for {name} to {raw_out}");
+        video.write_raw(std::io::BufWriter::new(fs::File::create(raw_out)?))?;
+
+        return Ok(None);
+    }
+
+    println!("Starting to encode the video for: {name}");
 
-            match video_gen::image_manager::ImageManager::new(
-                image_description,
-                &openai,
-                video.frame_rate(),
-                video.duration(),
-                &mut video.ui,
+    let video_out = format!("{name}.{}", config.codec.extension());
+    let video = video.with_chapters(chapters);
+    let audio_in = audio.map(|audio| audio.path().to_str().unwrap());
+
+    match config.preview {
+        Some(divisor) => Ok(video
+            .encode_with_preview(
+                divisor,
+                approve_preview,
+                config.codec,
+                audio_in,
+                &video_out,
+                &config.encode,
             )
-            .await
-            {
-                Ok(img_mgr) => break img_mgr,
-                Err(err) => {
-                    println!("\nError Generating Images: {err:?}");
-                    image_description = scp.image_description(&openai, reqwest.clone()).await?;
-                    println!("Trying to use new description: {}", image_description);
+            .await?
+            .then_some(video_out)),
+        None => {
+            video
+                .encode(
+                    config.codec,
+                    audio_in,
+                    &video_out,
+                    &config.encode,
+                    Some(encode_progress()),
+                )
+                .await?;
+
+            Ok(Some(video_out))
+        }
+    }
+}
+
+/// Prints how far along encoding is every 5%.
+fn encode_progress() -> Box<dyn Fn(u32, u32) + Send> {
+    Box::new(|frame, total_frames| {
+        let step = (total_frames / 20).max(1);
+        if frame % step == 0 || frame == total_frames {
+            println!(
+                "Encoded {frame}/{total_frames} frames ({}%)",
+                frame as u64 * 100 / total_frames.max(1) as u64
+            );
+        }
+    })
+}
+
+/// Asks on the terminal whether the full video should be made from the preview at `path`.
+fn approve_preview(path: &str) -> bool {
+    println!("Encode the full video after checking {path}? [y/N]");
+
+    let mut answer = String::default();
+    std::io::stdin().read_line(&mut answer).is_ok() && answer.trim().eq_ignore_ascii_case("y")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_script() -> Script {
+        Script {
+            title: "The Sculpture".into(),
+            classification: Classification {
+                primary: None,
+                containment: None,
+                secondary: None,
+                disruption: None,
+                risk: None,
+                omit_missing: true,
+            },
+            beats: None,
+            dialogue: "SCP-173 moves when it isn't seen.".into(),
+        }
+    }
+
+    fn test_video(config: &Config) -> video_gen::VideoFrameIter {
+        video_gen::VideoFrameIter::new(
+            config.preset.size(),
+            config.frame_rate,
+            std::time::Duration::from_secs(10),
+        )
+        .unwrap()
+    }
+
+    fn test_scene(video: &mut video_gen::VideoFrameIter, theme: video_gen::ui::Theme) -> Scene {
+        let font = video.ui.add_font(
+            "default",
+            rusttype::Font::try_from_bytes(include_bytes!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/assets/SpecialElite-Regular.ttf"
+            )))
+            .unwrap(),
+        );
+        let image = video
+            .ui
+            .add(image::RgbaImage::from_pixel(8, 8, [200, 0, 0, 255].into()));
+
+        Scene {
+            font,
+            theme,
+            image,
+            article: None,
+        }
+    }
+
+    #[test]
+    fn language_versions_are_laid_out_like_the_original() {
+        let dir = std::env::temp_dir().join("hoti-rs-language-layout");
+        fs::create_dir_all(&dir).unwrap();
+        let hashes = dir.join("hashes.txt");
+
+        let mut config = Config::from_env().unwrap();
+        config.frame_rate = 30;
+        config.overlay = Some(OverlayConfig {
+            name: true,
+            time: true,
+        });
+        config.frame_hashes = Some(hashes.to_string_lossy().into_owned());
+
+        let script = test_script();
+        let mut video = test_video(&config);
+        let scene = test_scene(&mut video, video_gen::ui::Theme::default());
+
+        let original = lay_out(
+            video,
+            &config,
+            "SCP-173",
+            &script,
+            &scene,
+            config.frame_hashes.clone(),
+        )
+        .unwrap();
+        let base_ui = original.ui.clone();
+        // A translation is rarely as long as the original
+        let translated = language_video(
+            &config,
+            "SCP-173",
+            &script,
+            &scene,
+            &base_ui,
+            "hi",
+            std::time::Duration::from_secs(14),
+        )
+        .unwrap();
+
+        assert_eq!(translated.ui.children.len(), original.ui.children.len());
+        let [(_, original_frame), (_, translated_frame)] =
+            [original, translated].map(|mut video| video.next().unwrap());
+        assert!(original_frame == translated_frame);
+
+        // Both write their hashes, and the first frames hash the same
+        let first_hash = |path| {
+            fs::read_to_string(path)
+                .unwrap()
+                .lines()
+                .next()
+                .unwrap()
+                .to_owned()
+        };
+        assert_eq!(first_hash(&hashes), first_hash(&dir.join("hashes-hi.txt")));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn the_theme_colors_the_whole_video() {
+        /// The color of every text node under `node`.
+        fn text_colors(node: &video_gen::ui::StyledNode) -> Vec<image::Rgba<u8>> {
+            match &node.node {
+                video_gen::ui::Node::Text { color, .. } => vec![*color],
+                video_gen::ui::Node::Container(children) => {
+                    children.iter().flat_map(text_colors).collect()
                 }
+                _ => Vec::default(),
             }
+        }
+
+        let mut config = Config::from_env().unwrap();
+        config.frame_rate = 30;
+        config.overlay = None;
+        config.legend_secs = None;
+        let night = video_gen::ui::Theme {
+            background: [0, 0, 40, 255].into(),
+            text: [250, 220, 120, 255].into(),
+            accent: [120, 200, 250, 255].into(),
+            subtitle: [250, 250, 250, 255].into(),
         };
 
-        video.updaters.push(Box::new(sub_mgr));
-        video.updaters.push(Box::new(img_mgr));
+        for theme in [video_gen::ui::Theme::default(), night] {
+            let mut video = test_video(&config);
+            let scene = test_scene(&mut video, theme);
+            let video = lay_out(video, &config, "SCP-173", &test_script(), &scene, None).unwrap();
 
-        println!("Starting to encode the video for: {}", scp.name());
+            assert_eq!(video.ui.background_color, theme.background);
+            assert_eq!(text_colors(&video.ui.children[0]), [theme.text; 2]);
+            assert_eq!(text_colors(&video.ui.children[3]), [theme.subtitle]);
+        }
+    }
 
-        video
-            .encode_h264(
-                path.to_str().unwrap(),
-                format!("{}.mp4", scp.name()).as_str(),
-            )
+    #[tokio::test]
+    async fn safe_mode_makes_no_paid_calls() {
+        use wiremock::{
+            matchers::{method, path},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let server = MockServer::start().await;
+        let mut png = std::io::Cursor::new(Vec::default());
+        image::RgbaImage::from_pixel(2, 2, [0, 200, 0, 255].into())
+            .write_to(&mut png, image::ImageOutputFormat::Png)
+            .unwrap();
+        // The first five strings of #page-content are the rating module and are skipped
+        Mock::given(method("GET"))
+            .and(path("/scp-safe-mode-test"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "<html><body><div id=\"page-content\">\
+                 <p>1</p><p>2</p><p>3</p><p>4</p><p>5</p>\
+                 <img src=\"/scp-safe-mode-test.png\">\
+                 <p>Item #: SCP-SAFE-MODE-TEST</p><p>Object Class: Safe</p>\
+                 <p>Description: A small statue. It does not move.</p></div>\
+                 <div class=\"licensebox\">Licensed</div></body></html>",
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/scp-safe-mode-test.png"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(png.into_inner()))
+            .mount(&server)
+            .await;
+        // Every OpenAI and text-to-speech request is a POST
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(0)
+            .mount(&server)
             .await;
 
-        println!(
-            "Made video for {} and it took {:?}",
-            scp.name(),
-            start.elapsed()
+        let openai = async_openai::Client::with_config(
+            OpenAIConfig::new()
+                .with_api_key("test")
+                .with_api_base(server.uri()),
         );
-        println!("----------------------------------------------------------\n");
-    }
+        let mut client =
+            gcloud::Client::from_client(reqwest::Client::new()).with_base_url(server.uri());
+        let pages = ClientBuilder::new(reqwest::Client::new()).build();
+        let budget = RetryBudget::new(0);
 
-    Ok(())
+        let mut config = Config::from_env().unwrap();
+        config.safe_mode = true;
+        let overrides = Overrides {
+            title: Some("The Safe One".into()),
+            ..Default::default()
+        };
+
+        let mut scp = SCP::new(
+            "SCP-SAFE-MODE-TEST",
+            hoti_rs::scp::SCPSeries::Series1,
+            format!("{}/scp-safe-mode-test", server.uri()),
+        );
+        scp.set_min_article_chars(0);
+
+        let mut script = write_script(&mut scp, &config, &overrides, &openai, &pages)
+            .await
+            .unwrap();
+        assert!(script.dialogue.contains("A small statue."));
+
+        let audio = narrate(
+            &mut scp,
+            &mut script,
+            &config,
+            &overrides,
+            &openai,
+            &pages,
+            &mut client,
+            &budget,
+        )
+        .await
+        .unwrap();
+        assert!(audio.is_none());
+
+        let mut video = video_gen::VideoFrameIter::new(
+            glam::UVec2::new(4, 4),
+            config.frame_rate,
+            std::time::Duration::from_secs(10),
+        )
+        .unwrap();
+        let fallback = video.ui.add(image::RgbaImage::new(1, 1));
+        let images = make_images(
+            &mut scp, &script, &config, &overrides, &openai, &pages, &budget, fallback, &mut video,
+        )
+        .await
+        .unwrap();
+        assert!(images.is_none());
+
+        let image = scp.article_image(pages).await.unwrap().unwrap();
+        assert_eq!(image.dimensions(), (2, 2));
+    }
 }
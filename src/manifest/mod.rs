@@ -0,0 +1,164 @@
+use std::{
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::scp::Classification;
+
+/// Everything other tools (like the uploader) need to know about a produced video. Written next to
+/// the video as `<name>.json`.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct VideoManifest {
+    pub name: String,
+    pub title: String,
+    pub classification: ManifestClassification,
+    /// The encoded video, `None` when raw frames were written for an external encoder instead.
+    pub output: Option<String>,
+    pub duration_secs: f64,
+    pub thumbnail: Option<String>,
+    pub source_url: String,
+    /// Seconds since the unix epoch.
+    pub started_at: u64,
+    pub finished_at: u64,
+    #[serde(default)]
+    pub chapters: Vec<Chapter>,
+}
+
+/// A part of the video that starts `start_secs` into it.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Chapter {
+    pub start_secs: f64,
+    pub title: String,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct ManifestClassification {
+    #[serde(default)]
+    pub primary: Option<String>,
+    pub containment: Option<String>,
+    pub secondary: Option<String>,
+    pub disruption: Option<String>,
+    pub risk: Option<String>,
+}
+
+impl From<&Classification> for ManifestClassification {
+    fn from(classification: &Classification) -> Self {
+        ManifestClassification {
+            primary: classification.primary.as_ref().map(|c| c.to_string()),
+            containment: classification.containment.as_ref().map(|c| c.to_string()),
+            secondary: classification.secondary.as_ref().map(|c| c.to_string()),
+            disruption: classification.disruption.as_ref().map(|c| c.to_string()),
+            risk: classification.risk.as_ref().map(|c| c.to_string()),
+        }
+    }
+}
+
+impl VideoManifest {
+    pub fn read(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// The chapters as `M:SS Title` lines for the video description. YouTube only turns them into
+    /// chapters when there are at least 3 and the first starts at 0:00, so it is empty otherwise.
+    pub fn description_timestamps(&self) -> String {
+        if self.chapters.len() < 3 {
+            return String::default();
+        }
+
+        self.chapters
+            .iter()
+            .enumerate()
+            .map(|(idx, chapter)| {
+                let secs = if idx == 0 {
+                    0
+                } else {
+                    chapter.start_secs as u64
+                };
+
+                format!("{}:{:02} {}", secs / 60, secs % 60, chapter.title)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+pub fn unix_timestamp(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_manifest(chapters: Vec<Chapter>) -> VideoManifest {
+        VideoManifest {
+            name: "SCP-173".into(),
+            title: "The Sculpture".into(),
+            classification: ManifestClassification {
+                primary: Some("EUCLID".into()),
+                containment: Some("EUCLID".into()),
+                secondary: None,
+                disruption: None,
+                risk: None,
+            },
+            output: None,
+            duration_secs: 59.5,
+            thumbnail: Some("SCP-173.png".into()),
+            source_url: "https://scp-wiki.wikidot.com/scp-173".into(),
+            started_at: 1,
+            finished_at: 2,
+            chapters,
+        }
+    }
+
+    #[test]
+    fn manifest_has_every_field() {
+        let manifest = test_manifest(Vec::default());
+
+        let json = serde_json::to_value(&manifest).unwrap();
+        for field in [
+            "name",
+            "title",
+            "classification",
+            "output",
+            "duration_secs",
+            "thumbnail",
+            "source_url",
+            "started_at",
+            "finished_at",
+            "chapters",
+        ] {
+            assert!(json.get(field).is_some(), "{field} is missing");
+        }
+        assert_eq!(json["duration_secs"], 59.5);
+    }
+
+    #[test]
+    fn chapters_are_listed_as_timestamps() {
+        let chapter = |start_secs, title: &str| Chapter {
+            start_secs,
+            title: title.into(),
+        };
+
+        let manifest = test_manifest(vec![
+            chapter(0.4, "Intro"),
+            chapter(12.6, "Containment"),
+            chapter(75.0, "Description"),
+        ]);
+        assert_eq!(
+            manifest.description_timestamps(),
+            "0:00 Intro\n0:12 Containment\n1:15 Description"
+        );
+
+        // Too few to be turned into chapters
+        let manifest = test_manifest(vec![chapter(0.0, "Intro"), chapter(30.0, "Description")]);
+        assert_eq!(manifest.description_timestamps(), "");
+    }
+}
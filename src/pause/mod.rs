@@ -0,0 +1,65 @@
+use std::{
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Something the batch checks between videos to know whether it should hold off.
+pub trait PauseSignal {
+    fn is_paused(&self) -> bool;
+}
+
+/// Paused for as long as the file exists.
+impl PauseSignal for Path {
+    fn is_paused(&self) -> bool {
+        self.exists()
+    }
+}
+
+impl PauseSignal for AtomicBool {
+    fn is_paused(&self) -> bool {
+        self.load(Ordering::Relaxed)
+    }
+}
+
+/// Waits until `signal` is no longer paused, checking it every `interval`. Returns how long it
+/// waited.
+pub async fn wait_while_paused<S: PauseSignal + ?Sized>(
+    signal: &S,
+    interval: Duration,
+) -> Duration {
+    let paused_at = Instant::now();
+    while signal.is_paused() {
+        tokio::time::sleep(interval).await;
+    }
+    paused_at.elapsed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test]
+    async fn the_batch_holds_while_paused_and_continues_when_resumed() {
+        let paused = Arc::new(AtomicBool::new(true));
+        let done = Arc::new(Mutex::new(Vec::new()));
+
+        let batch = tokio::spawn({
+            let (paused, done) = (paused.clone(), done.clone());
+            async move {
+                for video in 0..3 {
+                    wait_while_paused(&*paused, Duration::from_millis(5)).await;
+                    done.lock().unwrap().push(video);
+                }
+            }
+        });
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(done.lock().unwrap().is_empty());
+
+        paused.store(false, Ordering::Relaxed);
+        batch.await.unwrap();
+        assert_eq!(*done.lock().unwrap(), vec![0, 1, 2]);
+    }
+}
@@ -1,24 +1,32 @@
-use std::{cmp::Ordering, collections::HashMap, fs, path::Path};
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fs,
+    hash::Hasher,
+    path::{Path, PathBuf},
+};
 
+use anyhow::Context;
 use async_openai::{
-    config::OpenAIConfig,
     types::{ChatCompletionRequestMessage, CreateChatCompletionRequest, Role},
     Chat,
 };
-use image::RgbaImage;
+use futures_util::{stream, Stream, StreamExt};
+use glam::IVec2;
+use image::{Rgba, RgbaImage};
 use markup5ever::interface::TreeSink;
 use reqwest_middleware::ClientWithMiddleware;
-use rusttype::Font;
 use scraper::{ElementRef, Html, Selector};
 use serde::de::Visitor;
 use taffy::{
     prelude::{Rect, Size},
     style::{AlignContent, Dimension, FlexWrap, LengthPercentageAuto, Style},
 };
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
 
 use crate::{
-    video_gen::ui::{StyledNode, VideoUI},
-    ContentSource,
+    video_gen::ui::{FontHandle, ImageFit, Shadow, StyledNode, Theme, UiUpdater, VideoUI},
+    ContentSource, OpenAIConfig,
 };
 
 #[derive(Debug)]
@@ -186,14 +194,180 @@ impl SCPIndex {
     }
 }
 
+/// Where the wiki pages are read from.
+pub trait PageSource {
+    async fn page(&self, url: &str) -> anyhow::Result<String>;
+
+    /// Anything else on the wiki, like the images in an article.
+    async fn file(&self, url: &str) -> anyhow::Result<Vec<u8>>;
+}
+
+impl PageSource for ClientWithMiddleware {
+    async fn page(&self, url: &str) -> anyhow::Result<String> {
+        Ok(self.get(url).send().await?.text().await?)
+    }
+
+    async fn file(&self, url: &str) -> anyhow::Result<Vec<u8>> {
+        Ok(self.get(url).send().await?.bytes().await?.to_vec())
+    }
+}
+
+/// Pages saved as HTML files in a directory, named after their path on the wiki with `/` replaced
+/// by `_`. `https://scp-wiki.wikidot.com/scp-173/offset/1` is read from `scp-173_offset_1.html`.
+/// Other files keep their own extension, so an image at `/local--files/scp-173/173.jpg` is read
+/// from `local--files_scp-173_173.jpg`.
+#[derive(Debug, Clone)]
+pub struct LocalArchive {
+    dir: PathBuf,
+}
+
+impl LocalArchive {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        LocalArchive { dir: dir.into() }
+    }
+
+    fn path(&self, url: &str) -> PathBuf {
+        self.dir.join(format!("{}.html", Self::file_name(url)))
+    }
+
+    fn file_name(url: &str) -> String {
+        let path = url
+            .split_once("://")
+            .and_then(|(_, rest)| rest.split_once('/'))
+            .map_or(url, |(_, path)| path);
+
+        path.trim_matches('/').replace('/', "_")
+    }
+}
+
+impl PageSource for LocalArchive {
+    async fn page(&self, url: &str) -> anyhow::Result<String> {
+        let path = self.path(url);
+
+        tokio::fs::read_to_string(&path)
+            .await
+            .with_context(|| format!("{url} is not archived at {}", path.display()))
+    }
+
+    async fn file(&self, url: &str) -> anyhow::Result<Vec<u8>> {
+        let path = self.dir.join(Self::file_name(url));
+
+        tokio::fs::read(&path)
+            .await
+            .with_context(|| format!("{url} is not archived at {}", path.display()))
+    }
+}
+
+/// A part of the narration together with a description of the image shown while it is spoken.
+#[derive(Debug, Clone)]
+pub struct Beat {
+    pub narration: String,
+    pub image_description: String,
+}
+
+/// Hand-written values for an SCP that replace the scraped/generated ones. Loaded from
+/// `overrides/<name>.json`, every field is optional.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct Overrides {
+    pub title: Option<String>,
+    pub classification: ClassificationOverrides,
+    pub dialogue: Option<String>,
+    pub image_description: Option<String>,
+    /// Name of the text-to-speech voice, e.g. `en-US-Studio-O`
+    pub voice: Option<String>,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct ClassificationOverrides {
+    pub primary: Option<String>,
+    pub containment: Option<String>,
+    pub secondary: Option<String>,
+    pub disruption: Option<String>,
+    pub risk: Option<String>,
+}
+
 pub struct SCP {
     name: String,
     series: SCPSeries,
     url: String,
     article: Option<String>,
+    article_limit: usize,
+    narrated_sections: Option<Vec<String>>,
+    min_article_chars: usize,
+    refresh_completions: bool,
+    /// The article page downloaded by [`prefetch_articles`]
+    prefetched_page: Option<String>,
 }
 
 impl SCP {
+    pub const MAX_ARTICLE_CHARS: usize = 65000;
+    const MAX_OFFSET_FOLLOWS: usize = 5;
+    pub const MIN_ARTICLE_CHARS: usize = 300;
+    const MAX_BEAT_ATTEMPTS: usize = 3;
+
+    pub fn new(name: impl Into<String>, series: SCPSeries, url: impl Into<String>) -> Self {
+        SCP {
+            name: name.into(),
+            series,
+            url: url.into(),
+            article: None,
+            article_limit: 0,
+            narrated_sections: None,
+            min_article_chars: Self::MIN_ARTICLE_CHARS,
+            refresh_completions: false,
+            prefetched_page: None,
+        }
+    }
+
+    /// Articles with less text than this are treated as stubs, see [`StubArticle`].
+    pub fn set_min_article_chars(&mut self, chars: usize) {
+        self.min_article_chars = chars;
+    }
+
+    /// Asks GPT for the dialogue and image description again instead of reusing the answers cached
+    /// for the same prompt. The new answers replace the cached ones.
+    pub fn set_refresh_completions(&mut self, refresh: bool) {
+        self.refresh_completions = refresh;
+    }
+
+    /// Only narrate the sections whose heading starts with one of `sections`, e.g. "Description"
+    /// or "Special Containment Procedures". Matching ignores case.
+    pub fn narrate_sections(&mut self, sections: Vec<String>) {
+        self.narrated_sections = Some(sections);
+    }
+
+    /// The part of the article the narration is written from.
+    async fn narration_source(&mut self, pages: impl PageSource) -> anyhow::Result<String> {
+        let article = self.article(pages).await?;
+
+        let Some(narrated) = &self.narrated_sections else {
+            return Ok(article);
+        };
+
+        let selected = split_sections(&article)
+            .into_iter()
+            .filter(|(heading, _)| {
+                let heading = heading.to_lowercase();
+                narrated
+                    .iter()
+                    .any(|section| heading.starts_with(&section.to_lowercase()))
+            })
+            .map(|(heading, text)| format!("{heading}:\n{text}"))
+            .collect::<Vec<_>>();
+
+        if selected.is_empty() {
+            println!(
+                "Warning: {} has none of the sections {narrated:?}, narrating the whole article",
+                self.name
+            );
+            return Ok(article);
+        }
+
+        Ok(selected.join("\n"))
+    }
+
     pub fn name(&self) -> &str {
         &self.name
     }
@@ -202,159 +376,137 @@ impl SCP {
         &self.url
     }
 
-    pub async fn title(&self, reqwest: ClientWithMiddleware) -> Option<String> {
+    /// The title from the series page, `None` if the SCP isn't listed on it.
+    pub async fn title(&self, pages: impl PageSource) -> anyhow::Result<Option<String>> {
+        let series_url = self.series.url();
         let body = Html::parse_document(
-            &reqwest
-                .get(&self.series.url())
-                .send()
+            &pages
+                .page(&series_url)
                 .await
-                .unwrap()
-                .text()
-                .await
-                .unwrap(),
+                .with_context(|| format!("Failed to fetch {series_url}"))?,
         );
 
-        let elm = body
-            .select(
-                &Selector::parse(&format!("a[href=\"/{}\"]", self.name.to_ascii_lowercase()))
-                    .unwrap(),
-            )
-            .next()?;
-
-        let title = ElementRef::wrap(elm.parent().unwrap())
-            .unwrap()
-            .text()
-            .collect::<Vec<_>>()
-            .join(" ")
-            .split("-")
-            .last()
-            .unwrap()
-            .trim()
-            .to_owned();
+        let selector = format!("a[href=\"/{}\"]", self.name.to_ascii_lowercase());
+        let selector = Selector::parse(&selector)
+            .map_err(|err| anyhow::anyhow!("Invalid selector {selector}: {err}"))?;
 
-        Some(title)
-    }
+        let Some(elm) = body.select(&selector).next() else {
+            return Ok(None);
+        };
 
-    pub async fn classification(
-        &mut self,
-        reqwest: ClientWithMiddleware,
-    ) -> anyhow::Result<Classification> {
-        let article = self.article(reqwest).await?.clone();
+        let parent = elm
+            .parent()
+            .and_then(ElementRef::wrap)
+            .with_context(|| format!("The link to {} has no parent element", self.name))?;
 
-        let mut end = 500;
+        let text = parent.text().collect::<Vec<_>>().join(" ");
 
-        let article = loop {
-            match article.get(..end) {
-                Some(article) => break article,
-                None => end += 1,
-            }
-        };
+        let title = strip_designation(&text, &self.name);
 
-        Ok(Classification::from_article(article))
+        Ok((!title.is_empty()).then_some(title))
     }
 
-    pub async fn article(&mut self, reqwest: ClientWithMiddleware) -> anyhow::Result<String> {
-        if let Some(article) = &self.article {
-            Ok(article.clone())
-        } else {
-            let mut body = Html::parse_document(
-                &reqwest
-                    .get(&self.url)
-                    .send()
-                    .await
-                    .unwrap()
-                    .text()
-                    .await
-                    .unwrap(),
-            );
-
-            for script_tag in body
-                .select(&Selector::parse("script").unwrap())
-                .map(|elm| elm.id())
-                .collect::<Vec<_>>()
-            {
-                body.remove_from_parent(&script_tag);
-            }
+    pub fn overrides(&self) -> anyhow::Result<Overrides> {
+        let path = format!("overrides/{}.json", self.name);
 
-            for license_tag in body
-                .select(&Selector::parse(".licensebox").unwrap())
-                .map(|elm| elm.id())
-                .collect::<Vec<_>>()
-            {
-                body.remove_from_parent(&license_tag);
-            }
+        match fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data).with_context(|| format!("Invalid {path}")),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Overrides::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
 
-            for footer_tag in body
-                .select(&Selector::parse(".footer-wikiwalk-nav").unwrap())
-                .map(|elm| elm.id())
-                .collect::<Vec<_>>()
-            {
-                body.remove_from_parent(&footer_tag);
-            }
+    pub async fn classification(
+        &mut self,
+        pages: impl PageSource,
+    ) -> anyhow::Result<Classification> {
+        self.classification_with_window(pages, Classification::SCAN_WINDOW)
+            .await
+    }
 
-            for collection_tag in body
-                .select(&Selector::parse(".collection").unwrap())
-                .map(|elm| elm.id())
-                .collect::<Vec<_>>()
-            {
-                body.remove_from_parent(&collection_tag);
-            }
+    /// Like [`SCP::classification`], but searches the first `window` bytes of the article for the
+    /// classes when it has no classification bar.
+    pub async fn classification_with_window(
+        &mut self,
+        pages: impl PageSource,
+        window: usize,
+    ) -> anyhow::Result<Classification> {
+        let doc = Html::parse_document(&self.article_page(&pages).await?);
+        if let Some(classification) = Classification::from_acs(&doc) {
+            return Ok(classification);
+        }
 
-            if let Some(link) = body
-                .select(&Selector::parse("a").unwrap())
-                .filter_map(|elm| elm.value().attr("href"))
-                .find(|link| link.contains("offset/1"))
-            {
-                let data = reqwest
-                    .get(link)
-                    .send()
-                    .await
-                    .unwrap()
-                    .text()
-                    .await
-                    .unwrap();
-                body = Html::parse_document(&data);
-            }
+        let article = self.article(pages).await?;
+        let end = floor_char_boundary(&article, window);
 
-            let root = body
-                .select(&Selector::parse("#page-content").unwrap())
-                .next()
-                .unwrap();
-
-            let full_article = root
-                .text()
-                .map(|s| s.trim())
-                .filter(|s| !s.is_empty())
-                .enumerate()
-                .filter_map(|(i, s)| if i < 5 { None } else { Some(s) })
-                .collect::<Vec<_>>()
-                .join("\n");
+        Ok(Classification::from_article(&article[..end]))
+    }
 
-            let start = full_article.find("SCP-").unwrap();
-            let full_article = full_article[start.checked_sub(100).unwrap_or(0)..].to_string();
+    /// Generates the summary split into `count` beats, each paired with a matching image description.
+    pub async fn dialogue_beats(
+        &mut self,
+        openai: &async_openai::Client<OpenAIConfig>,
+        pages: impl PageSource,
+        count: usize,
+    ) -> anyhow::Result<Vec<Beat>> {
+        let article = self.narration_source(pages).await?;
 
-            let full_article = if full_article.len() > 65000 {
-                full_article[0..65000].into()
-            } else {
-                full_article
-            };
+        let messages = vec![
+            ChatCompletionRequestMessage {
+                role: Role::User,
+                content: format!("Here is a fragment of {}'s information page:\n```\n{article}\n```", self.name),
+                name: None,
+            },
+            ChatCompletionRequestMessage {
+                role: Role::User,
+                content: format!("Generate a summary of {} based on the information provided above. Start with its object classification, then go on to describe the SCP. Then talk about its containment procedures. Split the summary into exactly {count} parts. For each part write a line starting with \"NARRATION:\" followed by that part of the summary, then a line starting with \"IMAGE:\" followed by a concise visual description of what the narration is talking about. Do not use the █ character.", self.name),
+                name: None,
+            },
+        ];
 
-            self.article = Some(full_article);
+        // The beats set how many images are made and where the subtitles split, so GPT is asked
+        // again when it writes a different number of them
+        let mut found = 0;
+        for _ in 0..Self::MAX_BEAT_ATTEMPTS {
+            let beats = parse_beats(&chat_completion(openai, messages.clone()).await?);
+            if beats.len() == count {
+                return Ok(beats);
+            }
 
-            Ok(self.article.as_ref().unwrap().clone())
+            println!(
+                "Asked for {count} beats but got {}, asking again",
+                beats.len()
+            );
+            found = beats.len();
         }
+
+        anyhow::bail!(
+            "The generated summary had {found} beats instead of {count} after {} attempts",
+            Self::MAX_BEAT_ATTEMPTS
+        )
     }
-}
 
-impl ContentSource for SCP {
-    type ContentIter = SCPIter;
+    /// Same as [`ContentSource::dialogue`] but at most `max_words` words long.
+    pub async fn dialogue_with_max_words(
+        &mut self,
+        openai: &async_openai::Client<OpenAIConfig>,
+        pages: impl PageSource,
+        max_words: usize,
+    ) -> anyhow::Result<String> {
+        self.summary(openai, pages, Some(max_words)).await
+    }
 
-    async fn dialogue(
+    async fn summary(
         &mut self,
         openai: &async_openai::Client<OpenAIConfig>,
-        reqwest: ClientWithMiddleware,
+        pages: impl PageSource,
+        max_words: Option<usize>,
     ) -> anyhow::Result<String> {
-        let article = self.article(reqwest).await?.clone();
+        let article = self.narration_source(pages).await?;
+        let length = match max_words {
+            Some(max_words) => format!(" The summary must be at most {max_words} words long."),
+            None => String::default(),
+        };
 
         let messages = vec![
             ChatCompletionRequestMessage {
@@ -364,167 +516,895 @@ impl ContentSource for SCP {
             },
             ChatCompletionRequestMessage {
                 role: Role::User,
-                content: format!("Generate a summary of {} based on the information provided above. The summary should be a paragraph. Start the paragraph with its object classification, then go on to describe the SCP. Then talk about its containment procedures. Do not use the █ character.", self.name),
+                content: format!("Generate a summary of {} based on the information provided above. The summary should be a paragraph. Start the paragraph with its object classification, then go on to describe the SCP. Then talk about its containment procedures.{length} Do not use the █ character.", self.name),
                 name: None,
             },
         ];
 
-        let resp = Chat::new(openai)
-            .create(CreateChatCompletionRequest {
-                model: "gpt-3.5-turbo-16k".into(),
-                messages,
-                temperature: None,
-                top_p: None,
-                n: None,
-                stream: None,
-                stop: None,
-                max_tokens: None,
-                presence_penalty: None,
-                frequency_penalty: None,
-                logit_bias: None,
-                user: None,
-            })
-            .await?;
+        completion_without_refusal(openai, messages, self.refresh_completions).await
+    }
 
-        assert!(resp.choices.len() == 1);
-        assert!(resp.choices[0].message.role == Role::Assistant);
+    pub async fn article(&mut self, pages: impl PageSource) -> anyhow::Result<String> {
+        self.article_with_limit(pages, Self::MAX_ARTICLE_CHARS)
+            .await
+    }
 
-        Ok(resp.choices[0].message.content.clone())
+    /// The article split into `(heading, text)` sections like "Description" or "Addendum 1".
+    /// Articles without any headings come back as a single "Body" section.
+    pub async fn sections(
+        &mut self,
+        pages: impl PageSource,
+    ) -> anyhow::Result<Vec<(String, String)>> {
+        Ok(split_sections(&self.article(pages).await?))
     }
 
-    async fn image_description(
+    /// The first `sentences` sentences of the description, for narrating without generating a
+    /// summary.
+    pub async fn summary_from_article(
         &mut self,
-        openai: &async_openai::Client<OpenAIConfig>,
-        reqwest: ClientWithMiddleware,
+        pages: impl PageSource,
+        sentences: usize,
     ) -> anyhow::Result<String> {
-        let article = self.article(reqwest).await?.clone();
+        let sections = self.sections(pages).await?;
+
+        let text = match sections
+            .iter()
+            .find(|(heading, _)| heading.to_lowercase().starts_with("description"))
+        {
+            Some((_, text)) => text.clone(),
+            None => sections
+                .into_iter()
+                .map(|(_, text)| text)
+                .collect::<Vec<_>>()
+                .join("\n"),
+        };
 
-        let resp = Chat::new(openai)
-            .create(CreateChatCompletionRequest {
-                model: "gpt-3.5-turbo-16k".into(),
-                messages: vec![
-                    ChatCompletionRequestMessage {
-                        role: Role::User,
-                        content: format!("Here is a fragment of {}'s information page:\n```\n{article}\n```", self.name),
-                        name: None,
-                    },
-                    ChatCompletionRequestMessage {
-                        role: Role::User,
-                        content: format!("Visually describe {} based on the information provided above. Do not use the █ character. Do not mention anything outside of the visual description. Try to be as concise as possible.", self.name),
-                        name: None,
+        Ok(text
+            .split_inclusive(['.', '!', '?'])
+            .take(sentences)
+            .collect::<String>()
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" "))
+    }
+
+    /// The first image in the article, if it has one.
+    pub async fn article_image(&self, pages: impl PageSource) -> anyhow::Result<Option<RgbaImage>> {
+        let page = self.article_page(&pages).await?;
+
+        let src = {
+            let body = Html::parse_document(&page);
+            let src = body
+                .select(&Selector::parse("#page-content img").unwrap())
+                .next()
+                .and_then(|img| img.value().attr("src"))
+                .map(|src| src.to_owned());
+            src
+        };
+
+        let Some(src) = src else {
+            return Ok(None);
+        };
+
+        let src = reqwest::Url::parse(&self.url)?.join(&src)?;
+        let data = pages.file(src.as_str()).await?;
+
+        Ok(Some(image::load_from_memory(&data)?.to_rgba8()))
+    }
+
+    pub async fn article_with_limit(
+        &mut self,
+        pages: impl PageSource,
+        max_chars: usize,
+    ) -> anyhow::Result<String> {
+        let cached = self.article.as_ref().filter(|article| {
+            max_chars <= self.article_limit || article.chars().count() < self.article_limit
+        });
+
+        if let Some(article) = cached {
+            Ok(truncate_chars(article, max_chars).to_owned())
+        } else {
+            let cached = fs::read_to_string(self.cache_path(None))
+                .or_else(|_| fs::read_to_string(self.cache_path(Some(max_chars))));
+
+            let full_article = match cached {
+                Ok(article) => {
+                    self.check_not_stub(&article)?;
+                    article
+                }
+                Err(_) => {
+                    let article = self.scrape_article(pages, max_chars).await?;
+                    self.check_not_stub(&article)?;
+
+                    // Scraping stops following offsets once there is enough text, so a capped
+                    // article is only reused for the same limit
+                    let limit = (article.chars().count() >= max_chars).then_some(max_chars);
+                    let cached = fs::create_dir_all(Self::cache_dir())
+                        .and_then(|_| fs::write(self.cache_path(limit), &article));
+                    if let Err(err) = cached {
+                        println!(
+                            "Warning: couldn't cache the article of {}: {err}",
+                            self.name
+                        );
                     }
-                ],
-                temperature: None,
-                top_p: None,
-                n: None,
-                stream: None,
-                stop: None,
-                max_tokens: None,
-                presence_penalty: None,
-                frequency_penalty: None,
-                logit_bias: None,
-                user: None,
-            })
-            .await?;
 
-        assert!(resp.choices.len() == 1);
-        assert!(resp.choices[0].message.role == Role::Assistant);
+                    article
+                }
+            };
+            let full_article = truncate_chars(&full_article, max_chars).to_string();
+
+            self.article = Some(full_article);
+            self.article_limit = max_chars;
 
-        Ok(resp.choices[0]
-            .message
-            .content
-            .clone()
-            .replace("memetic", "███████")
-            .replace("bodily fluids", "****** fluids")
-            .replace("living humans", "****** humans")
-            .replace("trauma", "******")
-            .replace("necrosis", "********")
-            .replace("gangrene", "********")
-            .replace("orifices", "********")
-            .replace("oral", "mouth's"))
+            Ok(self.article.as_ref().unwrap().clone())
+        }
     }
 
-    fn iter() -> anyhow::Result<Self::ContentIter> {
-        let index = SCPIndex::from_file("src/scp/index.json")?;
+    /// Fails with [`StubArticle`] if `article` is too short or a placeholder.
+    fn check_not_stub(&self, article: &str) -> Result<(), StubArticle> {
+        // Stories can mention deleted pages too, placeholders say it right at the start
+        let lowercase = truncate_chars(article, 500).to_lowercase();
+        if let Some(phrase) = STUB_PHRASES
+            .iter()
+            .find(|phrase| lowercase.contains(*phrase))
+        {
+            return Err(StubArticle {
+                name: self.name.clone(),
+                reason: format!("it says \"{phrase}\""),
+            });
+        }
 
-        Ok(SCPIter {
-            ordered_keys: index.sorted_keys().into_iter(),
-            index,
-        })
+        let chars = article.chars().count();
+        if chars < self.min_article_chars {
+            return Err(StubArticle {
+                name: self.name.clone(),
+                reason: format!("it only has {chars} characters"),
+            });
+        }
+
+        Ok(())
     }
-}
 
-pub struct SCPIter {
-    ordered_keys: std::vec::IntoIter<String>,
-    index: SCPIndex,
-}
+    /// Downloads the article and strips it down to its text.
+    async fn scrape_article(
+        &self,
+        pages: impl PageSource,
+        max_chars: usize,
+    ) -> anyhow::Result<String> {
+        let mut body = Html::parse_document(&self.article_page(&pages).await?);
 
-impl Iterator for SCPIter {
-    type Item = SCP;
+        for script_tag in body
+            .select(&Selector::parse("script").unwrap())
+            .map(|elm| elm.id())
+            .collect::<Vec<_>>()
+        {
+            body.remove_from_parent(&script_tag);
+        }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let key = self.ordered_keys.next()?;
-        let item = self.index.0.remove(&key)?;
+        for license_tag in body
+            .select(&Selector::parse(".licensebox").unwrap())
+            .map(|elm| elm.id())
+            .collect::<Vec<_>>()
+        {
+            body.remove_from_parent(&license_tag);
+        }
 
-        Some(SCP {
-            name: item.scp,
-            series: item.series,
-            url: item.url,
-            article: None,
-        })
+        for footer_tag in body
+            .select(&Selector::parse(".footer-wikiwalk-nav").unwrap())
+            .map(|elm| elm.id())
+            .collect::<Vec<_>>()
+        {
+            body.remove_from_parent(&footer_tag);
+        }
+
+        for collection_tag in body
+            .select(&Selector::parse(".collection").unwrap())
+            .map(|elm| elm.id())
+            .collect::<Vec<_>>()
+        {
+            body.remove_from_parent(&collection_tag);
+        }
+
+        // Offset links are followed until a page holds enough text. Some malformed pages link
+        // back to themselves or each other, so pages already fetched are never fetched again.
+        let mut visited = vec![self.url.clone()];
+        while visited.len() <= Self::MAX_OFFSET_FOLLOWS {
+            let needs_more = match page_text(&body) {
+                Some(text) => text.chars().count() < max_chars,
+                None => true,
+            };
+
+            let Some(link) = body
+                .select(&Selector::parse("a").unwrap())
+                .filter_map(|elm| elm.value().attr("href"))
+                .find(|link| {
+                    needs_more
+                        && link.contains("offset/")
+                        && !visited.iter().any(|visited| visited == link)
+                })
+                .map(|link| link.to_owned())
+            else {
+                break;
+            };
+
+            body = Html::parse_document(&pages.page(&link).await?);
+            visited.push(link);
+        }
+
+        let full_article = page_text(&body).context("Article has no #page-content")?;
+
+        let start = full_article.find("SCP-").unwrap_or(0);
+        let start = floor_char_boundary(&full_article, start.saturating_sub(100));
+        Ok(full_article[start..].to_owned())
     }
-}
 
-#[derive(Debug, proc_macros::FromArticle, proc_macros::AsText)]
-pub enum ContainmentClass {
-    Safe,
-    Euclid,
-    Keter,
-    Neutralized,
-    Pending,
-    Explained,
-    Esoteric,
-}
+    /// The article page, without downloading it again if it was prefetched.
+    async fn article_page(&self, pages: &impl PageSource) -> anyhow::Result<String> {
+        match &self.prefetched_page {
+            Some(page) => Ok(page.clone()),
+            None => pages.page(&self.url).await,
+        }
+    }
 
-impl Into<RgbaImage> for &ContainmentClass {
-    fn into(self) -> RgbaImage {
-        match self {
-            ContainmentClass::Safe => image::open("assets/containment/Safe.png")
-                .unwrap()
-                .to_rgba8(),
-            ContainmentClass::Euclid => image::open("assets/containment/Euclid.png")
-                .unwrap()
-                .to_rgba8(),
-            ContainmentClass::Keter => image::open("assets/containment/Keter.png")
-                .unwrap()
-                .to_rgba8(),
-            ContainmentClass::Neutralized => image::open("assets/containment/Neutralized.png")
-                .unwrap()
-                .to_rgba8(),
-            ContainmentClass::Pending => image::open("assets/containment/Pending.png")
-                .unwrap()
-                .to_rgba8(),
-            ContainmentClass::Explained => image::open("assets/containment/Explained.png")
-                .unwrap()
-                .to_rgba8(),
-            ContainmentClass::Esoteric => image::open("assets/containment/Esoteric.png")
-                .unwrap()
-                .to_rgba8(),
+    /// Where the article is cached, with the limit it was scraped with if it was cut short.
+    fn cache_path(&self, limit: Option<usize>) -> PathBuf {
+        match limit {
+            Some(limit) => Self::cache_dir().join(format!("{}-{limit}.article", self.name)),
+            None => Self::cache_dir().join(format!("{}.article", self.name)),
         }
     }
-}
 
-impl Into<String> for &ContainmentClass {
-    fn into(self) -> String {
-        self.as_text().into()
+    // SCP_CACHE_DIR is where scraped articles are kept between runs
+    fn cache_dir() -> PathBuf {
+        match std::env::var("SCP_CACHE_DIR") {
+            Ok(dir) => PathBuf::from(dir),
+            Err(_) => std::env::temp_dir().join("hoti-rs-articles"),
+        }
+    }
+
+    /// Deletes every cached article so they are scraped again.
+    pub fn clear_cache() -> anyhow::Result<()> {
+        let entries = match fs::read_dir(Self::cache_dir()) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "article") {
+                fs::remove_file(path)?;
+            }
+        }
+
+        Ok(())
     }
 }
 
-#[derive(Debug, proc_macros::FromArticle, proc_macros::AsText)]
-pub enum SecondaryClass {
-    Apollyon,
-    Archon,
-    Cernunnos,
+const CHAT_MODEL: &str = "gpt-3.5-turbo-16k";
+
+async fn chat_completion(
+    openai: &async_openai::Client<OpenAIConfig>,
+    messages: Vec<ChatCompletionRequestMessage>,
+) -> anyhow::Result<String> {
+    let resp = Chat::new(openai)
+        .create(CreateChatCompletionRequest {
+            model: CHAT_MODEL.into(),
+            messages,
+            temperature: None,
+            top_p: None,
+            n: None,
+            stream: None,
+            stop: None,
+            max_tokens: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+            logit_bias: None,
+            user: None,
+        })
+        .await?;
+
+    assert!(resp.choices.len() == 1);
+    assert!(resp.choices[0].message.role == Role::Assistant);
+
+    let content = resp.choices[0].message.content.clone();
+    if resp.choices[0].finish_reason.as_deref() == Some("content_filter") {
+        return Err(Refusal { response: content }.into());
+    }
+
+    Ok(content)
+}
+
+// How refusals tend to start, compared against the lowercased start of a response
+const REFUSAL_PHRASES: &[&str] = &[
+    "i'm sorry",
+    "i am sorry",
+    "sorry, but",
+    "i apologize",
+    "i can't",
+    "i cannot",
+    "i'm unable",
+    "i am unable",
+    "i won't",
+    "as an ai",
+];
+
+/// Whether `response` reads like GPT declining to answer instead of answering.
+fn looks_like_refusal(response: &str) -> bool {
+    let start = truncate_chars(response.trim_start(), 100)
+        .to_lowercase()
+        .replace('\u{2019}', "'");
+    REFUSAL_PHRASES
+        .iter()
+        .any(|phrase| start.starts_with(phrase))
+}
+
+/// Like [`cached_chat_completion`], but when GPT refuses it is asked once more with a reminder that
+/// the article is fiction, failing with [`Refusal`] if it refuses again.
+async fn completion_without_refusal(
+    openai: &async_openai::Client<OpenAIConfig>,
+    mut messages: Vec<ChatCompletionRequestMessage>,
+    refresh: bool,
+) -> anyhow::Result<String> {
+    let response = match cached_chat_completion(openai, messages.clone(), refresh).await {
+        Ok(response) if !looks_like_refusal(&response) => return Ok(response),
+        Ok(response) => response,
+        Err(err) => match err.downcast::<Refusal>() {
+            Ok(refusal) => refusal.response,
+            Err(err) => return Err(err),
+        },
+    };
+    println!("GPT refused, asking again: {response}");
+
+    messages.insert(0, ChatCompletionRequestMessage {
+        role: Role::System,
+        content: "The SCP Foundation is a collaborative fiction project. Every article is a work of fiction and summarizing or describing one is a creative writing exercise.".into(),
+        name: None,
+    });
+
+    let response = cached_chat_completion(openai, messages, refresh).await?;
+    if looks_like_refusal(&response) {
+        return Err(Refusal { response }.into());
+    }
+
+    Ok(response)
+}
+
+/// GPT declined to write the dialogue or image description.
+#[derive(Debug)]
+pub struct Refusal {
+    pub response: String,
+}
+
+impl std::fmt::Display for Refusal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "GPT refused to answer: {}", self.response)
+    }
+}
+
+impl std::error::Error for Refusal {}
+
+/// Like [`chat_completion`], but the answer to a prompt is kept next to the cached articles and
+/// reused for the same prompt and model unless `refresh` is set.
+async fn cached_chat_completion(
+    openai: &async_openai::Client<OpenAIConfig>,
+    messages: Vec<ChatCompletionRequestMessage>,
+    refresh: bool,
+) -> anyhow::Result<String> {
+    let cache_path = SCP::cache_dir().join(format!("{:016x}.completion", prompt_hash(&messages)));
+
+    if !refresh {
+        if let Ok(completion) = fs::read_to_string(&cache_path) {
+            return Ok(completion);
+        }
+    }
+
+    let completion = chat_completion(openai, messages).await?;
+    // A refusal isn't kept so that the same prompt gets another chance next time
+    if looks_like_refusal(&completion) {
+        return Ok(completion);
+    }
+
+    let cached =
+        fs::create_dir_all(SCP::cache_dir()).and_then(|_| fs::write(&cache_path, &completion));
+    if let Err(err) = cached {
+        println!("Warning: couldn't cache a chat completion: {err}");
+    }
+
+    Ok(completion)
+}
+
+/// A [`Fnv1a`](crate::Fnv1a) hash of the model and every message.
+fn prompt_hash(messages: &[ChatCompletionRequestMessage]) -> u64 {
+    let mut hasher = crate::Fnv1a::default();
+    // Each field ends in 0xff, which never appears in UTF-8, so fields can't run into each other
+    let mut write = |bytes: &[u8]| {
+        hasher.write(bytes);
+        hasher.write_u8(0xff);
+    };
+
+    write(CHAT_MODEL.as_bytes());
+    for message in messages {
+        write(format!("{:?}", message.role).as_bytes());
+        write(message.content.as_bytes());
+    }
+
+    hasher.finish()
+}
+
+// Dashes a series page puts between the designation and the title
+const TITLE_SEPARATORS: &[char] = &['-', '\u{2013}', '\u{2014}'];
+
+/// The title in a series page entry like "SCP-173 - The Sculpture - The Original", with only the
+/// leading designation and the separator after it removed so dashes in the title itself are kept.
+fn strip_designation(entry: &str, name: &str) -> String {
+    let entry = entry.trim();
+
+    let rest = entry
+        .get(..name.len())
+        .filter(|designation| designation.eq_ignore_ascii_case(name))
+        .map(|_| &entry[name.len()..])
+        // "SCP-1730" is a different entry than "SCP-173"
+        .filter(|rest| !rest.starts_with(|ch: char| ch.is_alphanumeric()));
+
+    let title = match rest {
+        Some(rest) => rest.trim_start().trim_start_matches(TITLE_SEPARATORS),
+        // Without the designation in front, only a spaced out separator is taken to be one
+        None => entry.split_once(" - ").map_or(entry, |(_, title)| title),
+    };
+
+    title.trim().to_owned()
+}
+
+/// Translates narration written in English into `language`, e.g. "Hindi". Redactions like
+/// "[REDACTED]" and runs of █ are kept as they are.
+pub async fn translate(
+    openai: &async_openai::Client<OpenAIConfig>,
+    text: &str,
+    language: &str,
+) -> anyhow::Result<String> {
+    let (protected, redactions) = protect_redactions(text);
+
+    let messages = vec![ChatCompletionRequestMessage {
+        role: Role::User,
+        content: format!("Translate the following narration into {language}. Keep every placeholder like {{0}} exactly as it is. Reply with only the translation.\n```\n{protected}\n```"),
+        name: None,
+    }];
+
+    let translated = chat_completion(openai, messages).await?;
+
+    let missing = (0..redactions.len())
+        .filter(|idx| !translated.contains(&format!("{{{idx}}}")))
+        .count();
+    if missing > 0 {
+        println!("Warning: the {language} translation dropped {missing} redactions");
+    }
+
+    Ok(restore_redactions(&translated, &redactions))
+}
+
+// Kept untranslated so the narration sounds as redacted as the article
+const REDACTION_MARKERS: &[&str] = &["[REDACTED]", "[DATA EXPUNGED]", "[DATA REDACTED]"];
+
+/// Swaps every redaction in `text` for a numbered placeholder like `{0}`, returning the
+/// redactions in placeholder order.
+fn protect_redactions(text: &str) -> (String, Vec<String>) {
+    let mut protected = String::default();
+    let mut redactions = Vec::default();
+    let mut rest = text;
+
+    while let Some(ch) = rest.chars().next() {
+        let redaction_len = REDACTION_MARKERS
+            .iter()
+            .find(|marker| rest.starts_with(*marker))
+            .map(|marker| marker.len())
+            .or_else(|| {
+                Some(rest.len() - rest.trim_start_matches('█').len()).filter(|len| *len > 0)
+            });
+
+        let len = match redaction_len {
+            Some(len) => {
+                protected.push_str(&format!("{{{}}}", redactions.len()));
+                redactions.push(rest[..len].to_owned());
+                len
+            }
+            None => {
+                protected.push(ch);
+                ch.len_utf8()
+            }
+        };
+
+        rest = &rest[len..];
+    }
+
+    (protected, redactions)
+}
+
+fn restore_redactions(text: &str, redactions: &[String]) -> String {
+    redactions
+        .iter()
+        .enumerate()
+        .fold(text.to_owned(), |text, (idx, redaction)| {
+            text.replace(&format!("{{{idx}}}"), redaction)
+        })
+}
+
+fn censor_image_description(description: &str) -> String {
+    normalize(description)
+        .replace("memetic", "███████")
+        .replace("bodily fluids", "****** fluids")
+        .replace("living humans", "****** humans")
+        .replace("trauma", "******")
+        .replace("necrosis", "********")
+        .replace("gangrene", "********")
+        .replace("orifices", "********")
+        .replace("oral", "mouth's")
+}
+
+fn parse_beats(resp: &str) -> Vec<Beat> {
+    let mut beats = Vec::default();
+    let mut narration = None;
+
+    for line in resp.lines().map(|line| line.trim()) {
+        if let Some(text) = line.strip_prefix("NARRATION:") {
+            narration = Some(text.trim().to_owned());
+        } else if let Some(text) = line.strip_prefix("IMAGE:") {
+            if let Some(narration) = narration.take() {
+                beats.push(Beat {
+                    narration,
+                    image_description: censor_image_description(text.trim()),
+                });
+            }
+        }
+    }
+
+    beats
+}
+
+// Text only found on pages that were deleted or never written
+const STUB_PHRASES: &[&str] = &[
+    "this page doesn't exist",
+    "the page does not exist",
+    "this page has been deleted",
+];
+
+/// The article of an SCP is too short or a placeholder page, so there is nothing to make a video
+/// from.
+#[derive(Debug)]
+pub struct StubArticle {
+    pub name: String,
+    pub reason: String,
+}
+
+impl std::fmt::Display for StubArticle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The article of {} is a stub, {}", self.name, self.reason)
+    }
+}
+
+impl std::error::Error for StubArticle {}
+
+fn page_text(body: &Html) -> Option<String> {
+    let root = body
+        .select(&Selector::parse("#page-content").unwrap())
+        .next()?;
+
+    Some(
+        root.text()
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .enumerate()
+            .filter_map(|(i, s)| if i < 5 { None } else { Some(s) })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    )
+}
+
+/// NFKC normalizes `s` and drops accents, so full-width and accented letters match their plain
+/// ASCII form.
+fn normalize(s: &str) -> String {
+    s.nfkd()
+        .filter(|ch| !is_combining_mark(*ch))
+        .nfc()
+        .collect()
+}
+
+// Headings are bold or heading elements in #page-content, which end up as their own short line
+// ending in a colon in the article text
+fn split_sections(article: &str) -> Vec<(String, String)> {
+    let is_heading = |line: &str| line.ends_with(':') && line.split_whitespace().count() <= 6;
+
+    let mut sections: Vec<(String, Vec<&str>)> = Vec::default();
+
+    for line in article.lines() {
+        if is_heading(line) {
+            sections.push((line.trim_end_matches(':').to_owned(), Vec::default()));
+        } else if let Some((_, text)) = sections.last_mut() {
+            text.push(line);
+        } else {
+            // Text before the first heading
+            sections.push(("Body".into(), vec![line]));
+        }
+    }
+
+    if sections.iter().all(|(heading, _)| heading == "Body") {
+        return vec![("Body".into(), article.to_owned())];
+    }
+
+    sections
+        .into_iter()
+        .map(|(heading, text)| (heading, text.join("\n")))
+        .collect()
+}
+
+fn truncate_chars(s: &str, max_chars: usize) -> &str {
+    match s.char_indices().nth(max_chars) {
+        Some((idx, _)) => &s[..idx],
+        None => s,
+    }
+}
+
+/// The closest char boundary of `s` at or before `idx`.
+fn floor_char_boundary(s: &str, idx: usize) -> usize {
+    s.char_indices()
+        .map(|(i, _)| i)
+        .chain([s.len()])
+        .take_while(|&i| i <= idx)
+        .last()
+        .unwrap_or(0)
+}
+
+impl ContentSource for SCP {
+    type ContentIter = SCPIter;
+
+    async fn dialogue(
+        &mut self,
+        openai: &async_openai::Client<OpenAIConfig>,
+        reqwest: ClientWithMiddleware,
+    ) -> anyhow::Result<String> {
+        self.summary(openai, reqwest, None).await
+    }
+
+    async fn image_description(
+        &mut self,
+        openai: &async_openai::Client<OpenAIConfig>,
+        reqwest: ClientWithMiddleware,
+    ) -> anyhow::Result<String> {
+        let article = self.article(reqwest).await?.clone();
+
+        let messages = vec![
+            ChatCompletionRequestMessage {
+                role: Role::User,
+                content: format!("Here is a fragment of {}'s information page:\n```\n{article}\n```", self.name),
+                name: None,
+            },
+            ChatCompletionRequestMessage {
+                role: Role::User,
+                content: format!("Visually describe {} based on the information provided above. Do not use the █ character. Do not mention anything outside of the visual description. Try to be as concise as possible.", self.name),
+                name: None,
+            }
+        ];
+
+        Ok(censor_image_description(
+            &completion_without_refusal(openai, messages, self.refresh_completions).await?,
+        ))
+    }
+
+    fn iter() -> anyhow::Result<Self::ContentIter> {
+        let index = SCPIndex::from_file("src/scp/index.json")?;
+
+        Ok(SCPIter {
+            ordered_keys: index.sorted_keys().into_iter(),
+            index,
+        })
+    }
+}
+
+pub struct SCPIter {
+    ordered_keys: std::vec::IntoIter<String>,
+    index: SCPIndex,
+}
+
+impl Iterator for SCPIter {
+    type Item = SCP;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let key = self.ordered_keys.next()?;
+
+            let Some(item) = self.index.0.remove(&key) else {
+                println!("Warning: {key} is missing from the index, skipping it");
+                continue;
+            };
+
+            break Some(SCP::new(item.scp, item.series, item.url));
+        }
+    }
+}
+
+/// Downloads the article pages of up to `concurrency` SCPs at once on their own task, so they keep
+/// coming in while the SCP before them is being made. The SCPs come out in the same order they go
+/// in.
+pub fn prefetch_articles(
+    scps: impl Iterator<Item = SCP> + Send + 'static,
+    pages: ClientWithMiddleware,
+    concurrency: usize,
+) -> impl Stream<Item = SCP> {
+    let (sender, receiver) = tokio::sync::mpsc::channel(concurrency.max(1));
+
+    // Only the download happens here, the page is parsed when the article is used since parsed
+    // HTML can't be sent between threads
+    tokio::spawn(async move {
+        let mut prefetched = stream::iter(scps)
+            .map(move |mut scp| {
+                let pages = pages.clone();
+                async move {
+                    let cached = [None, Some(SCP::MAX_ARTICLE_CHARS)]
+                        .into_iter()
+                        .any(|limit| scp.cache_path(limit).exists());
+                    if cached {
+                        return scp;
+                    }
+
+                    // A failed fetch is tried again when the article is used, which reports the
+                    // error
+                    match pages.page(&scp.url).await {
+                        Ok(page) => scp.prefetched_page = Some(page),
+                        Err(err) => println!(
+                            "Warning: couldn't prefetch the article of {}: {err}",
+                            scp.name
+                        ),
+                    }
+
+                    scp
+                }
+            })
+            .buffered(concurrency.max(1));
+
+        while let Some(scp) = prefetched.next().await {
+            // The batch stopped taking SCPs
+            if sender.send(scp).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    stream::unfold(receiver, |mut receiver| async move {
+        let scp = receiver.recv().await?;
+        Some((scp, receiver))
+    })
+}
+
+/// The classic object class from the "Object Class:" line, which older articles have instead of
+/// an Anomaly Classification System bar.
+#[derive(Debug, proc_macros::FromArticle, proc_macros::AsText, proc_macros::AsTextDisplay)]
+pub enum PrimaryObjectClass {
+    Safe,
+    Euclid,
+    Keter,
+    Thaumiel,
+    Apollyon,
+    #[article(aliases = "Neutralised")]
+    Neutralized,
+    Explained,
+}
+
+/// The containment or secondary class a [`PrimaryObjectClass`] is also known as in the Anomaly
+/// Classification System.
+enum AcsClass {
+    Containment(ContainmentClass),
+    Secondary(SecondaryClass),
+}
+
+impl PrimaryObjectClass {
+    /// Every object class is also an ACS class, which its definition, color and badge come from.
+    fn acs(&self) -> AcsClass {
+        match self {
+            PrimaryObjectClass::Safe => AcsClass::Containment(ContainmentClass::Safe),
+            PrimaryObjectClass::Euclid => AcsClass::Containment(ContainmentClass::Euclid),
+            PrimaryObjectClass::Keter => AcsClass::Containment(ContainmentClass::Keter),
+            PrimaryObjectClass::Thaumiel => AcsClass::Secondary(SecondaryClass::Thaumiel),
+            PrimaryObjectClass::Apollyon => AcsClass::Secondary(SecondaryClass::Apollyon),
+            PrimaryObjectClass::Neutralized => AcsClass::Containment(ContainmentClass::Neutralized),
+            PrimaryObjectClass::Explained => AcsClass::Containment(ContainmentClass::Explained),
+        }
+    }
+
+    pub fn definition(&self) -> &'static str {
+        match self.acs() {
+            AcsClass::Containment(class) => class.definition(),
+            AcsClass::Secondary(class) => class.definition(),
+        }
+    }
+
+    pub fn color(&self) -> Rgba<u8> {
+        match self.acs() {
+            AcsClass::Containment(class) => class.color(),
+            AcsClass::Secondary(class) => class.color(),
+        }
+    }
+}
+
+impl Into<RgbaImage> for &PrimaryObjectClass {
+    fn into(self) -> RgbaImage {
+        match self.acs() {
+            AcsClass::Containment(class) => (&class).into(),
+            AcsClass::Secondary(class) => (&class).into(),
+        }
+    }
+}
+
+#[derive(Debug, proc_macros::FromArticle, proc_macros::AsText, proc_macros::AsTextDisplay)]
+pub enum ContainmentClass {
+    Safe,
+    Euclid,
+    Keter,
+    #[article(aliases = "Neutralised")]
+    Neutralized,
+    Pending,
+    Explained,
+    Esoteric,
+}
+
+impl ContainmentClass {
+    pub fn definition(&self) -> &'static str {
+        match self {
+            ContainmentClass::Safe => "easily and reliably contained",
+            ContainmentClass::Euclid => "unpredictable, not always reliably contained",
+            ContainmentClass::Keter => "difficult to contain consistently",
+            ContainmentClass::Neutralized => "no longer anomalous",
+            ContainmentClass::Pending => "not classified yet",
+            ContainmentClass::Explained => "fully understood, no longer anomalous",
+            ContainmentClass::Esoteric => "does not fit the standard classes",
+        }
+    }
+
+    pub fn color(&self) -> Rgba<u8> {
+        match self {
+            ContainmentClass::Safe => [46, 204, 113, 255].into(),
+            ContainmentClass::Euclid => [241, 196, 15, 255].into(),
+            ContainmentClass::Keter => [231, 76, 60, 255].into(),
+            ContainmentClass::Neutralized => [149, 165, 166, 255].into(),
+            ContainmentClass::Pending => [189, 195, 199, 255].into(),
+            ContainmentClass::Explained => [52, 152, 219, 255].into(),
+            ContainmentClass::Esoteric => [155, 89, 182, 255].into(),
+        }
+    }
+}
+
+impl Into<RgbaImage> for &ContainmentClass {
+    fn into(self) -> RgbaImage {
+        match self {
+            ContainmentClass::Safe => image::open("assets/containment/Safe.png")
+                .unwrap()
+                .to_rgba8(),
+            ContainmentClass::Euclid => image::open("assets/containment/Euclid.png")
+                .unwrap()
+                .to_rgba8(),
+            ContainmentClass::Keter => image::open("assets/containment/Keter.png")
+                .unwrap()
+                .to_rgba8(),
+            ContainmentClass::Neutralized => image::open("assets/containment/Neutralized.png")
+                .unwrap()
+                .to_rgba8(),
+            ContainmentClass::Pending => image::open("assets/containment/Pending.png")
+                .unwrap()
+                .to_rgba8(),
+            ContainmentClass::Explained => image::open("assets/containment/Explained.png")
+                .unwrap()
+                .to_rgba8(),
+            ContainmentClass::Esoteric => image::open("assets/containment/Esoteric.png")
+                .unwrap()
+                .to_rgba8(),
+        }
+    }
+}
+
+#[derive(Debug, proc_macros::FromArticle, proc_macros::AsText, proc_macros::AsTextDisplay)]
+pub enum SecondaryClass {
+    Apollyon,
+    Archon,
+    Cernunnos,
     Decommissioned,
     Hiemal,
     Tiamat,
@@ -533,6 +1413,36 @@ pub enum SecondaryClass {
     Uncontained,
 }
 
+impl SecondaryClass {
+    pub fn definition(&self) -> &'static str {
+        match self {
+            SecondaryClass::Apollyon => "cannot be contained",
+            SecondaryClass::Archon => "could be contained, but is kept uncontained",
+            SecondaryClass::Cernunnos => "cannot be contained by normal means",
+            SecondaryClass::Decommissioned => "intentionally destroyed",
+            SecondaryClass::Hiemal => "contained only in part",
+            SecondaryClass::Tiamat => "actively at war with the Foundation",
+            SecondaryClass::Ticonderoga => "cannot and should not be contained",
+            SecondaryClass::Thaumiel => "used to contain other anomalies",
+            SecondaryClass::Uncontained => "no longer contained",
+        }
+    }
+
+    pub fn color(&self) -> Rgba<u8> {
+        match self {
+            SecondaryClass::Apollyon => [192, 57, 43, 255].into(),
+            SecondaryClass::Archon => [142, 68, 173, 255].into(),
+            SecondaryClass::Cernunnos => [39, 174, 96, 255].into(),
+            SecondaryClass::Decommissioned => [127, 140, 141, 255].into(),
+            SecondaryClass::Hiemal => [133, 193, 233, 255].into(),
+            SecondaryClass::Tiamat => [230, 126, 34, 255].into(),
+            SecondaryClass::Ticonderoga => [22, 160, 133, 255].into(),
+            SecondaryClass::Thaumiel => [236, 240, 241, 255].into(),
+            SecondaryClass::Uncontained => [211, 84, 0, 255].into(),
+        }
+    }
+}
+
 impl Into<RgbaImage> for &SecondaryClass {
     fn into(self) -> RgbaImage {
         match self {
@@ -567,13 +1477,7 @@ impl Into<RgbaImage> for &SecondaryClass {
     }
 }
 
-impl Into<String> for &SecondaryClass {
-    fn into(self) -> String {
-        self.as_text().into()
-    }
-}
-
-#[derive(Debug, proc_macros::FromArticle, proc_macros::AsText)]
+#[derive(Debug, proc_macros::FromArticle, proc_macros::AsText, proc_macros::AsTextDisplay)]
 pub enum DisruptionClass {
     Dark,
     Vlam,
@@ -582,6 +1486,28 @@ pub enum DisruptionClass {
     Amida,
 }
 
+impl DisruptionClass {
+    pub fn definition(&self) -> &'static str {
+        match self {
+            DisruptionClass::Dark => "unnoticeable to the public",
+            DisruptionClass::Vlam => "noticeable to a small area",
+            DisruptionClass::Keneq => "noticeable to a whole region",
+            DisruptionClass::Ekhi => "noticeable worldwide",
+            DisruptionClass::Amida => "threatens all of reality",
+        }
+    }
+
+    pub fn color(&self) -> Rgba<u8> {
+        match self {
+            DisruptionClass::Dark => [149, 165, 166, 255].into(),
+            DisruptionClass::Vlam => [46, 204, 113, 255].into(),
+            DisruptionClass::Keneq => [241, 196, 15, 255].into(),
+            DisruptionClass::Ekhi => [230, 126, 34, 255].into(),
+            DisruptionClass::Amida => [231, 76, 60, 255].into(),
+        }
+    }
+}
+
 impl Into<RgbaImage> for &DisruptionClass {
     fn into(self) -> RgbaImage {
         match self {
@@ -604,13 +1530,7 @@ impl Into<RgbaImage> for &DisruptionClass {
     }
 }
 
-impl Into<String> for &DisruptionClass {
-    fn into(self) -> String {
-        self.as_text().into()
-    }
-}
-
-#[derive(Debug, proc_macros::FromArticle, proc_macros::AsText)]
+#[derive(Debug, proc_macros::FromArticle, proc_macros::AsText, proc_macros::AsTextDisplay)]
 pub enum RiskClass {
     Notice,
     Caution,
@@ -619,6 +1539,28 @@ pub enum RiskClass {
     Critical,
 }
 
+impl RiskClass {
+    pub fn definition(&self) -> &'static str {
+        match self {
+            RiskClass::Notice => "little to no harm to people",
+            RiskClass::Caution => "minor harm to people",
+            RiskClass::Warning => "significant harm to people",
+            RiskClass::Danger => "serious harm to people",
+            RiskClass::Critical => "lethal to people",
+        }
+    }
+
+    pub fn color(&self) -> Rgba<u8> {
+        match self {
+            RiskClass::Notice => [236, 240, 241, 255].into(),
+            RiskClass::Caution => [46, 204, 113, 255].into(),
+            RiskClass::Warning => [241, 196, 15, 255].into(),
+            RiskClass::Danger => [230, 126, 34, 255].into(),
+            RiskClass::Critical => [231, 76, 60, 255].into(),
+        }
+    }
+}
+
 impl Into<RgbaImage> for &RiskClass {
     fn into(self) -> RgbaImage {
         match self {
@@ -631,44 +1573,226 @@ impl Into<RgbaImage> for &RiskClass {
     }
 }
 
-impl Into<String> for &RiskClass {
-    fn into(self) -> String {
-        self.as_text().into()
-    }
-}
-
 #[derive(Debug)]
 pub struct Classification {
+    pub primary: Option<PrimaryObjectClass>,
     pub containment: Option<ContainmentClass>,
     pub secondary: Option<SecondaryClass>,
     pub disruption: Option<DisruptionClass>,
     pub risk: Option<RiskClass>,
+    /// Leaves the tags of missing classes out of [`Classification::ui`] instead of showing them
+    /// as "???"
+    pub omit_missing: bool,
 }
 
 impl Classification {
+    /// How many bytes from the start of the article are searched for the classes.
+    const SCAN_WINDOW: usize = 500;
+
+    /// Reads the classes from the Anomaly Classification System bar of the article, falling back
+    /// to searching the start of the text when it doesn't have one.
+    pub fn from_html(doc: &Html) -> Self {
+        if let Some(classification) = Self::from_acs(doc) {
+            return classification;
+        }
+
+        let text = page_text(doc).unwrap_or_default();
+        let end = floor_char_boundary(&text, Self::SCAN_WINDOW);
+
+        Self::from_article(&text[..end])
+    }
+
+    /// The classes from the Anomaly Classification System bar, `None` if the article has none.
+    fn from_acs(doc: &Html) -> Option<Self> {
+        let bar = doc
+            .select(&Selector::parse(".anom-bar-container").unwrap())
+            .next()?;
+
+        let class_text = |category: &str| {
+            let selector = Selector::parse(&format!(".{category} .class-text")).unwrap();
+            let text = bar.select(&selector).next()?.text().collect::<String>();
+
+            Some(normalize(text.trim()))
+        };
+
+        // The bar has no separate object class, its containment class is the object class when
+        // it is one of the classic ones
+        Some(Classification {
+            primary: class_text("contain-class")
+                .and_then(|class| PrimaryObjectClass::from_article(&class)),
+            containment: class_text("contain-class")
+                .and_then(|class| ContainmentClass::from_article(&class)),
+            secondary: class_text("second-class")
+                .and_then(|class| SecondaryClass::from_article(&class)),
+            disruption: class_text("disrupt-class")
+                .and_then(|class| DisruptionClass::from_article(&class)),
+            risk: class_text("risk-class").and_then(|class| RiskClass::from_article(&class)),
+            omit_missing: false,
+        })
+    }
+
     fn from_article(s: &str) -> Self {
+        // Everything below only works with offsets into the normalized text, never the original
+        let s = &normalize(s);
+
         Classification {
-            containment: ContainmentClass::from_article(s),
-            secondary: SecondaryClass::from_article(s),
-            disruption: DisruptionClass::from_article(s),
-            risk: RiskClass::from_article(s),
+            primary: class_from_article(
+                s,
+                &["object class:"],
+                PrimaryObjectClass::from_article_all,
+            ),
+            containment: class_from_article(
+                s,
+                &["object class:", "containment class:"],
+                ContainmentClass::from_article_all,
+            ),
+            secondary: class_from_article(
+                s,
+                &["secondary class:"],
+                SecondaryClass::from_article_all,
+            ),
+            disruption: class_from_article(
+                s,
+                &["disruption class:"],
+                DisruptionClass::from_article_all,
+            ),
+            risk: class_from_article(s, &["risk class:"], RiskClass::from_article_all),
+            omit_missing: false,
+        }
+    }
+
+    /// A line per class in `categories` that is set, explaining what the class means. A class
+    /// that is both the primary and the containment class is only explained once.
+    pub fn legend_text(&self, categories: &[ClassCategory]) -> String {
+        let mut lines: Vec<String> = Vec::default();
+        for line in categories
+            .iter()
+            .filter_map(|category| match category {
+                ClassCategory::Primary => self
+                    .primary
+                    .as_ref()
+                    .map(|class| (class.as_text().to_owned(), class.definition())),
+                ClassCategory::Containment => self
+                    .containment
+                    .as_ref()
+                    .map(|class| (class.as_text().to_owned(), class.definition())),
+                ClassCategory::Secondary => self
+                    .secondary
+                    .as_ref()
+                    .map(|class| (class.as_text().to_owned(), class.definition())),
+                ClassCategory::Disruption => self
+                    .disruption
+                    .as_ref()
+                    .map(|class| (class.as_text().to_owned(), class.definition())),
+                ClassCategory::Risk => self
+                    .risk
+                    .as_ref()
+                    .map(|class| (class.as_text().to_owned(), class.definition())),
+            })
+            .map(|(name, definition)| format!("{name}: {definition}"))
+        {
+            if !lines.contains(&line) {
+                lines.push(line);
+            }
+        }
+
+        lines.join("\n")
+    }
+
+    /// An empty lower-third overlay for [`ClassLegend`] to fill in.
+    pub fn legend_ui(font: FontHandle, theme: &Theme) -> StyledNode {
+        StyledNode {
+            node: crate::video_gen::ui::Node::Text {
+                text: String::default(),
+                font,
+                scale: rusttype::Scale { x: 40.0, y: 40.0 },
+                line_height: 50,
+                color: theme.accent,
+                word_break: crate::video_gen::ui::WordBreak::Hyphenate,
+                align: crate::video_gen::ui::TextAlign::Center,
+                outline: None,
+                auto_contrast: false,
+            },
+            style: Style {
+                position: taffy::style::Position::Absolute,
+                size: Size {
+                    width: Dimension::Percent(1.0),
+                    height: Dimension::Points(200.0),
+                },
+                inset: Rect {
+                    left: LengthPercentageAuto::Points(0.0),
+                    right: LengthPercentageAuto::Points(0.0),
+                    top: LengthPercentageAuto::Auto,
+                    bottom: LengthPercentageAuto::Points(100.0),
+                },
+                ..Default::default()
+            },
+            opacity: 1.0,
+            z_index: None,
+        }
+    }
+
+    /// Replaces every class that is set in `overrides`. Fails if one isn't exactly the name of a
+    /// class, so a typo doesn't quietly drop it.
+    pub fn with_overrides(mut self, overrides: &ClassificationOverrides) -> anyhow::Result<Self> {
+        fn parse<T>(
+            class: &str,
+            from_text: fn(&str) -> Option<T>,
+            texts: &[&str],
+        ) -> anyhow::Result<Option<T>> {
+            from_text(class).map(Some).with_context(|| {
+                format!("Unknown class override {class:?}, expected one of {texts:?}")
+            })
         }
+
+        if let Some(class) = &overrides.primary {
+            self.primary = parse(
+                class,
+                PrimaryObjectClass::from_text,
+                PrimaryObjectClass::TEXTS,
+            )?;
+        }
+        if let Some(class) = &overrides.containment {
+            self.containment = parse(class, ContainmentClass::from_text, ContainmentClass::TEXTS)?;
+        }
+        if let Some(class) = &overrides.secondary {
+            self.secondary = parse(class, SecondaryClass::from_text, SecondaryClass::TEXTS)?;
+        }
+        if let Some(class) = &overrides.disruption {
+            self.disruption = parse(class, DisruptionClass::from_text, DisruptionClass::TEXTS)?;
+        }
+        if let Some(class) = &overrides.risk {
+            self.risk = parse(class, RiskClass::from_text, RiskClass::TEXTS)?;
+        }
+
+        Ok(self)
     }
 
-    pub fn ui(&self, font: Font<'static>, ui: &mut VideoUI) -> StyledNode {
+    pub fn with_omit_missing(mut self, omit_missing: bool) -> Self {
+        self.omit_missing = omit_missing;
+        self
+    }
+
+    /// A tag per class, always in the order primary, containment, secondary, disruption, risk.
+    pub fn ui(&self, font: FontHandle, theme: &Theme, ui: &mut VideoUI) -> StyledNode {
         let mut nodes = Vec::default();
 
         const TAG_WIDTH: f32 = 450.0;
         const ICON_TEXT_SIZE: f32 = 60.0;
 
-        fn add_ui<T: Into<RgbaImage> + Into<String> + Copy>(
-            font: &Font<'static>,
+        fn add_ui<T: Into<RgbaImage> + std::fmt::Display + Copy>(
+            font: FontHandle,
+            theme: &Theme,
             ui: &mut VideoUI,
             nodes: &mut Vec<StyledNode>,
             class: Option<T>,
+            color: Option<Rgba<u8>>,
+            omit_missing: bool,
         ) {
             let (img, text) = if let Some(class) = class {
-                (class.into(), class.into())
+                (class.into(), class.to_string())
+            } else if omit_missing {
+                return;
             } else {
                 (
                     image::open("assets/containment/Pending.png")
@@ -683,7 +1807,15 @@ impl Classification {
             nodes.push(StyledNode {
                 node: crate::video_gen::ui::Node::Container(vec![
                     StyledNode {
-                        node: crate::video_gen::ui::Node::Image(handle),
+                        node: crate::video_gen::ui::Node::Image {
+                            image: handle,
+                            fit: ImageFit::Fill,
+                            shadow: Some(Shadow {
+                                offset: IVec2::new(4, 4),
+                                blur: 6,
+                                color: [0, 0, 0, 160].into(),
+                            }),
+                        },
                         style: Style {
                             size: Size {
                                 width: Dimension::Points(ICON_TEXT_SIZE),
@@ -691,17 +1823,23 @@ impl Classification {
                             },
                             ..Default::default()
                         },
+                        opacity: 1.0,
+                        z_index: None,
                     },
                     StyledNode {
-                        node: crate::video_gen::ui::Node::TextCentered {
+                        node: crate::video_gen::ui::Node::Text {
                             text,
-                            font: font.clone(),
+                            font,
                             scale: rusttype::Scale {
                                 x: ICON_TEXT_SIZE,
                                 y: ICON_TEXT_SIZE,
                             },
                             line_height: ICON_TEXT_SIZE as u32,
-                            color: [255; 4].into(),
+                            color: color.unwrap_or(theme.accent),
+                            word_break: crate::video_gen::ui::WordBreak::Never,
+                            align: crate::video_gen::ui::TextAlign::Center,
+                            outline: None,
+                            auto_contrast: false,
                         },
                         style: Style {
                             size: Size {
@@ -716,6 +1854,8 @@ impl Classification {
                             },
                             ..Default::default()
                         },
+                        opacity: 1.0,
+                        z_index: None,
                     },
                 ]),
                 style: Style {
@@ -731,13 +1871,63 @@ impl Classification {
                     },
                     ..Default::default()
                 },
+                opacity: 1.0,
+                z_index: None,
             });
         }
 
-        add_ui(&font, ui, &mut nodes, self.containment.as_ref());
-        add_ui(&font, ui, &mut nodes, self.secondary.as_ref());
-        add_ui(&font, ui, &mut nodes, self.disruption.as_ref());
-        add_ui(&font, ui, &mut nodes, self.risk.as_ref());
+        // Only gets its own tag when it says something the containment class doesn't
+        let primary = self.primary.as_ref().filter(|primary| {
+            !matches!(&self.containment, Some(containment) if containment.as_text() == primary.as_text())
+        });
+        if let Some(primary) = primary {
+            add_ui(
+                font,
+                theme,
+                ui,
+                &mut nodes,
+                Some(primary),
+                Some(primary.color()),
+                self.omit_missing,
+            );
+        }
+
+        add_ui(
+            font,
+            theme,
+            ui,
+            &mut nodes,
+            self.containment.as_ref(),
+            self.containment.as_ref().map(|class| class.color()),
+            self.omit_missing,
+        );
+        add_ui(
+            font,
+            theme,
+            ui,
+            &mut nodes,
+            self.secondary.as_ref(),
+            self.secondary.as_ref().map(|class| class.color()),
+            self.omit_missing,
+        );
+        add_ui(
+            font,
+            theme,
+            ui,
+            &mut nodes,
+            self.disruption.as_ref(),
+            self.disruption.as_ref().map(|class| class.color()),
+            self.omit_missing,
+        );
+        add_ui(
+            font,
+            theme,
+            ui,
+            &mut nodes,
+            self.risk.as_ref(),
+            self.risk.as_ref().map(|class| class.color()),
+            self.omit_missing,
+        );
 
         StyledNode {
             node: crate::video_gen::ui::Node::Container(nodes),
@@ -756,6 +1946,744 @@ impl Classification {
                 flex_wrap: FlexWrap::Wrap,
                 ..Default::default()
             },
+            opacity: 1.0,
+            z_index: None,
+        }
+    }
+}
+
+/// Reads the class from the value after one of `labels` when the article has it, an explicit
+/// "None" or "N/A" there means there is no class and "Pending" means the pending class, for the
+/// classes that have one. Without a label the whole text is searched.
+fn class_from_article<T: std::fmt::Debug>(
+    article: &str,
+    labels: &[&str],
+    from_article_all: fn(&str) -> Vec<(T, usize)>,
+) -> Option<T> {
+    // ASCII lowercasing keeps byte offsets the same, so they can be used on `article`
+    let lowercase = article.to_ascii_lowercase();
+
+    let value = labels.iter().find_map(|label| {
+        let start = lowercase.find(label)? + label.len();
+
+        // The label and its value are usually separate text nodes, so on separate lines
+        article[start..]
+            .lines()
+            .map(|line| line.trim())
+            .find(|line| !line.is_empty())
+    });
+
+    match value {
+        Some(value) => {
+            let value_lowercase = value.to_ascii_lowercase();
+            if value_lowercase.starts_with("none") || value_lowercase.starts_with("n/a") {
+                None
+            } else if value_lowercase.starts_with("pending") {
+                from_article_all("Pending")
+                    .into_iter()
+                    .next()
+                    .map(|(class, _)| class)
+            } else {
+                from_article_all(value)
+                    .into_iter()
+                    .next()
+                    .map(|(class, _)| class)
+            }
+        }
+        None => {
+            let found = from_article_all(article);
+            if found.len() > 1 {
+                println!("Found competing classes {found:?}, using the closest one");
+            }
+
+            found.into_iter().next().map(|(class, _)| class)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClassCategory {
+    Primary,
+    Containment,
+    Secondary,
+    Disruption,
+    Risk,
+}
+
+/// Shows the legend from [`Classification::legend_text`] in the node from
+/// [`Classification::legend_ui`] for the given frames.
+pub struct ClassLegend {
+    node_idx: usize,
+    text: String,
+    frames: std::ops::Range<u32>,
+    shown: bool,
+}
+
+impl ClassLegend {
+    /// Fills in the legend node at `ui.children[node_idx]`.
+    pub fn new(
+        node_idx: usize,
+        classification: &Classification,
+        categories: &[ClassCategory],
+        frames: std::ops::Range<u32>,
+    ) -> Self {
+        ClassLegend {
+            node_idx,
+            text: classification.legend_text(categories),
+            frames,
+            shown: false,
+        }
+    }
+}
+
+impl UiUpdater for ClassLegend {
+    fn update(&mut self, frame_idx: u32, ui: &mut VideoUI) {
+        // Frames can be skipped, so the legend is shown or hidden whenever that changes rather
+        // than on the first and last frame
+        let show = self.frames.contains(&frame_idx);
+        if show == self.shown {
+            return;
+        }
+
+        if let crate::video_gen::ui::Node::Text { text, .. } = &mut ui.children[self.node_idx].node
+        {
+            *text = if show {
+                self.text.clone()
+            } else {
+                String::default()
+            };
+            self.shown = show;
+            ui.mark_dirty();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.shown = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn explicit_none_class_is_not_matched_elsewhere() {
+        let classification = Classification::from_article(
+            "Item #: SCP-0000\nObject Class: None\nSpecial Containment Procedures: Formerly a Keter \
+             anomaly, it no longer needs containment.",
+        );
+
+        assert!(classification.containment.is_none());
+        assert!(classification.primary.is_none());
+    }
+
+    #[test]
+    fn explicit_pending_class() {
+        let classification = Classification::from_article(
+            "Item #: SCP-0000\nObject Class: Pending\nSpecial Containment Procedures: Treat it as \
+             Euclid until then.",
+        );
+
+        assert!(matches!(
+            classification.containment,
+            Some(ContainmentClass::Pending)
+        ));
+        assert!(classification.primary.is_none());
+    }
+
+    #[test]
+    fn parse_beats_pairs_each_narration_with_its_image() {
+        let beats = parse_beats(
+            "NARRATION: SCP-173 is a statue.\nIMAGE: A concrete statue\n\nNARRATION: It moves when \
+             unobserved.\nIMAGE: An empty room\nNARRATION: A narration without an image",
+        );
+
+        assert_eq!(beats.len(), 2);
+        assert_eq!(beats[0].narration, "SCP-173 is a statue.");
+        assert_eq!(beats[0].image_description, "A concrete statue");
+        assert_eq!(beats[1].narration, "It moves when unobserved.");
+        assert_eq!(beats[1].image_description, "An empty room");
+    }
+
+    #[test]
+    fn class_from_article_reads_the_labelled_value() {
+        let article =
+            "item #: scp-0000\nobject class:\n\nsafe\nspecial containment procedures: it \
+                       was once thought to be keter.";
+
+        assert!(matches!(
+            class_from_article(
+                article,
+                &["object class:"],
+                ContainmentClass::from_article_all
+            ),
+            Some(ContainmentClass::Safe)
+        ));
+        assert!(class_from_article(
+            "disruption class: n/a, formerly keneq",
+            &["disruption class:"],
+            DisruptionClass::from_article_all
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn full_width_and_accented_classes_are_recognized() {
+        assert_eq!(normalize("Ｋｅｔｅｒ, Éuclid"), "Keter, Euclid");
+
+        // The full-width text before the label is longer in bytes than its normalized form
+        let classification = Classification::from_article(
+            "Ｉｔｅｍ #: ＳＣＰ-0000\nＯｂｊｅｃｔ Ｃｌａｓｓ:\nＫｅｔｅｒ\nDisruption Class: Vlam",
+        );
+        assert!(matches!(
+            classification.containment,
+            Some(ContainmentClass::Keter)
+        ));
+        assert!(matches!(
+            Classification::from_article("Object Class: Éuclid").containment,
+            Some(ContainmentClass::Euclid)
+        ));
+
+        assert_eq!(
+            censor_image_description("A ｍｅｍｅｔｉｃ statue"),
+            "A ███████ statue"
+        );
+    }
+
+    #[test]
+    fn class_from_article_without_a_label_takes_the_closest() {
+        assert!(matches!(
+            class_from_article(
+                "it was reclassified as euclid after the keter incident",
+                &["object class:"],
+                ContainmentClass::from_article_all
+            ),
+            Some(ContainmentClass::Euclid)
+        ));
+    }
+
+    #[test]
+    fn refusals_are_recognized() {
+        assert!(looks_like_refusal("I'm sorry, but I can't help with that."));
+        assert!(looks_like_refusal(
+            "  I\u{2019}m sorry, but I can't help with that."
+        ));
+        assert!(looks_like_refusal(
+            "As an AI language model, I cannot write this."
+        ));
+        assert!(!looks_like_refusal(
+            "SCP-173 is a Euclid class statue. I can't stress enough how dangerous it is."
+        ));
+    }
+
+    #[test]
+    fn prompt_hash_is_stable_and_depends_on_every_message() {
+        let message = |role, content: &str| ChatCompletionRequestMessage {
+            role,
+            content: content.into(),
+            name: None,
+        };
+        let messages = vec![message(Role::System, "ab"), message(Role::User, "c")];
+
+        assert_eq!(prompt_hash(&messages), prompt_hash(&messages.clone()));
+        assert_ne!(
+            prompt_hash(&messages),
+            prompt_hash(&[message(Role::System, "a"), message(Role::User, "bc")])
+        );
+        assert_ne!(
+            prompt_hash(&messages),
+            prompt_hash(&[message(Role::User, "ab"), message(Role::User, "c")])
+        );
+    }
+
+    /// An article page with `body` as its content.
+    fn mock_page(body: &str) -> String {
+        // The first five strings of #page-content are the rating module and are skipped
+        format!(
+            "<html><body><div id=\"page-content\">\
+             <p>1</p><p>2</p><p>3</p><p>4</p><p>5</p>{body}</div>\
+             <div class=\"licensebox\">Licensed</div></body></html>"
+        )
+    }
+
+    #[tokio::test]
+    async fn scraping_follows_offsets_on_a_mock_server() {
+        use wiremock::{
+            matchers::{method, path},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/scp-173"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(mock_page(&format!(
+                    "<p>Too short.</p><a href=\"{}/scp-173/offset/1\">Next</a>",
+                    server.uri()
+                ))),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/scp-173/offset/1"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(mock_page(
+                "<p>Item #: SCP-173</p><p>Object Class: Euclid</p>",
+            )))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let pages = reqwest_middleware::ClientBuilder::new(reqwest::Client::new()).build();
+        let scp = SCP::new(
+            "SCP-173",
+            SCPSeries::Series1,
+            format!("{}/scp-173", server.uri()),
+        );
+
+        let article = scp
+            .scrape_article(pages, SCP::MAX_ARTICLE_CHARS)
+            .await
+            .unwrap();
+
+        assert_eq!(article, "Item #: SCP-173\nObject Class: Euclid");
+    }
+
+    #[tokio::test]
+    async fn prefetched_pages_are_not_downloaded_again() {
+        use wiremock::{
+            matchers::{method, path},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/scp-prefetch-test"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(mock_page("<p>SCP-PREFETCH-TEST</p>")),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let pages = reqwest_middleware::ClientBuilder::new(reqwest::Client::new()).build();
+        let scps = vec![SCP::new(
+            "SCP-PREFETCH-TEST",
+            SCPSeries::Series1,
+            format!("{}/scp-prefetch-test", server.uri()),
+        )];
+
+        let mut prefetched = std::pin::pin!(prefetch_articles(scps.into_iter(), pages.clone(), 2));
+        let scp = prefetched.next().await.unwrap();
+        assert!(prefetched.next().await.is_none());
+
+        let article = scp
+            .scrape_article(pages, SCP::MAX_ARTICLE_CHARS)
+            .await
+            .unwrap();
+
+        assert_eq!(article, "SCP-PREFETCH-TEST");
+    }
+
+    #[tokio::test]
+    async fn small_limits_skip_later_pages() {
+        use wiremock::{
+            matchers::{method, path},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/scp-short-test"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(mock_page(&format!(
+                    "<p>Item #: SCP-SHORT-TEST</p><a href=\"{}/scp-short-test/offset/1\">Next</a>",
+                    server.uri()
+                ))),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/scp-short-test/offset/1"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(mock_page("<p>Description: The rest of the article.</p>")),
+            )
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let pages = reqwest_middleware::ClientBuilder::new(reqwest::Client::new()).build();
+        let scp = SCP::new(
+            "SCP-SHORT-TEST",
+            SCPSeries::Series1,
+            format!("{}/scp-short-test", server.uri()),
+        );
+
+        let article = scp.scrape_article(pages, 10).await.unwrap();
+        assert_eq!(article, "Item #: SCP-SHORT-TEST\nNext");
+        assert_eq!(truncate_chars(&article, 10), "Item #: SC");
+    }
+
+    #[tokio::test]
+    async fn articles_are_split_at_their_headings() {
+        use wiremock::{
+            matchers::{method, path},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/scp-sections-test"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(mock_page(
+                "<p><strong>Item #:</strong> SCP-SECTIONS-TEST</p>\
+                 <p><strong>Object Class:</strong> Euclid</p>\
+                 <p><strong>Special Containment Procedures:</strong></p>\
+                 <p>Kept in a locked container.</p><p>Only opened in pairs.</p>\
+                 <p><strong>Description:</strong></p><p>A concrete statue.</p>",
+            )))
+            .mount(&server)
+            .await;
+
+        let pages = reqwest_middleware::ClientBuilder::new(reqwest::Client::new()).build();
+        let scp = SCP::new(
+            "SCP-SECTIONS-TEST",
+            SCPSeries::Series1,
+            format!("{}/scp-sections-test", server.uri()),
+        );
+        let article = scp
+            .scrape_article(pages, SCP::MAX_ARTICLE_CHARS)
+            .await
+            .unwrap();
+
+        let sections = split_sections(&article);
+        let section = |heading: &str| {
+            sections
+                .iter()
+                .find(|(name, _)| name == heading)
+                .map(|(_, text)| text.as_str())
+        };
+        assert_eq!(
+            section("Special Containment Procedures"),
+            Some("Kept in a locked container.\nOnly opened in pairs.")
+        );
+        assert_eq!(section("Description"), Some("A concrete statue."));
+
+        // Without any headings the whole article is one section
+        assert_eq!(
+            split_sections("A concrete statue.\nIt moves."),
+            [(
+                "Body".to_owned(),
+                "A concrete statue.\nIt moves.".to_owned()
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn capped_articles_are_not_reused_for_a_larger_limit() {
+        use wiremock::{
+            matchers::{method, path},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/scp-limit-test"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(mock_page(&format!(
+                    "<p>Item #: SCP-LIMIT-TEST</p><a href=\"{}/scp-limit-test/offset/1\">Next</a>",
+                    server.uri()
+                ))),
+            )
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/scp-limit-test/offset/1"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(mock_page(
+                "<p>Item #: SCP-LIMIT-TEST</p><p>Description: The rest of the article.</p>",
+            )))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let pages = reqwest_middleware::ClientBuilder::new(reqwest::Client::new()).build();
+        let mut scp = SCP::new(
+            "SCP-LIMIT-TEST",
+            SCPSeries::Series1,
+            format!("{}/scp-limit-test", server.uri()),
+        );
+        scp.set_min_article_chars(0);
+        for limit in [None, Some(10), Some(1000)] {
+            let _ = fs::remove_file(scp.cache_path(limit));
+        }
+
+        let capped = scp.article_with_limit(pages.clone(), 10).await.unwrap();
+        assert_eq!(capped, "Item #: SC");
+
+        // A fresh SCP only has the cache on disk to go on
+        let mut scp = SCP::new(
+            "SCP-LIMIT-TEST",
+            SCPSeries::Series1,
+            format!("{}/scp-limit-test", server.uri()),
+        );
+        scp.set_min_article_chars(0);
+        let full = scp.article_with_limit(pages, 1000).await.unwrap();
+        assert_eq!(
+            full,
+            "Item #: SCP-LIMIT-TEST\nDescription: The rest of the article."
+        );
+
+        for limit in [None, Some(10)] {
+            let _ = fs::remove_file(scp.cache_path(limit));
         }
     }
+
+    #[tokio::test]
+    async fn a_second_dialogue_is_served_from_the_cache() {
+        use wiremock::{
+            matchers::{method, path},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/scp-dialogue-cache-test"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(mock_page(
+                "<p>Item #: SCP-DIALOGUE-CACHE-TEST</p><p>Description: A cached SCP.</p>",
+            )))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "chatcmpl-test",
+                "object": "chat.completion",
+                "created": 0,
+                "model": CHAT_MODEL,
+                "choices": [{
+                    "index": 0,
+                    "message": { "role": "assistant", "content": "Object Class: Safe." },
+                    "finish_reason": "stop"
+                }]
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let openai = async_openai::Client::with_config(
+            OpenAIConfig::new()
+                .with_api_key("test")
+                .with_api_base(server.uri()),
+        );
+        let pages = reqwest_middleware::ClientBuilder::new(reqwest::Client::new()).build();
+        let scp = || {
+            let mut scp = SCP::new(
+                "SCP-DIALOGUE-CACHE-TEST",
+                SCPSeries::Series1,
+                format!("{}/scp-dialogue-cache-test", server.uri()),
+            );
+            scp.set_min_article_chars(0);
+            scp
+        };
+
+        // Refreshing skips whatever an earlier run left behind and caches the new answer
+        let mut first = scp();
+        first.set_refresh_completions(true);
+        let dialogue = first.dialogue(&openai, pages.clone()).await.unwrap();
+        assert_eq!(dialogue, "Object Class: Safe.");
+
+        let cached = scp().dialogue(&openai, pages).await.unwrap();
+        assert_eq!(cached, dialogue);
+
+        let _ = fs::remove_file(first.cache_path(None));
+    }
+
+    #[tokio::test]
+    async fn translations_keep_their_redactions() {
+        use wiremock::{
+            matchers::{body_string_contains, method, path},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let text = "SCP-173 was moved to [REDACTED] by Dr. ████.";
+        let (protected, redactions) = protect_redactions(text);
+        assert_eq!(protected, "SCP-173 was moved to {0} by Dr. {1}.");
+        assert_eq!(redactions, ["[REDACTED]", "████"]);
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_string_contains("into Hindi"))
+            .and(body_string_contains("SCP-173 was moved to {0} by Dr. {1}."))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "chatcmpl-test",
+                "object": "chat.completion",
+                "created": 0,
+                "model": CHAT_MODEL,
+                "choices": [{
+                    "index": 0,
+                    "message": {
+                        "role": "assistant",
+                        "content": "SCP-173 को डॉ. {1} ने {0} में ले जाया गया।"
+                    },
+                    "finish_reason": "stop"
+                }]
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let openai = async_openai::Client::with_config(
+            OpenAIConfig::new()
+                .with_api_key("test")
+                .with_api_base(server.uri()),
+        );
+        let translated = translate(&openai, text, "Hindi").await.unwrap();
+
+        assert_eq!(translated, "SCP-173 को डॉ. ████ ने [REDACTED] में ले जाया गया।");
+    }
+
+    #[test]
+    fn legend_explains_each_class_once_while_it_is_shown() {
+        let classification = Classification {
+            primary: Some(PrimaryObjectClass::Keter),
+            containment: Some(ContainmentClass::Keter),
+            secondary: None,
+            disruption: None,
+            risk: Some(RiskClass::Danger),
+            omit_missing: false,
+        };
+        let categories = [
+            ClassCategory::Primary,
+            ClassCategory::Containment,
+            ClassCategory::Secondary,
+            ClassCategory::Risk,
+        ];
+        assert_eq!(
+            classification.legend_text(&categories),
+            format!(
+                "KETER: difficult to contain consistently\nDANGER: {}",
+                RiskClass::Danger.definition()
+            )
+        );
+
+        let mut ui = VideoUI::default();
+        let font = ui.add_font(
+            "test",
+            rusttype::Font::try_from_bytes(include_bytes!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/assets/SpecialElite-Regular.ttf"
+            )))
+            .unwrap(),
+        );
+        ui.children
+            .push(Classification::legend_ui(font, &Theme::default()));
+        let legend_text = |ui: &VideoUI| match &ui.children[0].node {
+            crate::video_gen::ui::Node::Text { text, .. } => text.clone(),
+            _ => unreachable!(),
+        };
+
+        // The first frame of the legend is skipped, like a frame dropped by the caller
+        let mut legend = ClassLegend::new(0, &classification, &categories, 10..20);
+        for (frame, shown) in [(5, false), (11, true), (19, true), (25, false)] {
+            legend.update(frame, &mut ui);
+            assert_eq!(!legend_text(&ui).is_empty(), shown, "frame {frame}");
+        }
+    }
+
+    #[test]
+    fn object_classes_look_like_their_acs_class() {
+        assert_eq!(
+            PrimaryObjectClass::Keter.definition(),
+            ContainmentClass::Keter.definition()
+        );
+        assert_eq!(
+            PrimaryObjectClass::Keter.color(),
+            ContainmentClass::Keter.color()
+        );
+        assert_eq!(
+            PrimaryObjectClass::Apollyon.definition(),
+            SecondaryClass::Apollyon.definition()
+        );
+        assert_eq!(
+            PrimaryObjectClass::Thaumiel.color(),
+            SecondaryClass::Thaumiel.color()
+        );
+    }
+
+    #[test]
+    fn multibyte_text_before_the_designation_is_not_cut_in_half() {
+        // "é" is two bytes and "█" three, so most offsets land inside a character
+        let article = format!("{}{}SCP-173 is a statue.", "é".repeat(40), "█".repeat(10));
+        let start = article.find("SCP-").unwrap();
+
+        for idx in 0..=article.len() {
+            let boundary = floor_char_boundary(&article, idx);
+            assert!(article.is_char_boundary(boundary), "{idx}");
+            assert!(boundary <= idx && idx - boundary < 3, "{idx}");
+        }
+
+        let lead_in = &article[floor_char_boundary(&article, start.saturating_sub(100))..];
+        assert!(lead_in.starts_with('é'));
+        assert!(lead_in.ends_with("SCP-173 is a statue."));
+    }
+
+    #[test]
+    fn keys_missing_from_the_index_are_skipped() {
+        let index: SCPIndex = serde_json::from_str(
+            r#"{
+                "SCP-002": {"series": "series-1", "scp": "SCP-002", "url": "https://example.com/scp-002"},
+                "SCP-003": {"series": "series-1", "scp": "SCP-003", "url": "https://example.com/scp-003"}
+            }"#,
+        )
+        .unwrap();
+        let iter = SCPIter {
+            ordered_keys: vec![
+                "SCP-001".into(),
+                "SCP-002".into(),
+                "SCP-999".into(),
+                "SCP-003".into(),
+            ]
+            .into_iter(),
+            index,
+        };
+
+        assert_eq!(
+            iter.map(|scp| scp.name().to_owned()).collect::<Vec<_>>(),
+            ["SCP-002", "SCP-003"]
+        );
+    }
+
+    #[test]
+    fn only_the_designation_is_stripped_from_titles() {
+        assert_eq!(
+            strip_designation("SCP-173 - The Sculpture", "SCP-173"),
+            "The Sculpture"
+        );
+        assert_eq!(
+            strip_designation("SCP-173 - The Sculpture - Original", "SCP-173"),
+            "The Sculpture - Original"
+        );
+        assert_eq!(
+            strip_designation("SCP-2000 \u{2014} Deus Ex Machina", "SCP-2000"),
+            "Deus Ex Machina"
+        );
+        assert_eq!(
+            strip_designation("scp-173 - Self-Portrait", "SCP-173"),
+            "Self-Portrait"
+        );
+
+        // Without a separator, or without the designation in front
+        assert_eq!(
+            strip_designation("The Sculpture", "SCP-173"),
+            "The Sculpture"
+        );
+        assert_eq!(
+            strip_designation("SCP-1730 - What Happened to Site-13?", "SCP-173"),
+            "What Happened to Site-13?"
+        );
+    }
 }
@@ -0,0 +1,142 @@
+use image::Rgba;
+
+use super::{
+    easing::Easing,
+    ui::{UiUpdater, VideoUI},
+};
+
+/// Values that can be interpolated between keyframes.
+pub trait Lerp: Copy + Send + Sync + 'static {
+    fn lerp(self, to: Self, t: f32) -> Self;
+}
+
+impl Lerp for f32 {
+    fn lerp(self, to: Self, t: f32) -> Self {
+        self + (to - self) * t
+    }
+}
+
+impl Lerp for Rgba<u8> {
+    fn lerp(self, to: Self, t: f32) -> Self {
+        let mut out = self;
+        for (out, to) in out.0.iter_mut().zip(to.0) {
+            *out = (*out as f32).lerp(to as f32, t).round() as u8;
+        }
+        out
+    }
+}
+
+type Apply<T> = Box<dyn FnMut(&mut VideoUI, T) + Send + Sync>;
+
+/// Animates any property of the UI between keyframes. `apply` writes the interpolated value into
+/// the UI, so it decides which node and property is animated.
+pub struct Animation<T: Lerp> {
+    keyframes: Vec<(u32, T)>,
+    easing: Easing,
+    apply: Apply<T>,
+}
+
+impl<T: Lerp> Animation<T> {
+    pub fn new(
+        mut keyframes: Vec<(u32, T)>,
+        easing: Easing,
+        apply: impl FnMut(&mut VideoUI, T) + Send + Sync + 'static,
+    ) -> Self {
+        keyframes.sort_by_key(|(frame, _)| *frame);
+
+        Animation {
+            keyframes,
+            easing,
+            apply: Box::new(apply),
+        }
+    }
+
+    /// The value at `frame_idx`. Before the first and after the last keyframe the value is held.
+    pub fn value_at(&self, frame_idx: u32) -> Option<T> {
+        self.value_at_time(frame_idx as f32)
+    }
+
+    /// Like [`Animation::value_at`], but in between frames too.
+    fn value_at_time(&self, time: f32) -> Option<T> {
+        let next = self
+            .keyframes
+            .iter()
+            .position(|(frame, _)| *frame as f32 > time);
+
+        match next {
+            Some(0) => self.keyframes.first().map(|(_, value)| *value),
+            Some(next) => {
+                let (from_frame, from) = self.keyframes[next - 1];
+                let (to_frame, to) = self.keyframes[next];
+                let t = (time - from_frame as f32) / (to_frame - from_frame) as f32;
+
+                Some(from.lerp(to, self.easing.apply(t)))
+            }
+            None => self.keyframes.last().map(|(_, value)| *value),
+        }
+    }
+}
+
+impl<T: Lerp> UiUpdater for Animation<T> {
+    fn update(&mut self, frame_idx: u32, ui: &mut VideoUI) {
+        if let Some(value) = self.value_at(frame_idx) {
+            (self.apply)(ui, value);
+
+            // `apply` can change anything, but outside the keyframes it keeps setting the same
+            // value
+            let animating = matches!(
+                (self.keyframes.first(), self.keyframes.last()),
+                (Some((first, _)), Some((last, _))) if (*first..=*last).contains(&frame_idx)
+            );
+            if animating || frame_idx == 0 {
+                ui.mark_dirty();
+            }
+        }
+    }
+
+    fn update_between(&mut self, frame_idx: u32, fraction: f32, ui: &mut VideoUI) {
+        let animating = matches!(
+            (self.keyframes.first(), self.keyframes.last()),
+            (Some((first, _)), Some((last, _))) if (*first..*last).contains(&frame_idx)
+        );
+
+        if let (true, Some(value)) = (animating, self.value_at_time(frame_idx as f32 + fraction)) {
+            (self.apply)(ui, value);
+            ui.mark_dirty();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use taffy::style::Style;
+
+    use super::*;
+    use crate::video_gen::ui::{Node, StyledNode};
+
+    #[test]
+    fn opacity_is_interpolated_between_keyframes() {
+        let mut ui = VideoUI::default();
+        ui.children.push(StyledNode {
+            node: Node::Container(Vec::default()),
+            style: Style::default(),
+            opacity: 1.0,
+            z_index: None,
+        });
+
+        for (easing, quarter) in [(Easing::Linear, 0.25), (Easing::EaseInOut, 0.125)] {
+            let mut fade = Animation::new(vec![(10, 0.0), (30, 1.0)], easing, |ui, opacity| {
+                ui.children[0].opacity = opacity
+            });
+
+            let mut opacity_at = |frame_idx| {
+                fade.update(frame_idx, &mut ui);
+                ui.children[0].opacity
+            };
+            assert_eq!(opacity_at(0), 0.0);
+            assert_eq!(opacity_at(15), quarter);
+            assert_eq!(opacity_at(20), 0.5);
+            assert_eq!(opacity_at(40), 1.0);
+        }
+    }
+}
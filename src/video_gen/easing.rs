@@ -0,0 +1,148 @@
+// Easing functions, each maps linear progress over [0, 1] onto eased progress with 0 -> 0 and
+// 1 -> 1.
+
+use std::f32::consts::PI;
+
+pub fn linear(t: f32) -> f32 {
+    t
+}
+
+pub fn quad_in(t: f32) -> f32 {
+    t * t
+}
+
+pub fn quad_out(t: f32) -> f32 {
+    t * (2.0 - t)
+}
+
+pub fn quad_in_out(t: f32) -> f32 {
+    if t < 0.5 {
+        2.0 * t * t
+    } else {
+        -1.0 + (4.0 - 2.0 * t) * t
+    }
+}
+
+pub fn cubic_in(t: f32) -> f32 {
+    t * t * t
+}
+
+pub fn cubic_out(t: f32) -> f32 {
+    1.0 - (1.0 - t).powi(3)
+}
+
+pub fn cubic_in_out(t: f32) -> f32 {
+    if t < 0.5 {
+        4.0 * t * t * t
+    } else {
+        1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+    }
+}
+
+/// Overshoots the end and springs back.
+pub fn elastic_out(t: f32) -> f32 {
+    if t <= 0.0 || t >= 1.0 {
+        return t.clamp(0.0, 1.0);
+    }
+
+    2f32.powf(-10.0 * t) * ((t * 10.0 - 0.75) * (2.0 * PI / 3.0)).sin() + 1.0
+}
+
+/// Bounces against the end a few times before settling.
+pub fn bounce_out(t: f32) -> f32 {
+    const N: f32 = 7.5625;
+    const D: f32 = 2.75;
+
+    if t < 1.0 / D {
+        N * t * t
+    } else if t < 2.0 / D {
+        let t = t - 1.5 / D;
+        N * t * t + 0.75
+    } else if t < 2.5 / D {
+        let t = t - 2.25 / D;
+        N * t * t + 0.9375
+    } else {
+        let t = t - 2.625 / D;
+        N * t * t + 0.984375
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Easing {
+    #[default]
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+    CubicIn,
+    CubicOut,
+    CubicInOut,
+    Elastic,
+    Bounce,
+}
+
+impl Easing {
+    pub fn apply(&self, t: f32) -> f32 {
+        let f = match self {
+            Easing::Linear => linear,
+            Easing::EaseIn => quad_in,
+            Easing::EaseOut => quad_out,
+            Easing::EaseInOut => quad_in_out,
+            Easing::CubicIn => cubic_in,
+            Easing::CubicOut => cubic_out,
+            Easing::CubicInOut => cubic_in_out,
+            Easing::Elastic => elastic_out,
+            Easing::Bounce => bounce_out,
+        };
+
+        f(t.clamp(0.0, 1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Easing; 9] = [
+        Easing::Linear,
+        Easing::EaseIn,
+        Easing::EaseOut,
+        Easing::EaseInOut,
+        Easing::CubicIn,
+        Easing::CubicOut,
+        Easing::CubicInOut,
+        Easing::Elastic,
+        Easing::Bounce,
+    ];
+
+    #[test]
+    fn easings_start_at_0_and_end_at_1() {
+        for easing in ALL {
+            assert!(easing.apply(0.0).abs() < 1e-6, "{easing:?}");
+            assert!((easing.apply(1.0) - 1.0).abs() < 1e-6, "{easing:?}");
+        }
+    }
+
+    #[test]
+    fn easings_only_go_forward() {
+        // Elastic overshoots the end and bounce falls back from it, both on purpose
+        for easing in ALL
+            .into_iter()
+            .filter(|easing| !matches!(easing, Easing::Elastic | Easing::Bounce))
+        {
+            for step in 1..=100 {
+                let t = step as f32 / 100.0;
+                assert!(
+                    easing.apply(t) >= easing.apply(t - 0.01),
+                    "{easing:?} goes back at {t}"
+                );
+            }
+        }
+
+        for step in 1..50 {
+            let t = step as f32 / 100.0;
+            assert!(Easing::EaseIn.apply(t) < t);
+            assert!(Easing::EaseOut.apply(t) > t);
+        }
+    }
+}
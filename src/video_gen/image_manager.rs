@@ -1,80 +1,813 @@
 use std::time::Duration;
 
 use async_openai::{
-    config::OpenAIConfig,
-    types::{CreateImageRequest, ImageData},
+    error::OpenAIError,
+    types::{CreateImageRequest, ImageData, ImageResponse, ImageSize},
 };
 use base64::Engine;
+use futures_util::{stream, StreamExt};
+use glam::UVec2;
+use image::RgbaImage;
+use reqwest_middleware::ClientWithMiddleware;
+
+use crate::{
+    budget::{BudgetExhausted, RetryBudget},
+    OpenAIConfig,
+};
 
 use super::ui::{ImageHandle, Node, UiUpdater, VideoUI};
 
+#[derive(Debug, Clone)]
 pub struct ImageManager {
     images: Vec<(u32, ImageHandle)>,
 }
 
+/// When the images of an [`ImageManager`] are shown, how many are requested at once and the style
+/// they are drawn in.
+#[derive(Debug, Clone)]
+pub struct ImageSchedule {
+    frame_rate: u32,
+    duration: Duration,
+    count: Option<u8>,
+    concurrency: usize,
+    style: Option<String>,
+    size: ImageSize,
+    /// Downloads the images the API sends as URLs instead of base64
+    client: ClientWithMiddleware,
+}
+
+impl ImageSchedule {
+    pub fn new(frame_rate: u32, duration: Duration) -> Self {
+        ImageSchedule {
+            frame_rate,
+            duration,
+            count: None,
+            concurrency: 1,
+            style: None,
+            size: ImageSize::S1024x1024,
+            client: reqwest_middleware::ClientBuilder::new(reqwest::Client::new()).build(),
+        }
+    }
+
+    /// Generates exactly `count` images instead of one every 5 seconds.
+    pub fn with_count(mut self, count: Option<u8>) -> Self {
+        self.count = count;
+        self
+    }
+
+    /// Sends up to `concurrency` image requests at a time instead of one after the other.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Adds `style`, like "dark cinematic, desaturated", to every image prompt so the images of
+    /// every video look alike.
+    pub fn with_style(mut self, style: Option<String>) -> Self {
+        self.style = style;
+        self
+    }
+
+    /// Downloads images sent as URLs with `client`, like one that retries.
+    pub fn with_client(mut self, client: ClientWithMiddleware) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Requests images shaped as close to a `canvas` sized frame as the API allows, see
+    /// [`image_size_for`].
+    pub fn with_canvas(mut self, canvas: UVec2) -> Self {
+        self.size = image_size_for(canvas);
+        self
+    }
+}
+
+// The sizes the image endpoint makes through this client, largest first. async-openai 0.11
+// doesn't send a model, so the API uses DALL·E 2 which only makes square images.
+const IMAGE_SIZES: &[(ImageSize, UVec2)] = &[
+    (ImageSize::S1024x1024, UVec2::new(1024, 1024)),
+    (ImageSize::S512x512, UVec2::new(512, 512)),
+    (ImageSize::S256x256, UVec2::new(256, 256)),
+];
+
+/// The largest image size with the aspect ratio closest to `canvas`, square for an empty canvas.
+pub fn image_size_for(canvas: UVec2) -> ImageSize {
+    if canvas.x == 0 || canvas.y == 0 {
+        return ImageSize::S1024x1024;
+    }
+
+    // Compared as logs so 1:2 is as far from square as 2:1
+    let aspect = |size: UVec2| (size.x as f32 / size.y as f32).ln();
+    let distance = |size: UVec2| (aspect(size) - aspect(canvas)).abs();
+
+    IMAGE_SIZES
+        .iter()
+        .min_by(|(_, a), (_, b)| distance(*a).total_cmp(&distance(*b)))
+        .map_or(ImageSize::S1024x1024, |(size, _)| size.clone())
+}
+
 impl ImageManager {
+    const MAX_BATCH_ATTEMPTS: u32 = 3;
+    const MAX_BATCH_SIZE: u8 = 10;
+
     pub async fn new(
         prompt: String,
         openai: &async_openai::Client<OpenAIConfig>,
-        frame_rate: u32,
-        duration: Duration,
+        schedule: ImageSchedule,
+        fallback: ImageHandle,
         ui: &mut VideoUI,
+        budget: &RetryBudget,
     ) -> anyhow::Result<Self> {
-        let mut n = ((duration - Duration::from_secs(5)).as_secs_f64() / 5.0) as u8;
-        println!("Generating {n} images");
+        let ImageSchedule {
+            frame_rate,
+            duration,
+            count,
+            concurrency,
+            style,
+            size,
+            client,
+        } = schedule;
+        let style = style.as_deref();
 
-        let frame_step =
-            ((duration - Duration::from_secs(5)).as_secs_f64() / n as f64) as u32 * frame_rate;
+        let slots = image_slots(frame_rate, duration, count);
+        let total = slots.len() as u8;
+        println!("Generating {total} images");
+        if total == 0 {
+            return Ok(ImageManager {
+                images: Vec::default(),
+            });
+        }
 
         let img_gen = async_openai::Images::new(openai);
-        let mut resps = Vec::default();
-
-        while n != 0 {
-            let resp = img_gen
-                .create(CreateImageRequest {
-                    prompt: prompt.clone(),
-                    n: Some(n.min(10)),
-                    size: Some(async_openai::types::ImageSize::S1024x1024),
-                    response_format: Some(async_openai::types::ResponseFormat::B64Json),
-                    user: None,
-                })
-                .await?;
-
-            n -= n.min(10);
-
-            resps.push(resp);
+        let batches = image_batches(total, concurrency);
+        let mut decoded = Vec::default();
+
+        // The first batch is sent on its own to settle on a prompt that gets through before the
+        // rest are sent with it
+        let mut current_prompt = prompt.clone();
+        if let Some(&n) = batches.first() {
+            let (resp, accepted_prompt) =
+                create_images_with_retries(&img_gen, &prompt, style, n, &size, budget).await?;
+            current_prompt = accepted_prompt;
+
+            decoded.extend(decode_images(resp, n, &client).await);
+        }
+
+        // `buffered` keeps the batches in order however they finish, so the slots line up
+        let current_prompt = current_prompt.as_str();
+        let img_gen = &img_gen;
+        let size = &size;
+        let rest = stream::iter(batches.into_iter().skip(1))
+            .map(|n| async move {
+                let mut attempt = 0;
+
+                // Only the failed batch is retried, the other batches are kept
+                loop {
+                    let prompt = styled_prompt(current_prompt, style);
+                    match create_images(img_gen, &prompt, n, size).await {
+                        Ok(resp) => break anyhow::Ok((n, Some(resp))),
+                        Err(err) if attempt < Self::MAX_BATCH_ATTEMPTS => {
+                            budget.spend()?;
+                            attempt += 1;
+                            println!("Failed to generate {n} images, retrying: {err:?}");
+                        }
+                        Err(err) => {
+                            println!(
+                                "Giving up on {n} images, they will use the fallback: {err:?}"
+                            );
+                            break Ok((n, None));
+                        }
+                    }
+                }
+            })
+            .buffered(concurrency)
+            .collect::<Vec<_>>()
+            .await;
+
+        for batch in rest {
+            match batch? {
+                (n, Some(resp)) => decoded.extend(decode_images(resp, n, &client).await),
+                (n, None) => decoded.extend((0..n).map(|_| None)),
+            }
         }
 
-        let images = resps
+        // Slots without a usable image show the fallback so the video can still be made
+        let images = decoded
             .into_iter()
-            .map(|resp| resp.data)
-            .flat_map(|images| {
-                images.into_iter().map(|img| {
-                    let ImageData::B64Json(data) = img.as_ref() else {
-                    panic!("Got response in wrong format");
+            .zip(slots)
+            .map(|(img, frame)| {
+                let handle = match img {
+                    Some(img) => ui.add(img),
+                    None => fallback,
                 };
 
-                    let data = base64::prelude::BASE64_STANDARD
-                        .decode(data.as_bytes())
-                        .unwrap();
-
-                    image::load_from_memory(&data).unwrap().to_rgba8()
-                })
+                (frame, handle)
             })
-            .enumerate()
-            .map(|(i, img)| (5 * frame_rate + frame_step * i as u32, ui.add(img)))
             .collect();
 
         Ok(ImageManager { images })
     }
+
+    /// Generates one image per beat, shown from the frame that beat starts on. A beat whose image
+    /// can't be generated shows `fallback` instead. The beats decide when and how many images
+    /// there are, so only the style and size of `schedule` are used.
+    pub async fn from_beats(
+        prompts: Vec<String>,
+        start_frames: &[u32],
+        openai: &async_openai::Client<OpenAIConfig>,
+        schedule: &ImageSchedule,
+        fallback: ImageHandle,
+        ui: &mut VideoUI,
+        budget: &RetryBudget,
+    ) -> anyhow::Result<Self> {
+        println!("Generating {} images", prompts.len());
+
+        let img_gen = async_openai::Images::new(openai);
+        let mut images = Vec::default();
+
+        let style = schedule.style.as_deref();
+        for (prompt, start_frame) in prompts.into_iter().zip(start_frames) {
+            let created =
+                create_images_with_retries(&img_gen, &prompt, style, 1, &schedule.size, budget);
+            let img = match created.await {
+                Ok((resp, _)) => decode_images(resp, 1, &schedule.client)
+                    .await
+                    .pop()
+                    .flatten(),
+                Err(err) if err.is::<BudgetExhausted>() => return Err(err),
+                Err(err) => {
+                    println!("Giving up on the image for a beat, using the fallback: {err:?}");
+                    None
+                }
+            };
+
+            let handle = match img {
+                Some(img) => ui.add(img),
+                None => fallback,
+            };
+            images.push((*start_frame, handle));
+        }
+
+        Ok(ImageManager { images })
+    }
+
+    /// Moves every image to the same point in a video that is `to_frames` long instead of
+    /// `from_frames`.
+    pub fn retimed(mut self, from_frames: u32, to_frames: u32) -> Self {
+        for (frame, _) in &mut self.images {
+            *frame = (*frame as u64 * to_frames as u64 / from_frames.max(1) as u64) as u32;
+        }
+
+        self
+    }
+
+    /// The image shown first, if any were generated.
+    pub fn first_image(&self) -> Option<ImageHandle> {
+        self.images.first().map(|(_, handle)| *handle)
+    }
+}
+
+/// The frame each image is shown from. The images are spread evenly over everything after the
+/// first 5 seconds, by default one every 5 seconds, so a video shorter than that gets none unless
+/// `count` asks for some. Those are spread over the whole video instead.
+fn image_slots(frame_rate: u32, duration: Duration, count: Option<u8>) -> Vec<u32> {
+    let intro = Duration::from_secs(5);
+    let (intro, total) = match duration
+        .checked_sub(intro)
+        .filter(|window| !window.is_zero())
+    {
+        Some(window) => (intro, count.unwrap_or((window.as_secs_f64() / 5.0) as u8)),
+        None => (Duration::ZERO, count.unwrap_or(0)),
+    };
+    let window = (duration - intro).as_secs_f64();
+    if total == 0 {
+        return Vec::default();
+    }
+
+    let start = (intro.as_secs_f64() * frame_rate as f64) as u32;
+    let frame_step = (window * frame_rate as f64 / total as f64) as u32;
+
+    (0..total as u32).map(|i| start + frame_step * i).collect()
+}
+
+/// How many images each request asks for, in order. With `concurrency` above 1 the first request
+/// only asks for one image, since it is sent on its own to find a prompt that gets through, and
+/// the rest are split over at least `concurrency` requests so they all go out at once. Otherwise
+/// the images are requested as few at a time as the API allows.
+fn image_batches(total: u8, concurrency: usize) -> Vec<u8> {
+    let max = ImageManager::MAX_BATCH_SIZE as usize;
+    let split_evenly = |images: usize, requests: usize| {
+        (0..requests)
+            .map(move |idx| (images / requests + usize::from(idx < images % requests)) as u8)
+    };
+
+    let total = total as usize;
+    if concurrency < 2 || total < 2 {
+        return split_evenly(total, total.div_ceil(max)).collect();
+    }
+
+    let rest = total - 1;
+    let requests = rest.div_ceil(max).max(concurrency.min(rest));
+    std::iter::once(1)
+        .chain(split_evenly(rest, requests))
+        .collect()
+}
+
+async fn create_images(
+    img_gen: &async_openai::Images<'_, OpenAIConfig>,
+    prompt: &str,
+    n: u8,
+    size: &ImageSize,
+) -> Result<ImageResponse, OpenAIError> {
+    img_gen
+        .create(CreateImageRequest {
+            prompt: prompt.to_owned(),
+            n: Some(n),
+            size: Some(size.clone()),
+            response_format: Some(async_openai::types::ResponseFormat::B64Json),
+            user: None,
+        })
+        .await
+}
+
+/// Requests `n` images, simplifying the prompt when the safety system rejects it and retrying other
+/// errors up to [`ImageManager::MAX_BATCH_ATTEMPTS`] times, each retry spending from `budget`.
+/// Returns the images with the prompt that got through.
+async fn create_images_with_retries(
+    img_gen: &async_openai::Images<'_, OpenAIConfig>,
+    prompt: &str,
+    style: Option<&str>,
+    n: u8,
+    size: &ImageSize,
+    budget: &RetryBudget,
+) -> anyhow::Result<(ImageResponse, String)> {
+    // Simplifying the prompt is a lot cheaper than having the caller write a new description, so
+    // try that first when the safety system rejects it
+    let mut current_prompt = prompt.to_owned();
+    let mut simplify_level = 0;
+    let mut attempt = 0;
+
+    loop {
+        match create_images(img_gen, &styled_prompt(&current_prompt, style), n, size).await {
+            Ok(resp) => return Ok((resp, current_prompt)),
+            Err(err) if is_policy_rejection(&err) => {
+                budget.spend()?;
+                simplify_level += 1;
+                let Some(simplified) = simplify_prompt(prompt, simplify_level) else {
+                    return Err(err.into());
+                };
+
+                println!("Image prompt was rejected, retrying with: {simplified}");
+                current_prompt = simplified;
+            }
+            Err(err) if attempt < ImageManager::MAX_BATCH_ATTEMPTS => {
+                budget.spend()?;
+                attempt += 1;
+                println!("Failed to generate {n} images, retrying: {err:?}");
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+/// Decodes a batch of `n` images, with `None` for every image that is missing or couldn't be
+/// decoded.
+async fn decode_images(
+    resp: ImageResponse,
+    n: u8,
+    client: &ClientWithMiddleware,
+) -> Vec<Option<RgbaImage>> {
+    let mut decoded = Vec::default();
+    for img in resp.data {
+        decoded.push(match decode_image(&img, client).await {
+            Ok(img) => Some(img),
+            Err(err) => {
+                println!("Failed to decode a generated image, using the fallback: {err:?}");
+                None
+            }
+        });
+    }
+
+    decoded.resize_with(n as usize, || None);
+    decoded
+}
+
+// The longest prompt the image endpoint takes
+const MAX_PROMPT_CHARS: usize = 1000;
+
+/// `prompt` with `style` added to the end, cutting the prompt short rather than the style if the
+/// two are too long together.
+fn styled_prompt(prompt: &str, style: Option<&str>) -> String {
+    let Some(style) = style.map(str::trim).filter(|style| !style.is_empty()) else {
+        return prompt.to_owned();
+    };
+
+    let suffix = format!(". Style: {style}");
+    let prompt = prompt.trim_end().trim_end_matches('.');
+    let max_chars = MAX_PROMPT_CHARS.saturating_sub(suffix.chars().count());
+
+    prompt.chars().take(max_chars).collect::<String>() + &suffix
+}
+
+fn is_policy_rejection(err: &OpenAIError) -> bool {
+    match err {
+        OpenAIError::ApiError(err) => {
+            err.code.as_ref().and_then(|code| code.as_str()) == Some("content_policy_violation")
+                || err.message.contains("safety system")
+        }
+        _ => false,
+    }
+}
+
+// Words that tend to get image prompts rejected, on top of what the description is already
+// censored for
+const REDACTED_WORDS: &[&str] = &[
+    "blood",
+    "bloody",
+    "gore",
+    "corpse",
+    "corpses",
+    "dead",
+    "death",
+    "flesh",
+    "decayed",
+    "rotting",
+    "mutilated",
+    "violent",
+    "violence",
+    "kill",
+    "killing",
+    "wound",
+    "wounds",
+    "skin",
+    "naked",
+    "bones",
+    "skull",
+    "teeth",
+    "eyes",
+    "horrific",
+    "grotesque",
+    "disturbing",
+    "terrifying",
+];
+
+/// Returns a tamer version of `prompt`, `level` being how hard it tries. `None` once there is
+/// nothing left to simplify.
+fn simplify_prompt(prompt: &str, level: u32) -> Option<String> {
+    let redacted = prompt
+        .split_whitespace()
+        .filter(|word| {
+            let bare = word
+                .trim_matches(|ch: char| !ch.is_alphanumeric())
+                .to_ascii_lowercase();
+            !REDACTED_WORDS.contains(&bare.as_str()) && !word.contains('█') && !word.contains('*')
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    match level {
+        1 => Some(redacted),
+        // Only keep the first sentence, which is usually the overall shape of the thing
+        2 => Some(
+            redacted
+                .split_inclusive(['.', '!', '?'])
+                .next()
+                .unwrap_or(&redacted)
+                .trim()
+                .to_owned(),
+        ),
+        _ => None,
+    }
+}
+
+/// Decodes a generated image, downloading it first if the API sent a URL instead of the image.
+async fn decode_image(img: &ImageData, client: &ClientWithMiddleware) -> anyhow::Result<RgbaImage> {
+    let data = match img {
+        ImageData::B64Json(data) => base64::prelude::BASE64_STANDARD.decode(data.as_bytes())?,
+        ImageData::Url(url) => client
+            .get(url.as_str())
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?
+            .to_vec(),
+    };
+
+    Ok(image::load_from_memory(&data)?.to_rgba8())
 }
 
 impl UiUpdater for ImageManager {
     fn update(&mut self, frame_idx: u32, ui: &mut VideoUI) {
         if let Some((_, new_img)) = self.images.iter().find(|(frame, _)| *frame == frame_idx) {
-            if let Node::Image(img) = &mut ui.children[1].node {
+            if let Node::Image { image: img, .. } = &mut ui.children[1].node {
                 *img = *new_img;
+                ui.mark_dirty();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_count_is_spread_evenly_whatever_the_duration() {
+        for secs in [25, 65, 300] {
+            let slots = image_slots(30, Duration::from_secs(secs), Some(4));
+            let step = (secs - 5) as u32 * 30 / 4;
+
+            assert_eq!(slots, (0..4).map(|i| 150 + step * i).collect::<Vec<_>>());
+        }
+
+        // One every 5 seconds after the first 5 without a count
+        assert_eq!(image_slots(30, Duration::from_secs(25), None).len(), 4);
+    }
+
+    #[test]
+    fn videos_shorter_than_the_intro_get_no_images_by_default() {
+        assert!(image_slots(30, Duration::from_secs(3), None).is_empty());
+
+        let slots = image_slots(30, Duration::from_secs(3), Some(2));
+        assert_eq!(slots, [0, 45]);
+    }
+
+    /// A 1x1 PNG filled with `color`, base64 encoded like the image API sends it.
+    fn b64_png(color: image::Rgba<u8>) -> String {
+        let mut png = std::io::Cursor::new(Vec::default());
+        RgbaImage::from_pixel(1, 1, color)
+            .write_to(&mut png, image::ImageOutputFormat::Png)
+            .unwrap();
+
+        base64::prelude::BASE64_STANDARD.encode(png.into_inner())
+    }
+
+    /// Answers every image request with images colored by the order it came in, the ones that came
+    /// in later answered sooner. Records how many images each request asked for.
+    struct OutOfOrder(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl wiremock::Respond for OutOfOrder {
+        fn respond(&self, request: &wiremock::Request) -> wiremock::ResponseTemplate {
+            let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+            let n = body["n"].as_u64().unwrap() as u8;
+
+            let mut sizes = self.0.lock().unwrap();
+            let idx = sizes.len() as u8;
+            sizes.push(n);
+
+            let image = serde_json::json!({ "b64_json": b64_png(image::Rgba([idx, 0, 0, 255])) });
+            wiremock::ResponseTemplate::new(200)
+                .set_body_json(serde_json::json!({ "created": 0, "data": vec![image; n as usize] }))
+                .set_delay(Duration::from_millis(400 - 100 * idx as u64))
+        }
+    }
+
+    #[test]
+    fn concurrency_splits_the_images_over_more_requests() {
+        assert_eq!(image_batches(6, 1), [6]);
+        assert_eq!(image_batches(23, 1), [8, 8, 7]);
+        assert_eq!(image_batches(6, 3), [1, 2, 2, 1]);
+        assert_eq!(image_batches(23, 2), [1, 8, 7, 7]);
+        assert_eq!(image_batches(1, 4), [1]);
+        assert!(image_batches(0, 4).is_empty());
+    }
+
+    #[tokio::test]
+    async fn concurrent_images_come_back_in_schedule_order() {
+        use wiremock::{
+            matchers::{method, path},
+            Mock, MockServer,
+        };
+
+        let server = MockServer::start().await;
+        let sizes = std::sync::Arc::new(std::sync::Mutex::new(Vec::default()));
+        Mock::given(method("POST"))
+            .and(path("/images/generations"))
+            .respond_with(OutOfOrder(sizes.clone()))
+            .expect(4)
+            .mount(&server)
+            .await;
+
+        let openai = async_openai::Client::with_config(
+            OpenAIConfig::new()
+                .with_api_key("test")
+                .with_api_base(server.uri()),
+        );
+        let mut ui = VideoUI::default();
+        let fallback = ui.add(RgbaImage::new(1, 1));
+
+        let img_mgr = ImageManager::new(
+            "A statue".into(),
+            &openai,
+            ImageSchedule::new(30, Duration::from_secs(35))
+                .with_count(Some(6))
+                .with_concurrency(3),
+            fallback,
+            &mut ui,
+            &RetryBudget::new(0),
+        )
+        .await
+        .unwrap();
+
+        // Each request's images stay together and in the slots of its batch, even though the
+        // later requests finished first
+        let requests: Vec<u8> = img_mgr
+            .images
+            .iter()
+            .map(|(_, handle)| ui.image(handle).get_pixel(0, 0).0[0])
+            .collect();
+        let sizes = sizes.lock().unwrap();
+        let mut batches: Vec<(u8, u8)> = Vec::default();
+        for request in requests {
+            match batches.last_mut() {
+                Some((last, count)) if *last == request => *count += 1,
+                _ => batches.push((request, 1)),
             }
         }
+
+        assert_eq!(
+            batches.iter().map(|(_, count)| *count).collect::<Vec<_>>(),
+            [1, 2, 2, 1]
+        );
+        for (request, count) in batches {
+            assert_eq!(sizes[request as usize], count);
+        }
+        assert_eq!(
+            img_mgr
+                .images
+                .iter()
+                .map(|(frame, _)| *frame)
+                .collect::<Vec<_>>(),
+            image_slots(30, Duration::from_secs(35), Some(6))
+        );
+    }
+
+    #[tokio::test]
+    async fn images_sent_as_urls_are_downloaded() {
+        use wiremock::{
+            matchers::{method, path},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let server = MockServer::start().await;
+        let png = base64::prelude::BASE64_STANDARD
+            .decode(b64_png(image::Rgba([0, 255, 0, 255])))
+            .unwrap();
+        Mock::given(method("GET"))
+            .and(path("/generated.png"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(png))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new()).build();
+        let url = ImageData::Url(format!("{}/generated.png", server.uri()).into());
+        let img = decode_image(&url, &client).await.unwrap();
+
+        assert_eq!(*img.get_pixel(0, 0), image::Rgba([0, 255, 0, 255]));
+    }
+
+    #[test]
+    fn portrait_canvas_does_not_get_a_landscape_image() {
+        for canvas in [UVec2::new(1080, 1920), UVec2::new(720, 1280)] {
+            let size = image_size_for(canvas);
+            let (_, dimensions) = IMAGE_SIZES.iter().find(|(s, _)| *s == size).unwrap();
+
+            assert!(dimensions.x <= dimensions.y, "{canvas} got {size:?}");
+            assert_eq!(size, ImageSize::S1024x1024);
+        }
+
+        assert_eq!(image_size_for(UVec2::ZERO), ImageSize::S1024x1024);
+    }
+
+    #[test]
+    fn prompts_are_simplified_step_by_step() {
+        let prompt = "A pale statue in a hallway, covered in blood. It has ██████ eyes.";
+
+        assert_eq!(
+            simplify_prompt(prompt, 1).unwrap(),
+            "A pale statue in a hallway, covered in It has"
+        );
+
+        let prompt = "A pale statue in a hallway. It is covered in blood.";
+        assert_eq!(
+            simplify_prompt(prompt, 2).unwrap(),
+            "A pale statue in a hallway."
+        );
+        assert_eq!(simplify_prompt(prompt, 3), None);
+    }
+
+    #[tokio::test]
+    async fn a_rejected_prompt_is_simplified_until_it_gets_through() {
+        use wiremock::{
+            matchers::{body_string_contains, method, path},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/images/generations"))
+            .and(body_string_contains("blood"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
+                "error": {
+                    "message": "Your request was rejected as a result of our safety system.",
+                    "type": "invalid_request_error",
+                    "param": null,
+                    "code": "content_policy_violation"
+                }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/images/generations"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "created": 0,
+                "data": [{ "b64_json": b64_png(image::Rgba([0, 0, 0, 255])) }]
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let openai = async_openai::Client::with_config(
+            OpenAIConfig::new()
+                .with_api_key("test")
+                .with_api_base(server.uri()),
+        );
+        let img_gen = async_openai::Images::new(&openai);
+
+        let (resp, prompt) = create_images_with_retries(
+            &img_gen,
+            "A statue covered in blood",
+            None,
+            1,
+            &ImageSize::S256x256,
+            &RetryBudget::new(1),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(prompt, "A statue covered in");
+        assert_eq!(resp.data.len(), 1);
+    }
+
+    #[test]
+    fn the_style_is_appended_to_the_prompt() {
+        assert_eq!(
+            styled_prompt("A statue.", Some("dark cinematic, desaturated")),
+            "A statue. Style: dark cinematic, desaturated"
+        );
+        assert_eq!(styled_prompt("A statue.", Some("  ")), "A statue.");
+        assert_eq!(styled_prompt("A statue.", None), "A statue.");
+
+        // Long prompts are cut short to make room for the style
+        let styled = styled_prompt(&"statue ".repeat(200), Some("found footage"));
+        assert_eq!(styled.chars().count(), MAX_PROMPT_CHARS);
+        assert!(styled.ends_with(". Style: found footage"));
+    }
+
+    #[tokio::test]
+    async fn every_image_request_has_the_style() {
+        use wiremock::{
+            matchers::{body_string_contains, method, path},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/images/generations"))
+            .and(body_string_contains("A statue. Style: dark cinematic"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "created": 0,
+                "data": [{ "b64_json": b64_png(image::Rgba([0, 0, 0, 255])) }]
+            })))
+            .expect(3)
+            .mount(&server)
+            .await;
+
+        let openai = async_openai::Client::with_config(
+            OpenAIConfig::new()
+                .with_api_key("test")
+                .with_api_base(server.uri()),
+        );
+        let mut ui = VideoUI::default();
+        let fallback = ui.add(RgbaImage::new(1, 1));
+
+        let img_mgr = ImageManager::new(
+            "A statue".into(),
+            &openai,
+            ImageSchedule::new(30, Duration::from_secs(20))
+                .with_count(Some(3))
+                .with_concurrency(2)
+                .with_style(Some("dark cinematic".into())),
+            fallback,
+            &mut ui,
+            &RetryBudget::new(0),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(img_mgr.images.len(), 3);
     }
 }
@@ -1,14 +1,35 @@
+pub mod animation;
+pub mod easing;
 pub mod image_manager;
+pub mod scroll;
 pub mod subtitle;
 pub mod ui;
 
-use std::time::Duration;
+use std::{
+    hash::Hasher,
+    io::Write,
+    ops::{Range, RangeInclusive},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 
+use anyhow::Context;
 use glam::UVec2;
 use gstreamer::{prelude::*, Caps, ClockTime, ElementFactory, Fraction, Pipeline};
 use gstreamer_app::{AppSrc, AppSrcCallbacks};
 use image::RgbaImage;
 
+use taffy::{
+    prelude::{Rect, Size},
+    style::{Dimension, LengthPercentageAuto, Style},
+};
+
+use crate::{manifest::Chapter, scp::Classification};
+
 use self::ui::VideoUI;
 
 pub struct Mp3(Vec<u8>);
@@ -23,25 +44,203 @@ impl Mp3 {
     }
 }
 
+/// Narration written to the temp dir for the encoder to read, deleted again once dropped unless
+/// it is kept.
+pub struct TempAudio {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl TempAudio {
+    pub fn write(name: &str, contents: &[u8]) -> std::io::Result<Self> {
+        let mut path = std::env::temp_dir();
+        path.push(format!("{name}-output.mp3"));
+
+        std::fs::write(&path, contents)?;
+
+        Ok(TempAudio { path, keep: false })
+    }
+
+    pub fn with_keep(mut self, keep: bool) -> Self {
+        self.keep = keep;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempAudio {
+    fn drop(&mut self) {
+        if self.keep {
+            return;
+        }
+
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            println!("Failed to remove {}: {err}", self.path.display());
+        }
+    }
+}
+
 pub struct VideoFrameIter {
     current_frame_idx: u32,
     size: UVec2,
     frame_rate: u32,
     total_frames: u32,
+    motion_blur_samples: usize,
+    /// The last rendered frame, only the parts that changed are drawn over it
+    buffer: Option<RgbaImage>,
+    /// The output frame when motion blur is on, averaged from `blur_sums`
+    blurred: Option<RgbaImage>,
+    blur_sums: Vec<u32>,
+    faststart: bool,
+    chapters: Vec<Chapter>,
+    music: Option<Music>,
+    info_overlay: Option<InfoOverlay>,
+    frame_hashes: Option<Box<dyn FnMut(u32, u64) + Send + Sync>>,
     pub ui: VideoUI,
     pub updaters: Vec<Box<dyn ui::UiUpdater>>,
 }
 
+/// A line of small text in the top left corner of every frame.
+#[derive(Clone)]
+struct InfoOverlay {
+    font: ui::FontHandle,
+    label: Option<String>,
+    show_time: bool,
+}
+
 impl VideoFrameIter {
-    pub fn new(size: UVec2, frame_rate: u32, duration: Duration) -> Self {
-        VideoFrameIter {
+    /// The frame rates a video can be made at.
+    pub const FRAME_RATES: RangeInclusive<u32> = 1..=120;
+
+    pub fn new(size: UVec2, frame_rate: u32, duration: Duration) -> anyhow::Result<Self> {
+        if !Self::FRAME_RATES.contains(&frame_rate) {
+            anyhow::bail!(
+                "{frame_rate} fps is not supported, it has to be between {} and {}",
+                Self::FRAME_RATES.start(),
+                Self::FRAME_RATES.end()
+            );
+        }
+
+        Ok(VideoFrameIter {
             current_frame_idx: 0,
             size,
             frame_rate,
             total_frames: (duration.as_secs_f64() * frame_rate as f64).round() as u32,
+            motion_blur_samples: 1,
+            buffer: None,
+            blurred: None,
+            blur_sums: Vec::default(),
+            faststart: true,
+            chapters: Vec::default(),
+            music: None,
+            info_overlay: None,
+            frame_hashes: None,
             ui: VideoUI::default(),
             updaters: Vec::default(),
+        })
+    }
+
+    /// Cuts the video off after `max_duration`, the audio is cut off with it when encoding.
+    pub fn with_max_duration(mut self, max_duration: Duration) -> Self {
+        let max_frames = (max_duration.as_secs_f64() * self.frame_rate as f64).round() as u32;
+
+        if self.total_frames > max_frames {
+            println!(
+                "Warning: video is {} frames long, clamping it to {max_frames} frames ({max_duration:?})",
+                self.total_frames
+            );
+            self.total_frames = max_frames;
         }
+
+        self
+    }
+
+    /// Replaces the frame count computed from the duration, for when it has to line up exactly with
+    /// something else like [`VideoFrameIter::frames_for_samples`].
+    pub fn with_total_frames(mut self, total_frames: u32) -> Self {
+        self.total_frames = total_frames;
+        self
+    }
+
+    /// The number of frames that covers `samples` audio samples at `sample_rate` without cutting
+    /// off the last partial frame.
+    pub fn frames_for_samples(&self, samples: u64, sample_rate: u32) -> u32 {
+        (samples as f64 * self.frame_rate as f64 / sample_rate as f64).ceil() as u32
+    }
+
+    /// Renders everything that animates at `samples` points spread over each frame and averages
+    /// them, smoothing out movement. Cuts and other changes that happen on a frame stay sharp, 1
+    /// turns blending off.
+    pub fn with_motion_blur(mut self, samples: usize) -> Self {
+        self.motion_blur_samples = samples.max(1);
+        self
+    }
+
+    /// Whether the MP4 index is written at the start of the file so it can be played while it is
+    /// still downloading. On by default.
+    pub fn with_faststart(mut self, faststart: bool) -> Self {
+        self.faststart = faststart;
+        self
+    }
+
+    /// Writes `chapters` next to the encoded video, see [`ffmetadata_chapters`].
+    pub fn with_chapters(mut self, chapters: Vec<Chapter>) -> Self {
+        self.chapters = chapters;
+        self
+    }
+
+    /// Mixes `music` in under the audio when encoding.
+    pub fn with_music(mut self, music: Music) -> Self {
+        self.music = Some(music);
+        self
+    }
+
+    /// Draws `label`, e.g. the SCP designation, and the time into the video as `M:SS` over every
+    /// frame when `show_time` is set.
+    pub fn with_info_overlay(
+        mut self,
+        font: ui::FontHandle,
+        label: Option<String>,
+        show_time: bool,
+    ) -> Self {
+        self.info_overlay = Some(InfoOverlay {
+            font,
+            label,
+            show_time,
+        });
+        self
+    }
+
+    /// Calls `hook` with the index and [`hash_frame`] of every frame as it is rendered, so a run
+    /// can be compared against a recorded one.
+    pub fn with_frame_hashes(mut self, hook: impl FnMut(u32, u64) + Send + Sync + 'static) -> Self {
+        self.frame_hashes = Some(Box::new(hook));
+        self
+    }
+
+    /// The text of the info overlay on frame `idx`, `None` without an overlay.
+    pub fn info_overlay_text(&self, idx: u32) -> Option<String> {
+        let overlay = self.info_overlay.as_ref()?;
+
+        let secs = idx / self.frame_rate;
+        let time = overlay
+            .show_time
+            .then(|| format!("{}:{:02}", secs / 60, secs % 60));
+
+        Some(
+            [overlay.label.clone(), time]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>()
+                .join(" "),
+        )
+    }
+
+    pub fn size(&self) -> UVec2 {
+        self.size
     }
 
     pub fn frame_rate(&self) -> u32 {
@@ -52,36 +251,121 @@ impl VideoFrameIter {
         self.total_frames
     }
 
+    /// When frame `idx` starts. Rounded per frame so rates that don't divide a second evenly don't
+    /// drift.
+    pub fn frame_time(&self, idx: u32) -> ClockTime {
+        ClockTime::from_nseconds(idx as u64 * ClockTime::SECOND.nseconds() / self.frame_rate as u64)
+    }
+
     pub fn duration(&self) -> Duration {
         Duration::from_secs((self.total_frames / self.frame_rate) as u64)
     }
 
-    pub async fn encode_h264(mut self, audio_in: &str, video_out: &str) {
+    /// Writes every frame as raw RGBA to `out` instead of encoding it, for piping into an external
+    /// encoder. The frames are preceded by a single `RGBA <width> <height> <frame rate>\n` line.
+    pub fn write_raw(mut self, mut out: impl Write) -> std::io::Result<()> {
+        writeln!(
+            out,
+            "RGBA {} {} {}",
+            self.size.x, self.size.y, self.frame_rate
+        )?;
+
+        while let Some((_, frame)) = self.next_frame() {
+            out.write_all(frame.as_raw())?;
+        }
+
+        out.flush()
+    }
+
+    /// Encodes the video as H.264 in an MP4, see [`VideoFrameIter::encode`].
+    pub async fn encode_h264(
+        self,
+        audio_in: Option<&str>,
+        video_out: &str,
+        config: &EncodeConfig,
+        on_progress: Option<Box<dyn Fn(u32, u32) + Send>>,
+    ) -> anyhow::Result<()> {
+        self.encode(VideoCodec::H264, audio_in, video_out, config, on_progress)
+            .await
+    }
+
+    /// Encodes the video with the audio from `audio_in`, or with silence when there is none.
+    /// `on_progress` is called with the number of frames handed to the encoder so far and the
+    /// total after every frame.
+    pub async fn encode(
+        mut self,
+        codec: VideoCodec,
+        audio_in: Option<&str>,
+        video_out: &str,
+        config: &EncodeConfig,
+        on_progress: Option<Box<dyn Fn(u32, u32) + Send>>,
+    ) -> anyhow::Result<()> {
         // Initialize GStreamer
-        gstreamer::init().unwrap();
+        gstreamer::init().context("Failed to initialize GStreamer")?;
+
+        let faststart = self.faststart;
+        let total_frames = self.total_frames;
+        let duration_secs = self.total_frames as f64 / self.frame_rate as f64;
+        let chapters = std::mem::take(&mut self.chapters);
+        let music = self.music.take();
+        let audio_end = self.frame_time(total_frames);
 
         // Create the pipeline
         let pipeline = Pipeline::new(Some("image-sequence"));
 
         // Create the appsrc element
-        let appsrc = ElementFactory::make("appsrc").build().unwrap();
+        let appsrc = make_element("appsrc")?;
 
         // Create the video convert element
-        let video_convert = ElementFactory::make("videoconvert").build().unwrap();
+        let video_convert = make_element("videoconvert")?;
 
-        // Create the x264enc element
-        let x264enc = ElementFactory::make("x264enc").build().unwrap();
+        // Create the video encoder element
+        let video_encoder = match codec {
+            VideoCodec::H264 => {
+                let x264enc = make_element("x264enc")?;
+                // The quantizer is only used as a quality target in the constant quality pass
+                if let Some(crf) = config.crf {
+                    x264enc.set_property_from_str("pass", "qual");
+                    x264enc.set_property("quantizer", crf);
+                }
+                if let Some(preset) = &config.preset {
+                    x264enc.set_property_from_str("speed-preset", preset);
+                }
+                if let Some(bitrate_kbps) = config.bitrate_kbps {
+                    x264enc.set_property("bitrate", bitrate_kbps);
+                }
+                x264enc
+            }
+            VideoCodec::Vp9 => {
+                let vp9enc = make_element("vp9enc")?;
+                // vp9enc's cq-level runs to 63 like libvpx's, so the CRF carries over as is
+                if let Some(crf) = config.crf {
+                    vp9enc.set_property_from_str("end-usage", "cq");
+                    vp9enc.set_property("cq-level", crf as i32);
+                }
+                if config.preset.is_some() {
+                    println!("Warning: vp9enc has no speed presets, ignoring HOTI_PRESET");
+                }
+                if let Some(bitrate_kbps) = config.bitrate_kbps {
+                    vp9enc.set_property("target-bitrate", bitrate_kbps as i32 * 1000);
+                }
+                vp9enc
+            }
+        };
 
         // Create the queue element
-        let video_queue = ElementFactory::make("queue").build().unwrap();
+        let video_queue = make_element("queue")?;
 
         // Add and link the elements
         pipeline
-            .add_many(&[&appsrc, &video_convert, &x264enc, &video_queue])
-            .unwrap();
-        gstreamer::Element::link_many(&[&appsrc, &video_convert, &x264enc, &video_queue]).unwrap();
+            .add_many(&[&appsrc, &video_convert, &video_encoder, &video_queue])
+            .context("Failed to add the video elements to the pipeline")?;
+        gstreamer::Element::link_many(&[&appsrc, &video_convert, &video_encoder, &video_queue])
+            .context("Failed to link appsrc, videoconvert, the video encoder and queue")?;
 
-        let appsrc = appsrc.downcast::<AppSrc>().unwrap();
+        let appsrc = appsrc
+            .downcast::<AppSrc>()
+            .map_err(|_| anyhow::anyhow!("The appsrc element is not an AppSrc"))?;
         appsrc.set_format(gstreamer::Format::Time);
         appsrc.set_caps(Some(
             &Caps::builder("video/x-raw")
@@ -101,12 +385,18 @@ impl VideoFrameIter {
                             let mut buffer = gstreamer::Buffer::from_mut_slice(frame.into_raw());
 
                             // Set the duration of the buffer
-                            let duration = ClockTime::from_seconds(1) / self.frame_rate as u64;
+                            let pts = self.frame_time(idx);
                             let buffer_ref = buffer.get_mut().unwrap();
-                            buffer_ref.set_duration(duration);
-                            buffer_ref.set_pts(duration * idx as u64);
+                            buffer_ref.set_duration(ClockTime::from_nseconds(
+                                self.frame_time(idx + 1).nseconds() - pts.nseconds(),
+                            ));
+                            buffer_ref.set_pts(pts);
 
                             appsrc.push_buffer(buffer).unwrap();
+
+                            if let Some(on_progress) = &on_progress {
+                                on_progress(idx + 1, total_frames);
+                            }
                         }
                         None => {
                             appsrc.end_of_stream().unwrap();
@@ -116,68 +406,197 @@ impl VideoFrameIter {
                 .build(),
         );
 
-        let audio_filesrc = ElementFactory::make("filesrc").build().unwrap();
-        audio_filesrc.set_property("location", audio_in);
-
-        // Create the decodebin element
-        let audio_decodebin = ElementFactory::make("decodebin").build().unwrap();
-
         // Create the audio convert element
-        let audio_convert = ElementFactory::make("audioconvert").build().unwrap();
+        let audio_convert = make_element("audioconvert")?;
 
         // Create the queue for audio
-        let audio_queue = ElementFactory::make("queue").build().unwrap();
+        let audio_queue = make_element("queue")?;
 
         pipeline
-            .add_many(&[
-                &audio_filesrc,
-                &audio_decodebin,
-                &audio_convert,
-                &audio_queue,
-            ])
-            .unwrap();
-        gstreamer::Element::link(&audio_filesrc, &audio_decodebin).unwrap();
-        let audio_convert_weak = audio_convert.downgrade();
-        audio_decodebin.connect_pad_added(move |_, src_pad| {
-            let sink_pad = match audio_convert_weak.upgrade() {
-                None => return,
-                Some(s) => s.static_pad("sink").expect("cannot get sink pad from sink"),
-            };
+            .add_many(&[&audio_convert, &audio_queue])
+            .context("Failed to add the audio elements to the pipeline")?;
 
-            src_pad
-                .link(&sink_pad)
-                .expect("Cannot link the decodebin source pad to the audioconvert sink pad");
-        });
-        gstreamer::Element::link(&audio_convert, &audio_queue).unwrap();
+        if let Some(audio_in) = audio_in {
+            let audio_filesrc = make_element("filesrc")?;
+            audio_filesrc.set_property("location", audio_in);
+
+            // Create the decodebin element
+            let audio_decodebin = make_element("decodebin")?;
+
+            pipeline
+                .add_many(&[&audio_filesrc, &audio_decodebin])
+                .context("Failed to add the audio file elements to the pipeline")?;
+            gstreamer::Element::link(&audio_filesrc, &audio_decodebin)
+                .context("Failed to link filesrc and decodebin")?;
+            let audio_convert_weak = audio_convert.downgrade();
+            audio_decodebin.connect_pad_added(move |_, src_pad| {
+                let sink_pad = match audio_convert_weak.upgrade() {
+                    None => return,
+                    Some(s) => s.static_pad("sink").expect("cannot get sink pad from sink"),
+                };
+
+                // The unlinked pad stops the pipeline with an error on the bus, which is reported
+                // with more context than a panic on a GStreamer thread
+                if let Err(err) = src_pad.link(&sink_pad) {
+                    println!(
+                        "Cannot link the decodebin source pad to the audioconvert sink pad: {err:?}"
+                    );
+                }
+            });
+        } else {
+            // audiotestsrc never ends on its own, so stop it after enough buffers to cover the
+            // video at its default 44.1kHz and 1024 samples per buffer
+            let silence = make_element("audiotestsrc")?;
+            silence.set_property_from_str("wave", "silence");
+            silence.set_property(
+                "num-buffers",
+                (duration_secs * 44100.0 / 1024.0).ceil() as i32,
+            );
+
+            pipeline
+                .add_many(&[&silence])
+                .context("Failed to add audiotestsrc to the pipeline")?;
+            gstreamer::Element::link(&silence, &audio_convert)
+                .context("Failed to link audiotestsrc and audioconvert")?;
+        }
+        // The audio can run past the last frame, e.g. after `with_max_duration`, so it is ended
+        // there too
+        end_audio_at(&audio_convert, audio_end)?;
+
+        match music {
+            Some(music) => {
+                let mixer = make_element("audiomixer")?;
+                let music_filesrc = make_element("filesrc")?;
+                music_filesrc.set_property("location", music.path.as_str());
+                let music_decodebin = make_element("decodebin")?;
+                let music_convert = make_element("audioconvert")?;
+                let music_resample = make_element("audioresample")?;
+                let music_volume = make_element("volume")?;
+
+                pipeline
+                    .add_many(&[
+                        &mixer,
+                        &music_filesrc,
+                        &music_decodebin,
+                        &music_convert,
+                        &music_resample,
+                        &music_volume,
+                    ])
+                    .context("Failed to add the music elements to the pipeline")?;
+                gstreamer::Element::link(&music_filesrc, &music_decodebin)
+                    .context("Failed to link the music filesrc and decodebin")?;
+                gstreamer::Element::link_many(&[
+                    &music_convert,
+                    &music_resample,
+                    &music_volume,
+                    &mixer,
+                ])
+                .context(
+                    "Failed to link the music audioconvert, audioresample, volume and audiomixer",
+                )?;
+                gstreamer::Element::link_many(&[&audio_convert, &mixer, &audio_queue])
+                    .context("Failed to link audioconvert, audiomixer and queue")?;
+
+                let music_convert_weak = music_convert.downgrade();
+                music_decodebin.connect_pad_added(move |_, src_pad| {
+                    let sink_pad = match music_convert_weak.upgrade() {
+                        None => return,
+                        Some(s) => s.static_pad("sink").expect("cannot get sink pad from sink"),
+                    };
+
+                    if let Err(err) = src_pad.link(&sink_pad) {
+                        println!("Cannot link the music decodebin to its audioconvert: {err:?}");
+                    }
+                });
+
+                // The volume is set for each buffer as it goes past, which is fine grained enough
+                // for attacks and releases of a few tens of milliseconds
+                let volume_weak = music_volume.downgrade();
+                music_volume
+                    .static_pad("sink")
+                    .context("volume has no sink pad")?
+                    .add_probe(gstreamer::PadProbeType::BUFFER, move |_, info| {
+                        if let (Some(gstreamer::PadProbeData::Buffer(buffer)), Some(volume)) =
+                            (&info.data, volume_weak.upgrade())
+                        {
+                            if let Some(pts) = buffer.pts() {
+                                let secs =
+                                    pts.nseconds() as f64 / ClockTime::SECOND.nseconds() as f64;
+                                volume.set_property("volume", music.gain_at(secs));
+                            }
+                        }
+                        gstreamer::PadProbeReturn::Ok
+                    });
 
-        // Create the h264parse element
-        let h264parse = ElementFactory::make("h264parse").build().unwrap();
+                // audiomixer only ends once every input has, so longer music is cut off too
+                end_audio_at(&music_volume, audio_end)?;
+            }
+            None => {
+                gstreamer::Element::link(&audio_convert, &audio_queue)
+                    .context("Failed to link audioconvert and queue")?;
+            }
+        }
 
-        let resample = ElementFactory::make("audioresample").build().unwrap();
+        let resample = make_element("audioresample")?;
 
-        let avenc_aac = ElementFactory::make("avenc_aac").build().unwrap();
+        let audio_encoder_name = match codec {
+            VideoCodec::H264 => {
+                aac_encoder().context("No AAC encoder is installed, install gst-libav")?
+            }
+            VideoCodec::Vp9 => "vorbisenc",
+        };
+        let audio_encoder = make_element(audio_encoder_name)?;
 
-        // Create the mp4mux element
-        let mp4mux = ElementFactory::make("mp4mux").build().unwrap();
+        // Create the muxer element
+        let (muxer_name, muxer) = match codec {
+            VideoCodec::H264 => {
+                let mp4mux = make_element("mp4mux")?;
+                mp4mux.set_property("faststart", faststart);
+                ("mp4mux", mp4mux)
+            }
+            VideoCodec::Vp9 => ("webmmux", make_element("webmmux")?),
+        };
 
         // Create the filesink element
-        let filesink = ElementFactory::make("filesink").build().unwrap();
+        let filesink = make_element("filesink")?;
         filesink.set_property("location", video_out);
 
         pipeline
-            .add_many(&[&h264parse, &resample, &avenc_aac, &mp4mux, &filesink])
-            .unwrap();
-        gstreamer::Element::link_many(&[&video_queue, &h264parse, &mp4mux, &filesink]).unwrap();
+            .add_many(&[&resample, &audio_encoder, &muxer, &filesink])
+            .context("Failed to add the muxing elements to the pipeline")?;
+        match codec {
+            VideoCodec::H264 => {
+                // Create the h264parse element
+                let h264parse = make_element("h264parse")?;
+                pipeline
+                    .add_many(&[&h264parse])
+                    .context("Failed to add h264parse to the pipeline")?;
+                gstreamer::Element::link_many(&[&video_queue, &h264parse, &muxer, &filesink])
+                    .context("Failed to link queue, h264parse, mp4mux and filesink")?;
+            }
+            VideoCodec::Vp9 => {
+                gstreamer::Element::link_many(&[&video_queue, &muxer, &filesink])
+                    .context("Failed to link queue, webmmux and filesink")?;
+            }
+        }
 
         // Link audio queue and muxer
-        gstreamer::Element::link_many(&[&audio_queue, &resample, &avenc_aac, &mp4mux]).unwrap();
+        gstreamer::Element::link_many(&[&audio_queue, &resample, &audio_encoder, &muxer])
+            .with_context(|| {
+                format!(
+                    "Failed to link queue, audioresample, {audio_encoder_name} and {muxer_name}"
+                )
+            })?;
 
         // Start playing
-        pipeline.set_state(gstreamer::State::Playing).unwrap();
+        pipeline
+            .set_state(gstreamer::State::Playing)
+            .context("Failed to start the pipeline")?;
 
         let mut eof_count = 0;
+        let mut failure = None;
         // Wait until the pipeline finishes
-        let bus = pipeline.bus().unwrap();
+        let bus = pipeline.bus().context("The pipeline has no bus")?;
         for msg in bus.iter_timed(gstreamer::ClockTime::NONE) {
             use gstreamer::MessageView;
 
@@ -189,45 +608,1122 @@ impl VideoFrameIter {
                     }
                 }
                 MessageView::Error(err) => {
-                    println!(
+                    failure = Some(anyhow::anyhow!(
                         "Error from {:?}: {} ({:?})",
                         err.src().map(|s| s.path_string()),
                         err.error(),
                         err.debug()
-                    );
+                    ));
                     break;
                 }
                 _ => {}
             }
         }
 
-        pipeline.set_state(gstreamer::State::Null).unwrap();
+        pipeline
+            .set_state(gstreamer::State::Null)
+            .context("Failed to stop the pipeline")?;
+
+        if let Some(err) = failure {
+            return Err(err.context(format!("Failed to encode {video_out}")));
+        }
+
+        if !chapters.is_empty() {
+            let path = chapters_path(video_out);
+            std::fs::write(&path, ffmetadata_chapters(&chapters, duration_secs))
+                .with_context(|| format!("Failed to write the chapters to {}", path.display()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Splits the video into a preview `divisor` times smaller in each direction and the full
+    /// size video, laid out the same and driven by the same updaters. The preview has to be
+    /// rendered first, motion blur, frame hashes and chapters are left to the full video.
+    pub fn with_preview(mut self, divisor: u32) -> (VideoFrameIter, VideoFrameIter) {
+        let divisor = divisor.max(1);
+        let updaters = Arc::new(Mutex::new(std::mem::take(&mut self.updaters)));
+
+        let preview = VideoFrameIter {
+            current_frame_idx: 0,
+            size: (self.size / divisor).max(UVec2::ONE),
+            frame_rate: self.frame_rate,
+            total_frames: self.total_frames,
+            motion_blur_samples: 1,
+            buffer: None,
+            blurred: None,
+            blur_sums: Vec::default(),
+            faststart: self.faststart,
+            chapters: Vec::default(),
+            music: self.music.clone(),
+            info_overlay: self.info_overlay.clone(),
+            frame_hashes: None,
+            ui: self.ui.clone().with_render_scale(1.0 / divisor as f32),
+            updaters: vec![Box::new(SharedUpdaters(updaters.clone()))],
+        };
+        self.updaters = vec![Box::new(SharedUpdaters(updaters))];
+
+        (preview, self)
+    }
+
+    /// Encodes a preview `divisor` times smaller to [`preview_path`] first, see
+    /// [`VideoFrameIter::with_preview`], and only encodes the full video to `video_out` once
+    /// `approve` accepts the preview at the path it is given. Returns whether it was accepted.
+    pub async fn encode_with_preview(
+        self,
+        divisor: u32,
+        approve: impl FnOnce(&str) -> bool,
+        codec: VideoCodec,
+        audio_in: Option<&str>,
+        video_out: &str,
+        config: &EncodeConfig,
+    ) -> anyhow::Result<bool> {
+        let (preview, full) = self.with_preview(divisor);
+        let preview_out = preview_path(video_out);
+
+        println!(
+            "Encoding a {}x{} preview to {preview_out}",
+            preview.size.x, preview.size.y
+        );
+        preview
+            .encode(codec, audio_in, &preview_out, config, None)
+            .await?;
+
+        if !approve(&preview_out) {
+            println!("The preview {preview_out} wasn't approved, skipping the full video");
+            return Ok(false);
+        }
+
+        full.encode(codec, audio_in, video_out, config, None)
+            .await?;
+
+        Ok(true)
+    }
+
+    /// Renders the next frame into a buffer that is reused for every frame.
+    pub fn next_frame(&mut self) -> Option<(u32, &RgbaImage)> {
+        if self.current_frame_idx >= self.total_frames {
+            return None;
+        }
+
+        for updater in &mut self.updaters {
+            if self.current_frame_idx == 0 {
+                updater.restart();
+            }
+            updater.update(self.current_frame_idx, &mut self.ui);
+        }
+
+        // The overlay is only in the tree while rendering so the indices updaters use for the
+        // other nodes stay the same
+        let overlay = self.info_overlay_text(self.current_frame_idx);
+        if overlay != self.info_overlay_text(self.current_frame_idx.saturating_sub(1)) {
+            self.ui.mark_dirty();
+        }
+        if let (Some(text), Some(info_overlay)) = (overlay.clone(), &self.info_overlay) {
+            self.ui
+                .children
+                .push(info_overlay_node(text, info_overlay.font));
+        }
+
+        let rendered = self.render_to_buffer().and_then(|_| self.blur_motion());
+
+        if overlay.is_some() {
+            self.ui.children.pop();
+        }
+
+        if let Err(err) = rendered {
+            panic!(
+                "Failed to render frame {} due to error: {}",
+                self.current_frame_idx, err
+            );
+        }
+
+        let frame = match self.motion_blur_samples {
+            0 | 1 => self.buffer.as_ref(),
+            _ => self.blurred.as_ref(),
+        }
+        .unwrap();
+
+        if let Some(hook) = &mut self.frame_hashes {
+            hook(self.current_frame_idx, hash_frame(frame));
+        }
+
+        self.current_frame_idx += 1;
+
+        Some((self.current_frame_idx - 1, frame))
+    }
+
+    /// Renders the in-between samples of the frame in the buffer and averages them all into
+    /// `blurred`.
+    fn blur_motion(&mut self) -> anyhow::Result<()> {
+        if self.motion_blur_samples < 2 {
+            return Ok(());
+        }
+
+        self.blur_sums.clear();
+        self.blur_sums.extend(
+            self.buffer
+                .as_ref()
+                .unwrap()
+                .as_raw()
+                .iter()
+                .map(|value| *value as u32),
+        );
+
+        for sample in 1..self.motion_blur_samples {
+            let fraction = sample as f32 / self.motion_blur_samples as f32;
+            for updater in &mut self.updaters {
+                updater.update_between(self.current_frame_idx, fraction, &mut self.ui);
+            }
+
+            self.render_to_buffer()?;
+            for (sum, value) in self
+                .blur_sums
+                .iter_mut()
+                .zip(self.buffer.as_ref().unwrap().as_raw())
+            {
+                *sum += *value as u32;
+            }
+        }
+
+        let count = self.motion_blur_samples as u32;
+        let blurred = self
+            .blurred
+            .get_or_insert_with(|| RgbaImage::new(self.size.x, self.size.y));
+        for (value, sum) in blurred.iter_mut().zip(&self.blur_sums) {
+            *value = ((sum + count / 2) / count) as u8;
+        }
+
+        Ok(())
+    }
+
+    /// Draws the UI over the last rendered frame, only where something changed.
+    fn render_to_buffer(&mut self) -> anyhow::Result<()> {
+        match &mut self.buffer {
+            Some(buffer) => self.ui.render_damaged(buffer).map(|_| ()),
+            None => {
+                let mut buffer = RgbaImage::new(self.size.x, self.size.y);
+                let rendered = self.ui.render(&mut buffer);
+                self.buffer = Some(buffer);
+                rendered
+            }
+        }
+    }
+}
+
+/// Ends the audio coming out of `element` at `end` by sending an end of stream and dropping every
+/// buffer after it.
+fn end_audio_at(element: &gstreamer::Element, end: ClockTime) -> anyhow::Result<()> {
+    let ended = AtomicBool::new(false);
+    element
+        .static_pad("src")
+        .context("The audio element has no source pad")?
+        .add_probe(gstreamer::PadProbeType::BUFFER, move |pad, info| {
+            let Some(gstreamer::PadProbeData::Buffer(buffer)) = &info.data else {
+                return gstreamer::PadProbeReturn::Ok;
+            };
+
+            if !matches!(buffer.pts(), Some(pts) if pts >= end) {
+                return gstreamer::PadProbeReturn::Ok;
+            }
+
+            if !ended.swap(true, Ordering::Relaxed) {
+                pad.push_event(gstreamer::event::Eos::new());
+            }
+            gstreamer::PadProbeReturn::Drop
+        });
+
+    Ok(())
+}
+
+/// Music mixed in under the narration by [`VideoFrameIter::with_music`].
+#[derive(Debug, Clone)]
+pub struct Music {
+    /// Any audio file GStreamer can decode
+    pub path: String,
+    pub ducking: Ducking,
+    /// When the narration is speaking, in seconds from the start and in order
+    pub speech: Vec<Range<f64>>,
+}
+
+impl Music {
+    /// The music's volume at `secs` into the video, see [`Ducking::gain_at`].
+    pub fn gain_at(&self, secs: f64) -> f64 {
+        self.ducking.gain_at(&self.speech, secs)
+    }
+}
+
+/// How much and how quickly music gets quieter while the narration is speaking.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ducking {
+    /// How much quieter the music gets under the narration
+    pub duck_db: f64,
+    /// Seconds it takes to get all the way quieter once the narration starts
+    pub attack_secs: f64,
+    /// Seconds it takes to get back up once the narration stops
+    pub release_secs: f64,
+}
+
+impl Default for Ducking {
+    fn default() -> Self {
+        Ducking {
+            duck_db: 12.0,
+            attack_secs: 0.2,
+            release_secs: 0.6,
+        }
+    }
+}
+
+impl Ducking {
+    /// The music's volume at `secs`, 1.0 in pauses and `duck_db` lower while one of the `speech`
+    /// ranges is playing. It moves between the two in a straight line in dB, over `attack_secs`
+    /// going down and `release_secs` coming back up.
+    pub fn gain_at(&self, speech: &[Range<f64>], secs: f64) -> f64 {
+        let ramp = |elapsed: f64, over: f64| {
+            if elapsed <= 0.0 {
+                0.0
+            } else if over <= 0.0 {
+                1.0
+            } else {
+                elapsed / over
+            }
+        };
+
+        // How far down the music is, from 0.0 to 1.0, as of `last`
+        let mut ducked = 0.0f64;
+        let mut last = 0.0;
+        for range in speech.iter().take_while(|range| range.start < secs) {
+            ducked = (ducked - ramp(range.start - last, self.release_secs)).max(0.0);
+
+            let end = range.end.min(secs);
+            ducked = (ducked + ramp(end - range.start.max(last), self.attack_secs)).min(1.0);
+            last = last.max(end);
+        }
+        ducked = (ducked - ramp(secs - last, self.release_secs)).max(0.0);
+
+        10f64.powf(-self.duck_db * ducked / 20.0)
+    }
+}
+
+/// Updaters shared by the preview and the full video of [`VideoFrameIter::with_preview`].
+struct SharedUpdaters(Arc<Mutex<Vec<Box<dyn ui::UiUpdater>>>>);
+
+impl ui::UiUpdater for SharedUpdaters {
+    fn update(&mut self, frame_idx: u32, ui: &mut VideoUI) {
+        for updater in self.0.lock().unwrap().iter_mut() {
+            updater.update(frame_idx, ui);
+        }
+    }
+
+    fn update_between(&mut self, frame_idx: u32, fraction: f32, ui: &mut VideoUI) {
+        for updater in self.0.lock().unwrap().iter_mut() {
+            updater.update_between(frame_idx, fraction, ui);
+        }
+    }
+
+    fn restart(&mut self) {
+        for updater in self.0.lock().unwrap().iter_mut() {
+            updater.restart();
+        }
+    }
+}
+
+/// Where [`VideoFrameIter::encode_with_preview`] writes the preview of `video_out`,
+/// `SCP-173.mp4` gets `SCP-173-preview.mp4`.
+pub fn preview_path(video_out: &str) -> String {
+    let path = Path::new(video_out);
+    match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(extension)) => path
+            .with_file_name(format!(
+                "{}-preview.{}",
+                stem.to_string_lossy(),
+                extension.to_string_lossy()
+            ))
+            .to_string_lossy()
+            .into_owned(),
+        _ => format!("{video_out}-preview"),
+    }
+}
+
+/// A part of a compilation, like one SCP, that is `frames` long.
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub title: String,
+    pub frames: u32,
+}
+
+/// A chapter at the start of every segment, for segments played one after the other.
+pub fn segment_chapters(segments: &[Segment], frame_rate: u32) -> Vec<Chapter> {
+    let mut start_frame = 0u64;
+
+    segments
+        .iter()
+        .map(|segment| {
+            let chapter = Chapter {
+                start_secs: start_frame as f64 / frame_rate as f64,
+                title: segment.title.clone(),
+            };
+            start_frame += segment.frames as u64;
+            chapter
+        })
+        .collect()
+}
+
+/// Where [`VideoFrameIter::encode`] writes the chapters of `video_out`, `SCP-173.mp4` gets
+/// `SCP-173.ffmetadata`.
+pub fn chapters_path(video_out: &str) -> PathBuf {
+    Path::new(video_out).with_extension("ffmetadata")
+}
+
+/// `chapters` in FFmpeg's metadata format, each one ending where the next starts and the last at
+/// `duration_secs`. mp4mux can't write chapters, so they are muxed in afterwards with
+/// `ffmpeg -i <video> -i <chapters> -map_metadata 1 -codec copy <out>`.
+pub fn ffmetadata_chapters(chapters: &[Chapter], duration_secs: f64) -> String {
+    let millis = |secs: f64| (secs * 1000.0).round() as u64;
+
+    let mut out = String::from(";FFMETADATA1\n");
+    for (idx, chapter) in chapters.iter().enumerate() {
+        let end = chapters
+            .get(idx + 1)
+            .map_or(duration_secs, |next| next.start_secs);
+
+        let mut title = String::default();
+        for ch in chapter.title.chars() {
+            match ch {
+                '=' | ';' | '#' | '\\' => {
+                    title.push('\\');
+                    title.push(ch);
+                }
+                '\n' => title.push(' '),
+                ch => title.push(ch),
+            }
+        }
+
+        out.push_str(&format!(
+            "\n[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={title}\n",
+            millis(chapter.start_secs),
+            millis(end)
+        ));
+    }
+
+    out
+}
+
+/// The video codec and the container it's written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VideoCodec {
+    /// H.264 with AAC audio in an MP4
+    #[default]
+    H264,
+    /// VP9 with Vorbis audio in a WebM
+    Vp9,
+}
+
+impl VideoCodec {
+    /// The file extension of the container, without the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "mp4",
+            VideoCodec::Vp9 => "webm",
+        }
+    }
+}
+
+/// Frame sizes the generator's layout is tuned for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VideoPreset {
+    /// 1080x1920 portrait, for Shorts
+    #[default]
+    Shorts,
+    /// 1920x1080
+    Landscape,
+    /// 1080x1080
+    Square,
+}
+
+impl VideoPreset {
+    pub fn size(self) -> UVec2 {
+        match self {
+            VideoPreset::Shorts => UVec2::new(1080, 1920),
+            VideoPreset::Landscape => UVec2::new(1920, 1080),
+            VideoPreset::Square => UVec2::new(1080, 1080),
+        }
+    }
+
+    /// Spacing that fits the title, image, classification and subtitles in the frame.
+    pub fn layout(self) -> VideoLayout {
+        match self {
+            VideoPreset::Shorts => VideoLayout {
+                title_margin: 100.0,
+                side_margin: 100.0,
+                image_size: 800.0,
+                subtitle_height: 420.0,
+            },
+            // Both are 1080 high, which leaves room for 3 lines of subtitles under a smaller image
+            VideoPreset::Landscape | VideoPreset::Square => VideoLayout {
+                title_margin: 20.0,
+                side_margin: 100.0,
+                image_size: 300.0,
+                subtitle_height: 240.0,
+            },
+        }
+    }
+}
+
+/// The sizes and margins of the generator's layout, see [`VideoPreset::layout`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VideoLayout {
+    /// Space above and below the name and title
+    pub title_margin: f32,
+    /// Space left and right of the subtitles and scrolling text
+    pub side_margin: f32,
+    /// Width and height of the image
+    pub image_size: f32,
+    pub subtitle_height: f32,
+}
+
+/// Quality settings for [`VideoFrameIter::encode`], each left to the encoder when `None`.
+#[derive(Debug, Clone, Default)]
+pub struct EncodeConfig {
+    /// Constant quality from 0 for lossless to 50, lower is better
+    pub crf: Option<u32>,
+    /// One of [`EncodeConfig::PRESETS`], slower presets make smaller files
+    pub preset: Option<String>,
+    pub bitrate_kbps: Option<u32>,
+}
+
+impl EncodeConfig {
+    pub const PRESETS: &[&str] = &[
+        "ultrafast",
+        "superfast",
+        "veryfast",
+        "faster",
+        "fast",
+        "medium",
+        "slow",
+        "slower",
+        "veryslow",
+        "placebo",
+    ];
+    pub const MAX_CRF: u32 = 50;
+
+    /// Fails if the CRF or preset is one x264 doesn't have.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let Some(crf) = self.crf.filter(|crf| *crf > Self::MAX_CRF) {
+            anyhow::bail!(
+                "A CRF of {crf} is too high, it goes up to {}",
+                Self::MAX_CRF
+            );
+        }
+
+        if let Some(preset) = self
+            .preset
+            .as_ref()
+            .filter(|preset| !Self::PRESETS.contains(&preset.as_str()))
+        {
+            anyhow::bail!(
+                "There is no {preset} preset, it has to be one of {:?}",
+                Self::PRESETS
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Layout settings for [`produce_poster`].
+pub struct PosterConfig {
+    pub size: UVec2,
+    pub font: rusttype::Font<'static>,
+    pub theme: ui::Theme,
+}
+
+impl PosterConfig {
+    pub fn new(size: UVec2, font: rusttype::Font<'static>) -> Self {
+        PosterConfig {
+            size,
+            font,
+            theme: ui::Theme::default(),
+        }
+    }
+
+    pub fn with_theme(mut self, theme: ui::Theme) -> Self {
+        self.theme = theme;
+        self
+    }
+}
+
+/// Renders a still poster with the name and title on top, `image` in the middle and the
+/// classification at the bottom.
+pub fn produce_poster(
+    name: &str,
+    title: &str,
+    classification: &Classification,
+    image: RgbaImage,
+    config: &PosterConfig,
+) -> anyhow::Result<RgbaImage> {
+    let mut ui = VideoUI::default();
+    ui.background_color = config.theme.background;
+
+    let font = ui.add_font("poster", config.font.clone());
+    let image = ui.add(image);
+
+    let text_size = config.size.x as f32 / 12.0;
+    let text_node = |text: String| ui::StyledNode {
+        node: ui::Node::Text {
+            text,
+            font,
+            scale: rusttype::Scale {
+                x: text_size,
+                y: text_size,
+            },
+            line_height: text_size as u32,
+            color: config.theme.text,
+            word_break: ui::WordBreak::Hyphenate,
+            align: ui::TextAlign::Center,
+            outline: None,
+            auto_contrast: false,
+        },
+        style: Style {
+            size: Size {
+                width: Dimension::Auto,
+                height: Dimension::Auto,
+            },
+            ..Default::default()
+        },
+        opacity: 1.0,
+        z_index: None,
+    };
+
+    ui.children = vec![
+        text_node(name.into()),
+        text_node(title.to_ascii_uppercase()),
+        ui::StyledNode {
+            node: ui::Node::Image {
+                image,
+                fit: ui::ImageFit::Fill,
+                shadow: None,
+            },
+            style: Style {
+                flex_grow: 1.0,
+                size: Size {
+                    width: Dimension::Auto,
+                    height: Dimension::Auto,
+                },
+                margin: Rect {
+                    left: LengthPercentageAuto::Auto,
+                    right: LengthPercentageAuto::Auto,
+                    top: LengthPercentageAuto::Points(text_size / 2.0),
+                    bottom: LengthPercentageAuto::Points(text_size / 2.0),
+                },
+                ..Default::default()
+            },
+            opacity: 1.0,
+            z_index: None,
+        },
+    ];
+    let classification = classification.ui(font, &config.theme, &mut ui);
+    ui.children.push(classification);
+
+    let mut poster = RgbaImage::new(config.size.x, config.size.y);
+    ui.render(&mut poster)?;
+
+    Ok(poster)
+}
+
+// AAC encoders in order of preference. avenc_aac comes from gst-libav which isn't always
+// installed, the others come from the good/bad plugin sets.
+const AAC_ENCODERS: &[&str] = &["avenc_aac", "voaacenc", "faac", "fdkaacenc"];
+
+fn make_element(name: &str) -> anyhow::Result<gstreamer::Element> {
+    ElementFactory::make(name)
+        .build()
+        .with_context(|| format!("Failed to create {name}, is its GStreamer plugin installed?"))
+}
+
+/// The most preferred AAC encoder that is available.
+fn aac_encoder() -> Option<&'static str> {
+    let encoder = find_element(AAC_ENCODERS)?;
+
+    if encoder != AAC_ENCODERS[0] {
+        println!(
+            "{} is unavailable, encoding audio with {encoder}",
+            AAC_ENCODERS[0]
+        );
+    }
+
+    Some(encoder)
+}
+
+/// The first of `names` that GStreamer has a plugin for.
+fn find_element(names: &[&'static str]) -> Option<&'static str> {
+    names
+        .iter()
+        .copied()
+        .find(|name| ElementFactory::find(name).is_some())
+}
+
+/// The GStreamer element used for each codec and container, `None` when no plugin for it is
+/// installed.
+#[derive(Debug, Clone, Default)]
+pub struct AvailableEncoders {
+    pub h264: Option<&'static str>,
+    pub vp9: Option<&'static str>,
+    pub aac: Option<&'static str>,
+    pub opus: Option<&'static str>,
+    pub mp4: Option<&'static str>,
+    pub webm: Option<&'static str>,
+}
+
+impl AvailableEncoders {
+    /// The first element for each codec and container that `exists` says is installed.
+    fn probe(exists: impl Fn(&str) -> bool) -> Self {
+        let find = |names: &[&'static str]| names.iter().copied().find(|name| exists(name));
+
+        AvailableEncoders {
+            h264: find(&["x264enc", "openh264enc"]),
+            vp9: find(&["vp9enc"]),
+            aac: find(AAC_ENCODERS),
+            opus: find(&["opusenc"]),
+            mp4: find(&["mp4mux"]),
+            webm: find(&["webmmux"]),
+        }
+    }
+}
+
+/// Probes which encoders and muxers can be used on this system.
+pub fn available_encoders() -> anyhow::Result<AvailableEncoders> {
+    gstreamer::init()?;
+
+    Ok(AvailableEncoders::probe(|name| {
+        ElementFactory::find(name).is_some()
+    }))
+}
+
+// Tried in order when HOTI_FONT isn't set
+const FONT_SEARCH_PATHS: &[&str] = &[
+    "/usr/share/fonts/noto/NotoSansMono-ExtraBold.ttf",
+    "/usr/share/fonts/truetype/noto/NotoSansMono-ExtraBold.ttf",
+    "/usr/share/fonts/google-noto/NotoSansMono-ExtraBold.ttf",
+    "/usr/share/fonts/truetype/dejavu/DejaVuSansMono-Bold.ttf",
+    "assets/SpecialElite-Regular.ttf",
+];
+
+pub fn load_font_from_path(path: &str) -> anyhow::Result<rusttype::Font<'static>> {
+    let data = std::fs::read(path).with_context(|| format!("Couldn't read the font {path}"))?;
+
+    rusttype::Font::try_from_vec(data).with_context(|| format!("{path} is not a usable font"))
+}
+
+/// The font at `HOTI_FONT`, or the first of a few common fonts found on the system.
+pub fn load_default_font() -> anyhow::Result<rusttype::Font<'static>> {
+    if let Ok(path) = std::env::var("HOTI_FONT") {
+        return load_font_from_path(&path);
     }
+
+    let path = FONT_SEARCH_PATHS
+        .iter()
+        .find(|path| Path::new(path).exists())
+        .with_context(|| {
+            format!("None of the fonts {FONT_SEARCH_PATHS:?} exist, set HOTI_FONT to a font")
+        })?;
+
+    load_font_from_path(path)
 }
 
 impl Iterator for VideoFrameIter {
     type Item = (u32, RgbaImage);
 
+    /// Copies every frame out of the buffer it is rendered into, use
+    /// [`VideoFrameIter::next_frame`] where a borrowed frame is enough.
     fn next(&mut self) -> Option<Self::Item> {
-        if self.current_frame_idx >= self.total_frames {
-            None
-        } else {
-            for updater in &mut self.updaters {
-                updater.update(self.current_frame_idx, &mut self.ui);
-            }
+        self.next_frame().map(|(idx, frame)| (idx, frame.clone()))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.total_frames.saturating_sub(self.current_frame_idx) as usize;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for VideoFrameIter {}
+
+/// A [`Fnv1a`](crate::Fnv1a) hash of the pixels of `frame`, so recorded hashes stay comparable
+/// between builds.
+pub fn hash_frame(frame: &RgbaImage) -> u64 {
+    let mut hasher = crate::Fnv1a::default();
+    hasher.write(frame.as_raw());
+    hasher.finish()
+}
+
+fn info_overlay_node(text: String, font: ui::FontHandle) -> ui::StyledNode {
+    ui::StyledNode {
+        node: ui::Node::Text {
+            text,
+            font,
+            scale: rusttype::Scale { x: 32.0, y: 32.0 },
+            line_height: 40,
+            color: [255, 255, 255, 255].into(),
+            word_break: ui::WordBreak::Never,
+            align: ui::TextAlign::Center,
+            outline: None,
+            auto_contrast: true,
+        },
+        style: Style {
+            position: taffy::style::Position::Absolute,
+            size: Size {
+                width: Dimension::Points(400.0),
+                height: Dimension::Points(40.0),
+            },
+            inset: Rect {
+                left: LengthPercentageAuto::Points(20.0),
+                right: LengthPercentageAuto::Auto,
+                top: LengthPercentageAuto::Points(20.0),
+                bottom: LengthPercentageAuto::Auto,
+            },
+            ..Default::default()
+        },
+        opacity: 1.0,
+        z_index: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+    use taffy::style::LengthPercentage;
 
-            let mut frame = RgbaImage::new(self.size.x, self.size.y);
+    use super::*;
+    use crate::video_gen::{animation::Animation, easing::Easing};
 
-            if let Err(err) = self.ui.render(&mut frame) {
-                panic!(
-                    "Failed to render frame {} due to error: {}",
-                    self.current_frame_idx, err
-                );
+    /// Swaps the image of the first node on one frame, like a cut to the next image.
+    struct Cut {
+        frame: u32,
+        image: ui::ImageHandle,
+    }
+
+    impl ui::UiUpdater for Cut {
+        fn update(&mut self, frame_idx: u32, ui: &mut VideoUI) {
+            if frame_idx == self.frame {
+                if let ui::Node::Image { image, .. } = &mut ui.children[0].node {
+                    *image = self.image;
+                    ui.mark_dirty();
+                }
             }
+        }
+    }
+
+    #[test]
+    fn motion_blur_smears_movement_but_not_cuts() {
+        let white = Rgba([255, 255, 255, 255]);
+        let red = Rgba([255, 0, 0, 255]);
+
+        let mut video = VideoFrameIter::new(UVec2::new(16, 4), 1, Duration::from_secs(3))
+            .unwrap()
+            .with_motion_blur(4);
+        video.ui.background_color = Rgba([0, 0, 0, 255]);
+        let white_image = video.ui.add(RgbaImage::from_pixel(8, 4, white));
+        let red_image = video.ui.add(RgbaImage::from_pixel(8, 4, red));
+
+        video.ui.children.push(ui::StyledNode {
+            node: ui::Node::Image {
+                image: white_image,
+                fit: ui::ImageFit::Fill,
+                shadow: None,
+            },
+            style: Style {
+                position: taffy::style::Position::Absolute,
+                size: Size {
+                    width: Dimension::Points(8.0),
+                    height: Dimension::Points(4.0),
+                },
+                ..Default::default()
+            },
+            opacity: 1.0,
+            z_index: None,
+        });
+        video.updaters.push(Box::new(Animation::new(
+            vec![(0, 0.0), (1, 8.0)],
+            Easing::Linear,
+            |ui, left| {
+                ui.children[0].style.inset.left = LengthPercentageAuto::Points(left);
+            },
+        )));
+        video.updaters.push(Box::new(Cut {
+            frame: 2,
+            image: red_image,
+        }));
+
+        let frames: Vec<RgbaImage> = video.map(|(_, frame)| frame).collect();
+
+        // Over the first frame the image moves 8 pixels, so its left edge is only covered at the
+        // start
+        let edge = frames[0].get_pixel(0, 0).0[0];
+        assert!(0 < edge && edge < 255, "{edge}");
+        assert_eq!(*frames[0].get_pixel(7, 0), white);
 
-            self.current_frame_idx += 1;
+        assert_eq!(*frames[1].get_pixel(0, 0), Rgba([0, 0, 0, 255]));
+        assert_eq!(*frames[1].get_pixel(8, 0), white);
+        assert_eq!(*frames[2].get_pixel(8, 0), red);
+    }
+
+    #[test]
+    fn two_segments_get_a_chapter_each() {
+        let segments = [
+            Segment {
+                title: "SCP-173".into(),
+                frames: 300,
+            },
+            Segment {
+                title: "SCP-096 = Shy Guy".into(),
+                frames: 600,
+            },
+        ];
+
+        let chapters = segment_chapters(&segments, 30);
+        assert_eq!(
+            chapters
+                .iter()
+                .map(|chapter| (chapter.start_secs, chapter.title.as_str()))
+                .collect::<Vec<_>>(),
+            [(0.0, "SCP-173"), (10.0, "SCP-096 = Shy Guy")]
+        );
+
+        assert_eq!(
+            ffmetadata_chapters(&chapters, 30.0),
+            ";FFMETADATA1\n\
+             \n[CHAPTER]\nTIMEBASE=1/1000\nSTART=0\nEND=10000\ntitle=SCP-173\n\
+             \n[CHAPTER]\nTIMEBASE=1/1000\nSTART=10000\nEND=30000\ntitle=SCP-096 \\= Shy Guy\n"
+        );
+        assert_eq!(
+            chapters_path("SCP-173.mp4"),
+            Path::new("SCP-173.ffmetadata")
+        );
+    }
+
+    #[test]
+    fn preview_is_smaller_with_the_same_layout() {
+        let [white, red, blue] = [
+            Rgba([255, 255, 255, 255]),
+            Rgba([255, 0, 0, 255]),
+            Rgba([0, 0, 255, 255]),
+        ];
+        let image_node = |image, width, height, margin: Rect<f32>| ui::StyledNode {
+            node: ui::Node::Image {
+                image,
+                fit: ui::ImageFit::Fill,
+                shadow: None,
+            },
+            style: Style {
+                size: Size {
+                    width: Dimension::Points(width),
+                    height: Dimension::Points(height),
+                },
+                margin: margin.map(LengthPercentageAuto::Points),
+                ..Default::default()
+            },
+            opacity: 1.0,
+            z_index: None,
+        };
+
+        let mut video =
+            VideoFrameIter::new(UVec2::new(400, 400), 1, Duration::from_secs(2)).unwrap();
+        video.ui.background_color = Rgba([0, 0, 0, 255]);
+        let white_image = video.ui.add(RgbaImage::from_pixel(8, 8, white));
+        let red_image = video.ui.add(RgbaImage::from_pixel(8, 8, red));
+        let blue_image = video.ui.add(RgbaImage::from_pixel(8, 8, blue));
+
+        let margin = |left, top| Rect {
+            left,
+            right: 0.0,
+            top,
+            bottom: 0.0,
+        };
+        video.ui.children = vec![
+            image_node(white_image, 200.0, 120.0, margin(40.0, 40.0)),
+            ui::StyledNode {
+                node: ui::Node::Container(vec![image_node(
+                    red_image,
+                    120.0,
+                    80.0,
+                    margin(0.0, 0.0),
+                )]),
+                style: Style {
+                    padding: Rect {
+                        left: LengthPercentage::Points(20.0),
+                        right: LengthPercentage::Points(0.0),
+                        top: LengthPercentage::Points(20.0),
+                        bottom: LengthPercentage::Points(0.0),
+                    },
+                    ..Default::default()
+                },
+                opacity: 1.0,
+                z_index: None,
+            },
+        ];
+        video.updaters.push(Box::new(Cut {
+            frame: 1,
+            image: blue_image,
+        }));
+
+        // The corners of the area covered by `color`
+        let bounds = |frame: &RgbaImage, color| {
+            let covered: Vec<_> = frame
+                .enumerate_pixels()
+                .filter(|(_, _, pixel)| **pixel == color)
+                .map(|(x, y, _)| UVec2::new(x, y))
+                .collect();
+            let min = covered.iter().copied().reduce(UVec2::min).unwrap();
+            let max = covered.iter().copied().reduce(UVec2::max).unwrap();
+            (min, max + 1)
+        };
+
+        let (preview, full) = video.with_preview(4);
+        let previews: Vec<RgbaImage> = preview.map(|(_, frame)| frame).collect();
+        let fulls: Vec<RgbaImage> = full.map(|(_, frame)| frame).collect();
+
+        assert_eq!(previews[0].dimensions(), (100, 100));
+        assert_eq!(fulls[0].dimensions(), (400, 400));
+        assert_eq!(
+            bounds(&previews[0], white),
+            (UVec2::new(10, 10), UVec2::new(60, 40))
+        );
+
+        // The updaters run again from the first frame for the full video
+        for (color, idx) in [(white, 0), (red, 0), (blue, 1), (red, 1)] {
+            let (min, max) = bounds(&previews[idx], color);
+            assert_eq!(bounds(&fulls[idx], color), (min * 4, max * 4));
+        }
+    }
+
+    #[test]
+    fn music_is_ducked_while_the_narration_speaks() {
+        let ducking = Ducking {
+            duck_db: 20.0,
+            attack_secs: 0.5,
+            release_secs: 1.0,
+        };
+        let speech = [1.0..3.0, 5.0..6.0];
+        let gain = |secs| ducking.gain_at(&speech, secs);
+        let close = |gain: f64, expected: f64| (gain - expected).abs() < 1e-9;
+
+        // Full volume before the narration, and 20 dB down, a tenth, once the attack is over
+        assert!(close(gain(0.5), 1.0));
+        assert!(close(gain(1.25), 10f64.powf(-0.5)));
+        assert!(close(gain(2.0), 0.1));
+        assert!(close(gain(3.0), 0.1));
+
+        // Back up over the release in the pause, then down again for the next part
+        assert!(close(gain(3.5), 10f64.powf(-0.5)));
+        assert!(close(gain(4.5), 1.0));
+        assert!(close(gain(5.5), 0.1));
+        assert!(close(gain(7.0), 1.0));
+
+        // It only gets quieter while the narration goes on
+        for step in 1..=300 {
+            let secs = step as f64 / 100.0;
+            assert!(gain(secs) <= gain(secs - 0.01) + 1e-9, "louder at {secs}");
+        }
+    }
+
+    #[test]
+    fn frame_times_do_not_drift() {
+        let video = VideoFrameIter::new(UVec2::new(4, 4), 30, Duration::from_secs(60)).unwrap();
+
+        // A frame is 33.333...ms, a whole number of nanoseconds per frame would be 600ns short
+        // by now
+        assert_eq!(video.frame_time(1800), ClockTime::from_seconds(60));
+        assert_eq!(video.frame_time(1799).nseconds(), 59_966_666_666);
+    }
+
+    #[test]
+    fn unsupported_frame_rates_and_encode_settings_are_rejected() {
+        let new =
+            |frame_rate| VideoFrameIter::new(UVec2::new(4, 4), frame_rate, Duration::from_secs(1));
+
+        // A frame rate of 0 would divide every frame time by zero
+        assert!(new(0).is_err());
+        assert!(new(121).is_err());
 
-            Some((self.current_frame_idx - 1, frame))
+        for frame_rate in [30, 60] {
+            let video = new(frame_rate).unwrap();
+            assert_eq!(
+                video.frame_time(1).nseconds(),
+                1_000_000_000 / frame_rate as u64
+            );
+            assert_eq!(video.frame_time(frame_rate), ClockTime::SECOND);
         }
+
+        let encode = |crf, preset: Option<&str>| {
+            EncodeConfig {
+                crf,
+                preset: preset.map(str::to_owned),
+                bitrate_kbps: None,
+            }
+            .validate()
+        };
+        assert!(encode(Some(23), Some("slow")).is_ok());
+        assert!(encode(Some(EncodeConfig::MAX_CRF + 1), None).is_err());
+        assert!(encode(None, Some("sluggish")).is_err());
+    }
+
+    #[test]
+    fn info_overlay_shows_the_elapsed_time() {
+        let mut video = VideoFrameIter::new(UVec2::new(4, 4), 60, Duration::from_secs(5)).unwrap();
+        let font = video.ui.add_font(
+            "test",
+            rusttype::Font::try_from_bytes(include_bytes!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/assets/SpecialElite-Regular.ttf"
+            )))
+            .unwrap(),
+        );
+        assert_eq!(video.info_overlay_text(120), None);
+
+        let video = video.with_info_overlay(font, Some("SCP-173".into()), true);
+        assert_eq!(
+            video.info_overlay_text(120).as_deref(),
+            Some("SCP-173 0:02")
+        );
+        assert_eq!(
+            video.info_overlay_text(119).as_deref(),
+            Some("SCP-173 0:01")
+        );
+    }
+
+    #[test]
+    fn raw_output_is_a_header_and_every_frame() {
+        let path = std::env::temp_dir().join("hoti-rs-raw-output.rgba");
+        let video = VideoFrameIter::new(UVec2::new(6, 4), 5, Duration::from_secs(2)).unwrap();
+        let total_frames = video.total_frames() as usize;
+
+        video
+            .write_raw(std::io::BufWriter::new(
+                std::fs::File::create(&path).unwrap(),
+            ))
+            .unwrap();
+        let raw = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let header = b"RGBA 6 4 5\n";
+        assert!(raw.starts_with(header));
+        assert_eq!(raw.len() - header.len(), total_frames * 6 * 4 * 4);
+        assert_eq!(total_frames, 10);
+    }
+
+    #[test]
+    fn available_encoders_reflect_the_installed_plugins() {
+        let installed = ["openh264enc", "opusenc", "webmmux"];
+        let encoders = AvailableEncoders::probe(|name| installed.contains(&name));
+
+        assert_eq!(encoders.h264, Some("openh264enc"));
+        assert_eq!(encoders.vp9, None);
+        assert_eq!(encoders.aac, None);
+        assert_eq!(encoders.opus, Some("opusenc"));
+        assert_eq!(encoders.mp4, None);
+        assert_eq!(encoders.webm, Some("webmmux"));
+
+        // Whatever is installed here, probing for real doesn't panic
+        let _ = available_encoders();
     }
 }
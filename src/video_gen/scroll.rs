@@ -0,0 +1,70 @@
+use taffy::style::LengthPercentageAuto;
+
+use super::ui::{UiUpdater, VideoUI};
+
+/// Scrolls a text node up the frame like a teleprompter, from just below the bottom edge until
+/// its last line has left the top on the last frame.
+pub struct ScrollingText {
+    node_idx: usize,
+    canvas_height: f32,
+    content_height: f32,
+    total_frames: u32,
+}
+
+impl ScrollingText {
+    /// Scrolls the text node at `ui.children[node_idx]`, which should be absolutely positioned and
+    /// `width` wide. The longer its text and the shorter the video, the faster it goes.
+    pub fn new(
+        ui: &VideoUI,
+        node_idx: usize,
+        width: f32,
+        canvas_height: f32,
+        total_frames: u32,
+    ) -> anyhow::Result<Self> {
+        let content_height = ui
+            .children
+            .get(node_idx)
+            .and_then(|node| ui.text_height(node, width))
+            .ok_or_else(|| anyhow::anyhow!("Node {node_idx} is not a text node to scroll"))?;
+
+        Ok(ScrollingText {
+            node_idx,
+            canvas_height,
+            content_height,
+            total_frames,
+        })
+    }
+
+    /// Pixels the text moves up every frame.
+    pub fn speed(&self) -> f32 {
+        (self.canvas_height + self.content_height)
+            / self.total_frames.saturating_sub(1).max(1) as f32
+    }
+
+    /// Distance from the top of the frame to the top of the text at `frame_idx`.
+    pub fn offset_at(&self, frame_idx: u32) -> f32 {
+        self.offset_at_time(frame_idx as f32)
+    }
+
+    fn offset_at_time(&self, time: f32) -> f32 {
+        let time = time.min(self.total_frames.saturating_sub(1) as f32);
+        self.canvas_height - self.speed() * time
+    }
+
+    fn scroll_to(&self, offset: f32, ui: &mut VideoUI) {
+        if let Some(node) = ui.children.get_mut(self.node_idx) {
+            node.style.inset.top = LengthPercentageAuto::Points(offset);
+            ui.mark_dirty();
+        }
+    }
+}
+
+impl UiUpdater for ScrollingText {
+    fn update(&mut self, frame_idx: u32, ui: &mut VideoUI) {
+        self.scroll_to(self.offset_at(frame_idx), ui);
+    }
+
+    fn update_between(&mut self, frame_idx: u32, fraction: f32, ui: &mut VideoUI) {
+        self.scroll_to(self.offset_at_time(frame_idx as f32 + fraction), ui);
+    }
+}
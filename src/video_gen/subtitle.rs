@@ -1,65 +1,593 @@
-use super::ui::{Node, UiUpdater, VideoUI};
+use std::{collections::VecDeque, ops::Range};
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::{
+    animation::Lerp,
+    easing::Easing,
+    ui::{Node, StyledNode, UiUpdater, VideoUI},
+};
 
 pub struct SubtitleManager {
     parts: Vec<(u32, String)>,
+    rolling_cues: usize,
+    shown: VecDeque<String>,
+    pop_in: Option<PopIn>,
 }
 
+/// Scales each new cue up from `1.0 - amount` of its size with a slight bounce over `frames`.
+#[derive(Debug, Clone, Copy)]
+struct PopIn {
+    amount: f32,
+    frames: u32,
+    cue_start: Option<u32>,
+    full_scale: Option<rusttype::Scale>,
+}
+
+const MAX_UTTERANCES: u64 = 100;
+
 impl SubtitleManager {
     pub fn new(text: String, total_frames: u32) -> Self {
-        let mut utterances = 0;
+        SubtitleManager {
+            parts: cues(&text, 0, total_frames),
+            rolling_cues: 1,
+            shown: VecDeque::default(),
+            pop_in: None,
+        }
+    }
 
-        for ch in text.chars() {
-            match ch {
-                '█' => continue,
-                '.' | ',' | '?' => utterances += 2,
-                _ => utterances += 1,
-            }
+    /// Pops each cue in by growing it from `1.0 - amount` of its size over `frames` frames,
+    /// overshooting a little before it settles.
+    pub fn with_pop_in(mut self, amount: f32, frames: u32) -> Self {
+        self.pop_in = Some(PopIn {
+            amount,
+            frames: frames.max(1),
+            cue_start: None,
+            full_scale: None,
+        });
+        self
+    }
+
+    /// Keeps the last `count` cues on screen, one below the other, with new cues pushing the
+    /// oldest one off the top. See [`SubtitleManager::rolling_cues_that_fit`] for how many fit.
+    pub fn with_rolling_cues(mut self, count: usize) -> Self {
+        self.rolling_cues = count.max(1);
+        self
+    }
+
+    /// The most cues, up to `count`, that can be stacked in the text `node` with every run of
+    /// that many cues fitting in `height` once wrapped to `width`. Never less than 1.
+    pub fn rolling_cues_that_fit(
+        &self,
+        count: usize,
+        ui: &VideoUI,
+        node: &StyledNode,
+        width: f32,
+        height: f32,
+    ) -> usize {
+        let cues: Vec<&str> = self
+            .parts
+            .iter()
+            .map(|(_, cue)| cue.as_str())
+            .filter(|cue| !cue.is_empty())
+            .collect();
+        let mut node = node.clone();
+
+        (2..=count.min(cues.len()))
+            .take_while(|&stacked| {
+                cues.windows(stacked).all(|window| {
+                    if let Node::Text { text, .. } = &mut node.node {
+                        *text = window.join("\n");
+                    }
+                    !matches!(ui.text_height(&node, width), Some(text_height) if text_height > height)
+                })
+            })
+            .last()
+            .unwrap_or(1)
+    }
+
+    /// Subtitles for narration split into beats, each beat starts at its own frame so it lines up
+    /// with the image generated for it.
+    pub fn from_beats(beats: &[String], total_frames: u32) -> Self {
+        let start_frames = beat_start_frames(beats, total_frames);
+
+        let parts = beats
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, beat)| {
+                let start = start_frames[idx];
+                let end = start_frames.get(idx + 1).copied().unwrap_or(total_frames);
+
+                cues(beat, start, end - start)
+            })
+            .collect();
+
+        SubtitleManager {
+            parts,
+            rolling_cues: 1,
+            shown: VecDeque::default(),
+            pop_in: None,
         }
+    }
+
+    /// Subtitles with the exact cues and timings of an SRT file, skipping the guesswork of where
+    /// each cue starts. Nothing is shown in the gaps between cues.
+    pub fn from_srt(srt: &str, frame_rate: u32) -> anyhow::Result<Self> {
+        let to_frame = |timestamp: &str| -> anyhow::Result<u32> {
+            let ms = parse_srt_timestamp(timestamp.trim())
+                .ok_or_else(|| anyhow::anyhow!("Invalid SRT timestamp: {timestamp}"))?;
+            Ok((ms as f64 * frame_rate as f64 / 1000.0).round() as u32)
+        };
 
-        let frames_per_ch = total_frames as f64 / utterances as f64;
-        let mut prev_utterances = 0;
-        let mut current_utterances = 0;
-        let mut parts = vec![(0, "".to_owned())];
+        let srt = srt.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+        let mut parts: Vec<(u32, String)> = Vec::default();
 
-        for part in text.split(' ') {
-            let mut this_utterances = 0;
+        for block in srt.split("\n\n").filter(|block| !block.trim().is_empty()) {
+            // The counter line in front of the timing is optional
+            let mut lines = block
+                .trim()
+                .lines()
+                .skip_while(|line| !line.contains("-->"));
+            let timing = lines
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("SRT cue without a timing line: {block}"))?;
+            let (start, end) = timing.split_once("-->").unwrap();
+            let (start, end) = (to_frame(start)?, to_frame(end)?);
+            let text = lines.collect::<Vec<_>>().join("\n");
 
-            for ch in part.chars() {
-                match ch {
-                    '█' => continue,
-                    '.' | ',' | '?' => this_utterances += 2,
-                    _ => this_utterances += 1,
+            // An empty cue clears the subtitle when the previous cue ends before this one starts
+            if let Some((prev_end, _)) = parts.last().filter(|(_, text)| text.is_empty()) {
+                if *prev_end >= start {
+                    parts.pop();
                 }
             }
 
-            this_utterances += 1;
+            // Cues shorter than a frame can round to the frame the one before starts on, or end on
+            // the frame they start on, so each one gets at least a frame of its own
+            let start = match parts.last() {
+                Some((prev_start, _)) => start.max(prev_start + 1),
+                None => start,
+            };
+            let end = end.max(start + 1);
 
-            if (current_utterances + this_utterances) < 100 {
-                let last = &mut parts.last_mut().unwrap().1;
-                last.push(' ');
-                last.push_str(part);
+            parts.push((start, text));
+            parts.push((end, String::new()));
+        }
 
-                current_utterances += this_utterances;
-            } else {
-                parts.push((
-                    ((current_utterances + prev_utterances) as f64 * frames_per_ch).round() as u32,
-                    part.to_owned(),
-                ));
-                prev_utterances += current_utterances;
-                current_utterances = this_utterances;
+        Ok(SubtitleManager {
+            parts,
+            rolling_cues: 1,
+            shown: VecDeque::default(),
+            pop_in: None,
+        })
+    }
+
+    /// When a cue is shown, in seconds, with back to back cues merged. Cues are shown while their
+    /// narration is spoken, so these are also when the narration is speaking.
+    pub fn speech(&self, frame_rate: u32, total_frames: u32) -> Vec<Range<f64>> {
+        let secs = |frame: u32| frame as f64 / frame_rate as f64;
+        let mut speech: Vec<Range<f64>> = Vec::default();
+
+        for (idx, (start, cue)) in self.parts.iter().enumerate() {
+            let end = self
+                .parts
+                .get(idx + 1)
+                .map_or(total_frames, |(end, _)| *end);
+            if cue.is_empty() || end <= *start {
+                continue;
+            }
+
+            match speech.last_mut() {
+                Some(last) if last.end >= secs(*start) => last.end = secs(end),
+                _ => speech.push(secs(*start)..secs(end)),
             }
         }
 
-        SubtitleManager { parts }
+        speech
+    }
+}
+
+/// Milliseconds since the start of an SRT timestamp like `00:01:02,345`.
+fn parse_srt_timestamp(timestamp: &str) -> Option<u64> {
+    let (time, ms) = timestamp.split_once([',', '.'])?;
+    let mut fields = time.split(':').map(|field| field.parse::<u64>().ok());
+    let (hours, minutes, secs) = (fields.next()??, fields.next()??, fields.next()??);
+    if fields.next().is_some() {
+        return None;
     }
+
+    Some(((hours * 60 + minutes) * 60 + secs) * 1000 + ms.parse::<u64>().ok()?)
+}
+
+/// The frame each beat starts on, assuming the narration is spoken at an even pace.
+pub fn beat_start_frames(beats: &[String], total_frames: u32) -> Vec<u32> {
+    let utterances: Vec<u64> = beats.iter().map(|beat| text_utterances(beat) + 1).collect();
+    let total_utterances: u64 = utterances.iter().sum();
+    let mut prev_utterances = 0;
+
+    utterances
+        .into_iter()
+        .map(|utterances| {
+            let start = frame_at(0, total_frames, prev_utterances, total_utterances);
+            prev_utterances += utterances;
+            start
+        })
+        .collect()
+}
+
+/// The frame at which `utterances` of the `total_utterances` spoken over `frames` frames from
+/// `start_frame` have been said, never past the end of those frames.
+fn frame_at(start_frame: u32, frames: u32, utterances: u64, total_utterances: u64) -> u32 {
+    if total_utterances == 0 {
+        return start_frame;
+    }
+
+    let offset = (utterances.min(total_utterances) as f64 / total_utterances as f64 * frames as f64)
+        .round() as u32;
+    start_frame.saturating_add(offset.min(frames))
+}
+
+fn cues(text: &str, start_frame: u32, frames: u32) -> Vec<(u32, String)> {
+    // Counted the same way as the words below, each with the space after it
+    let utterances: u64 = text.split(' ').map(|word| text_utterances(word) + 1).sum();
+
+    let mut prev_utterances = 0;
+    let mut current: Vec<(&str, u64)> = Vec::default();
+    let mut parts = Vec::default();
+
+    for word in text.split(' ') {
+        let this_utterances = text_utterances(word) + 1;
+        let current_utterances: u64 = current.iter().map(|(_, u)| u).sum();
+
+        if current_utterances + this_utterances >= MAX_UTTERANCES && !current.is_empty() {
+            // Prefer ending the cue on a sentence boundary if one is close enough to the cap,
+            // carrying the rest of the sentence over into the next cue
+            let mut sentence_utterances = 0;
+            let mut split = current.len();
+            for (idx, (word, utterances)) in current.iter().enumerate() {
+                sentence_utterances += utterances;
+
+                if ends_sentence(word) && sentence_utterances >= MAX_UTTERANCES / 2 {
+                    split = idx + 1;
+                }
+            }
+
+            let carried = current.split_off(split);
+
+            parts.push((
+                frame_at(start_frame, frames, prev_utterances, utterances),
+                join_words(&current),
+            ));
+            prev_utterances += current.iter().map(|(_, u)| u).sum::<u64>();
+            current = carried;
+        }
+
+        current.push((word, this_utterances));
+    }
+
+    parts.push((
+        frame_at(start_frame, frames, prev_utterances, utterances),
+        join_words(&current),
+    ));
+
+    parts
+}
+
+fn text_utterances(text: &str) -> u64 {
+    text.graphemes(true).map(grapheme_utterances).sum()
+}
+
+fn grapheme_utterances(grapheme: &str) -> u64 {
+    match grapheme {
+        "█" => 0,
+        "." | "," | "?" => 2,
+        _ => 1,
+    }
+}
+
+fn ends_sentence(word: &str) -> bool {
+    word.trim_end_matches(['"', '\'', ')'])
+        .ends_with(['.', '?', '!'])
+}
+
+fn join_words(words: &[(&str, u64)]) -> String {
+    words
+        .iter()
+        .map(|(word, _)| *word)
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
 impl UiUpdater for SubtitleManager {
+    fn restart(&mut self) {
+        self.shown.clear();
+        if let Some(pop_in) = &mut self.pop_in {
+            pop_in.cue_start = None;
+        }
+    }
+
     fn update(&mut self, frame_idx: u32, ui: &mut VideoUI) {
         if let Some((_, s)) = self.parts.iter().find(|(frame, _)| *frame == frame_idx) {
-            if let Node::TextCentered { text, .. } = &mut ui.children[3].node {
-                *text = s.clone();
+            if s.is_empty() {
+                self.shown.clear();
+            } else {
+                self.shown.push_back(s.clone());
+                if self.shown.len() > self.rolling_cues {
+                    self.shown.pop_front();
+                }
             }
+
+            if let Node::Text { text, .. } = &mut ui.children[3].node {
+                *text = self.shown.iter().cloned().collect::<Vec<_>>().join("\n");
+                ui.mark_dirty();
+            }
+
+            if let Some(pop_in) = &mut self.pop_in {
+                pop_in.cue_start = Some(frame_idx);
+            }
+        }
+
+        if let Some(pop_in) = &mut self.pop_in {
+            pop_in.apply(frame_idx as f32, ui);
+        }
+    }
+
+    fn update_between(&mut self, frame_idx: u32, fraction: f32, ui: &mut VideoUI) {
+        if let Some(pop_in) = &mut self.pop_in {
+            pop_in.apply(frame_idx as f32 + fraction, ui);
+        }
+    }
+}
+
+impl PopIn {
+    /// Scales the subtitle for `time` frames into the video.
+    fn apply(&mut self, time: f32, ui: &mut VideoUI) {
+        let Some(cue_start) = self.cue_start else {
+            return;
+        };
+
+        if let Node::Text { scale, .. } = &mut ui.children[3].node {
+            let full_scale = *self.full_scale.get_or_insert(*scale);
+            let t = (time - cue_start as f32) / self.frames as f32;
+            let factor = (1.0 - self.amount).lerp(1.0, Easing::Elastic.apply(t));
+
+            let popped = rusttype::Scale {
+                x: full_scale.x * factor,
+                y: full_scale.y * factor,
+            };
+            if *scale != popped {
+                *scale = popped;
+                ui.mark_dirty();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use taffy::style::Style;
+
+    use super::*;
+    use crate::video_gen::ui::{TextAlign, WordBreak};
+
+    fn subtitle_ui() -> VideoUI {
+        let mut ui = VideoUI::default();
+        let font = ui.add_font(
+            "default",
+            rusttype::Font::try_from_bytes(include_bytes!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/assets/SpecialElite-Regular.ttf"
+            )))
+            .unwrap(),
+        );
+
+        let empty = || StyledNode {
+            node: Node::Container(Vec::default()),
+            style: Style::default(),
+            opacity: 1.0,
+            z_index: None,
+        };
+        ui.children = vec![empty(), empty(), empty(), empty()];
+        ui.children[3].node = Node::Text {
+            text: String::default(),
+            font,
+            scale: rusttype::Scale { x: 60.0, y: 60.0 },
+            line_height: 80,
+            color: [255, 255, 255, 255].into(),
+            word_break: WordBreak::Hyphenate,
+            align: TextAlign::Center,
+            outline: None,
+            auto_contrast: false,
+        };
+
+        ui
+    }
+
+    const SRT: &str =
+        "1\n00:00:00,000 --> 00:00:01,000\nOne\n\n2\n00:00:01,000 --> 00:00:02,000\nTwo\n\n\
+                       3\n00:00:02,000 --> 00:00:03,000\nThree\n";
+
+    #[test]
+    fn rolling_cues_keep_the_most_recent() {
+        let mut ui = subtitle_ui();
+        let mut subtitles = SubtitleManager::from_srt(SRT, 1)
+            .unwrap()
+            .with_rolling_cues(2);
+
+        for frame_idx in 0..=2 {
+            subtitles.update(frame_idx, &mut ui);
         }
+
+        let Node::Text { text, .. } = &ui.children[3].node else {
+            unreachable!()
+        };
+        assert_eq!(text, "Two\nThree");
+    }
+
+    #[test]
+    fn rolling_cues_are_limited_to_what_fits() {
+        let ui = subtitle_ui();
+        let subtitles = SubtitleManager::from_srt(SRT, 1).unwrap();
+
+        // Each cue is a single 80px line
+        assert_eq!(
+            subtitles.rolling_cues_that_fit(3, &ui, &ui.children[3], 880.0, 160.0),
+            2
+        );
+        assert_eq!(
+            subtitles.rolling_cues_that_fit(3, &ui, &ui.children[3], 880.0, 240.0),
+            3
+        );
+        assert_eq!(
+            subtitles.rolling_cues_that_fit(3, &ui, &ui.children[3], 880.0, 10.0),
+            1
+        );
+    }
+
+    #[test]
+    fn cues_pop_in_to_full_scale() {
+        let mut ui = subtitle_ui();
+        let mut subtitles = SubtitleManager::from_srt(SRT, 10)
+            .unwrap()
+            .with_pop_in(0.2, 4);
+
+        let mut scales = Vec::default();
+        for frame_idx in 0..20 {
+            subtitles.update(frame_idx, &mut ui);
+            let Node::Text { scale, .. } = &ui.children[3].node else {
+                unreachable!()
+            };
+            scales.push(scale.y);
+        }
+
+        // Each cue starts smaller and settles at full scale within the pop-in frames
+        for cue_start in [0, 10] {
+            assert!((47.0..60.0).contains(&scales[cue_start]));
+            assert!(scales[cue_start + 4..cue_start + 10]
+                .iter()
+                .all(|scale| *scale == 60.0));
+        }
+    }
+
+    #[test]
+    fn frame_at_stays_within_the_frames() {
+        assert_eq!(frame_at(10, 100, 0, 50), 10);
+        assert_eq!(frame_at(10, 100, 25, 50), 60);
+        assert_eq!(frame_at(10, 100, 50, 50), 110);
+        assert_eq!(frame_at(10, 100, 80, 50), 110);
+        assert_eq!(frame_at(10, 100, 5, 0), 10);
+        assert_eq!(frame_at(u32::MAX - 5, 100, 50, 50), u32::MAX);
+    }
+
+    #[test]
+    fn cues_of_very_long_text_are_in_order_and_in_bounds() {
+        let text = "Lorem ipsum dolor sit amet, consectetur. Adipiscing elit? ".repeat(20_000);
+
+        for (start, frames) in [(0, 18_000), (100, 1), (0, 0), (5, u32::MAX - 5)] {
+            let cue_frames: Vec<u32> = cues(&text, start, frames)
+                .into_iter()
+                .map(|(frame, _)| frame)
+                .collect();
+
+            assert!(cue_frames.len() > 1);
+            assert!(cue_frames.windows(2).all(|pair| pair[0] <= pair[1]));
+            assert!(cue_frames
+                .iter()
+                .all(|frame| (start..=start.saturating_add(frames)).contains(frame)));
+        }
+    }
+
+    #[test]
+    fn cues_end_on_sentence_boundaries() {
+        let text = "The door was sealed before anyone arrived. Nobody knows who sealed it. \
+                    Staff are told to keep their eyes on it at all times! Why it moves is unknown?";
+        let parts = cues(text, 0, 1000);
+
+        assert!(parts.len() > 1);
+        assert!(parts.iter().all(|(_, cue)| ends_sentence(cue)));
+        assert_eq!(
+            parts
+                .iter()
+                .map(|(_, cue)| cue.as_str())
+                .collect::<Vec<_>>()
+                .join(" "),
+            text
+        );
+
+        // Without a sentence boundary close to the cap the cue ends mid-sentence
+        let run_on = "and it moved again ".repeat(10);
+        let parts = cues(run_on.trim_end(), 0, 1000);
+        assert!(parts.len() > 1);
+        assert!(!ends_sentence(&parts[0].1));
+    }
+
+    #[test]
+    fn beats_start_in_order() {
+        let beats = vec!["a".repeat(50), "b".repeat(150), String::default()];
+        let starts = beat_start_frames(&beats, 1000);
+
+        assert_eq!(starts.len(), 3);
+        assert_eq!(starts[0], 0);
+        assert!(starts.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(starts.iter().all(|start| *start <= 1000));
+    }
+
+    /// The subtitle shown on each of the first `frames` frames.
+    fn shown_per_frame(mut subtitles: SubtitleManager, frames: u32) -> Vec<String> {
+        let mut ui = subtitle_ui();
+
+        (0..frames)
+            .map(|frame_idx| {
+                subtitles.update(frame_idx, &mut ui);
+                let Node::Text { text, .. } = &ui.children[3].node else {
+                    unreachable!()
+                };
+                text.clone()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn srt_counters_bom_and_crlf_are_optional() {
+        let plain = SubtitleManager::from_srt(SRT, 1).unwrap().parts;
+
+        let without_counters =
+            "00:00:00,000 --> 00:00:01,000\nOne\n\n00:00:01,000 --> 00:00:02,000\nTwo\n\n\
+             00:00:02,000 --> 00:00:03,000\nThree\n";
+        assert_eq!(
+            SubtitleManager::from_srt(without_counters, 1)
+                .unwrap()
+                .parts,
+            plain
+        );
+
+        let windows = format!("\u{feff}{}", SRT.replace('\n', "\r\n"));
+        assert_eq!(SubtitleManager::from_srt(&windows, 1).unwrap().parts, plain);
+    }
+
+    #[test]
+    fn gaps_between_srt_cues_clear_the_subtitle() {
+        let srt =
+            "1\n00:00:00,000 --> 00:00:01,000\nOne\n\n2\n00:00:02,000 --> 00:00:03,000\nTwo\n";
+        let subtitles = SubtitleManager::from_srt(srt, 1).unwrap();
+
+        assert_eq!(shown_per_frame(subtitles, 4), ["One", "", "Two", ""]);
+    }
+
+    #[test]
+    fn srt_cues_shorter_than_a_frame_still_get_one() {
+        // At 10fps both cues round to frame 1
+        let srt =
+            "1\n00:00:00,100 --> 00:00:00,120\nOne\n\n2\n00:00:00,130 --> 00:00:00,140\nTwo\n";
+        let subtitles = SubtitleManager::from_srt(srt, 10).unwrap();
+
+        assert_eq!(shown_per_frame(subtitles, 4), ["", "One", "Two", ""]);
+    }
+
+    #[test]
+    fn bad_srt_timestamps_are_an_error() {
+        assert!(SubtitleManager::from_srt("1\n00:00:00 --> 00:00:01,000\nOne\n", 30).is_err());
+        assert!(SubtitleManager::from_srt("1\n00:00:aa,000 --> 00:00:01,000\nOne\n", 30).is_err());
+        assert!(SubtitleManager::from_srt("1\nOne\n", 30).is_err());
     }
 }
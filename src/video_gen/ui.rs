@@ -1,14 +1,22 @@
-use std::{collections::HashMap, sync::atomic::AtomicUsize};
+use std::{
+    borrow::Cow,
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    sync::atomic::AtomicUsize,
+};
 
-use glam::UVec2;
+use glam::{IVec2, UVec2};
 use image::{Rgba, RgbaImage};
 use taffy::{
     geometry::Point,
     node::MeasureFunc,
     prelude::{Layout, Size},
-    style::{AvailableSpace, Position, Style},
+    style::{AvailableSpace, Dimension, LengthPercentage, LengthPercentageAuto, Position, Style},
     style_helpers::TaffyMaxContent,
 };
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::animation::Lerp;
 
 static IMAGE_HANDLE_IDX: AtomicUsize = AtomicUsize::new(0);
 
@@ -24,7 +32,7 @@ impl ImageHandle {
 #[derive(Debug, Default, Clone)]
 struct ImageStore {
     images: HashMap<ImageHandle, RgbaImage>,
-    resize_cache: HashMap<ImageHandle, HashMap<UVec2, RgbaImage>>,
+    resize_cache: HashMap<ImageHandle, HashMap<(UVec2, ImageFit), RgbaImage>>,
 }
 
 impl ImageStore {
@@ -38,33 +46,182 @@ impl ImageStore {
         &self.images[handle]
     }
 
-    pub fn get_resized(&mut self, handle: &ImageHandle, size: UVec2) -> &RgbaImage {
+    /// The image resized to go in a box of `size`. With [`ImageFit::Contain`] it can be smaller
+    /// than `size` on one side.
+    pub fn get_resized(&mut self, handle: &ImageHandle, size: UVec2, fit: ImageFit) -> &RgbaImage {
         let map = self
             .resize_cache
             .entry(*handle)
             .or_insert(HashMap::default());
 
-        map.entry(size).or_insert_with(|| {
-            image::imageops::resize(
-                &self.images[handle],
-                size.x,
-                size.y,
+        map.entry((size, fit)).or_insert_with(|| {
+            let img = &self.images[handle];
+            let scale_x = size.x as f32 / img.width() as f32;
+            let scale_y = size.y as f32 / img.height() as f32;
+
+            let scale = match fit {
+                ImageFit::Fill => {
+                    return image::imageops::resize(
+                        img,
+                        size.x,
+                        size.y,
+                        image::imageops::FilterType::Lanczos3,
+                    )
+                }
+                ImageFit::Contain => scale_x.min(scale_y),
+                ImageFit::Cover => scale_x.max(scale_y),
+            };
+
+            let resized = image::imageops::resize(
+                img,
+                ((img.width() as f32 * scale).round() as u32).max(1),
+                ((img.height() as f32 * scale).round() as u32).max(1),
                 image::imageops::FilterType::Lanczos3,
-            )
+            );
+
+            match fit {
+                // Keep the middle of the image, cutting off whatever sticks out of the box
+                ImageFit::Cover => {
+                    let width = size.x.min(resized.width());
+                    let height = size.y.min(resized.height());
+
+                    image::imageops::crop_imm(
+                        &resized,
+                        (resized.width() - width) / 2,
+                        (resized.height() - height) / 2,
+                        width,
+                        height,
+                    )
+                    .to_image()
+                }
+                _ => resized,
+            }
         })
     }
+
+    /// An image already resized by [`ImageStore::get_resized`].
+    fn resized(&self, handle: &ImageHandle, size: UVec2, fit: ImageFit) -> &RgbaImage {
+        &self.resize_cache[handle][&(size, fit)]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FontHandle(usize);
+
+/// Fonts registered on a [`VideoUI`], each font is stored once and nodes refer to it by handle.
+#[derive(Debug, Default, Clone)]
+struct FontStore {
+    fonts: Vec<rusttype::Font<'static>>,
+    names: HashMap<String, FontHandle>,
+}
+
+impl FontStore {
+    pub fn add(&mut self, name: String, font: rusttype::Font<'static>) -> FontHandle {
+        let handle = FontHandle(self.fonts.len());
+        self.fonts.push(font);
+        self.names.insert(name, handle);
+        handle
+    }
+
+    pub fn get(&self, handle: &FontHandle) -> &rusttype::Font<'static> {
+        &self.fonts[handle.0]
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WordBreak {
+    /// Words are never split, so a long word overflows its box
+    #[default]
+    Never,
+    /// Words wider than the box are split between characters
+    Break,
+    /// Like `Break`, but a hyphen is added at the end of each split piece
+    Hyphenate,
+}
+
+/// The colors used across a video.
+#[derive(Debug, Clone, Copy)]
+pub struct Theme {
+    pub background: Rgba<u8>,
+    pub text: Rgba<u8>,
+    /// Used for the classification labels
+    pub accent: Rgba<u8>,
+    pub subtitle: Rgba<u8>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            background: [24, 24, 24, 255].into(),
+            text: [255, 255, 255, 255].into(),
+            accent: [255, 255, 255, 255].into(),
+            subtitle: [255, 255, 255, 255].into(),
+        }
+    }
+}
+
+/// How an image is fit into the box laid out for it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ImageFit {
+    /// Stretched to the box, ignoring its aspect ratio
+    #[default]
+    Fill,
+    /// As large as it can be while staying inside the box, centered in it
+    Contain,
+    /// Covers the whole box, cropping off the sides that don't fit
+    Cover,
+}
+
+/// A blurred silhouette drawn under an image node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Shadow {
+    /// How far the shadow is moved from the image, in pixels
+    pub offset: IVec2,
+    /// Radius of the box blur softening the edges, 0 for a hard shadow
+    pub blur: u32,
+    pub color: Rgba<u8>,
+}
+
+/// Where each line of a text node sits horizontally in its box.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextAlign {
+    Left,
+    #[default]
+    Center,
+    Right,
 }
 
 #[derive(Debug, Clone)]
 pub enum Node {
+    Text {
+        text: String,
+        font: FontHandle,
+        scale: rusttype::Scale,
+        line_height: u32,
+        color: Rgba<u8>,
+        word_break: WordBreak,
+        align: TextAlign,
+        /// Color and thickness of a stroke drawn around the text
+        outline: Option<(Rgba<u8>, u32)>,
+        /// Ignores `color` and `outline` and draws black or white text, outlined in the other,
+        /// depending on which stands out more from what is behind the text
+        auto_contrast: bool,
+    },
+    #[deprecated(note = "use `Node::Text` with `TextAlign::Center`")]
     TextCentered {
         text: String,
-        font: rusttype::Font<'static>,
+        font: FontHandle,
         scale: rusttype::Scale,
         line_height: u32,
         color: Rgba<u8>,
+        word_break: WordBreak,
+        auto_contrast: bool,
+    },
+    Image {
+        image: ImageHandle,
+        fit: ImageFit,
+        shadow: Option<Shadow>,
     },
-    Image(ImageHandle),
     Container(Vec<StyledNode>),
 }
 
@@ -72,63 +229,52 @@ pub enum Node {
 pub struct StyledNode {
     pub node: Node,
     pub style: Style,
+    /// From 0.0 for invisible to 1.0 for fully opaque, doesn't carry over to children
+    pub opacity: f32,
+    /// Draws the node above everything with a lower z-index, nodes without one are stacked by
+    /// their layout order
+    pub z_index: Option<i32>,
 }
 
 impl StyledNode {
+    #[allow(deprecated)]
     fn process(
         &self,
         taffy: &mut taffy::Taffy,
         parent: taffy::prelude::Node,
         store: &ImageStore,
+        fonts: &FontStore,
     ) -> anyhow::Result<(taffy::prelude::Node, Option<&Vec<StyledNode>>)> {
         let mut children = None;
 
         let node = match &self.node {
-            Node::TextCentered {
+            Node::Text {
                 text,
                 font,
                 scale,
                 line_height,
+                word_break,
+                ..
+            }
+            | Node::TextCentered {
+                text,
+                font,
+                scale,
+                line_height,
+                word_break,
                 ..
             } => {
                 let text = text.clone();
-                let font = font.clone();
-                let scale = scale.clone();
-                let line_height = line_height.clone();
+                let font = fonts.get(font).clone();
+                let scale = *scale;
+                let line_height = *line_height;
+                let word_break = *word_break;
 
                 let calculate_text_size_for_width = move |width: f32| -> Size<f32> {
-                    let words = text.split(' ');
-                    let mut lines = Vec::default();
-
-                    let mut current_line = String::default();
-                    let mut max_width = 0.0;
-                    for word in words {
-                        let mut temp_line = current_line.clone();
-
-                        temp_line.push_str(word);
-
-                        let current_width = font
-                            .layout(&temp_line, scale, rusttype::point(0.0, 0.0))
-                            .last()
-                            .map(|g| g.position().x + g.unpositioned().h_metrics().advance_width)
-                            .unwrap_or(0.0);
-
-                        if current_width > width && !current_line.is_empty() {
-                            lines.push(current_line);
-                            current_line = word.to_owned();
-                        } else {
-                            if current_width > max_width {
-                                max_width = current_width;
-                            }
-
-                            current_line = temp_line;
-                        }
-
-                        current_line.push(' ');
-                    }
+                    let lines = wrap_text(&text, &font, scale, width, word_break);
 
                     Size {
-                        width: max_width,
+                        width: lines.iter().map(|(_, width)| *width).fold(0.0, f32::max),
                         height: (line_height as usize * lines.len()) as f32,
                     }
                 };
@@ -154,27 +300,38 @@ impl StyledNode {
                     )),
                 )?
             }
-            Node::Image(img) => {
-                let img = store.get(img);
+            Node::Image { image, .. } => {
+                let img = store.get(image);
                 let (width, height) = (img.width(), img.height());
 
+                // A box with a known height gets its width from the aspect ratio too, how the
+                // image is fit into a box of any other shape is up to its `ImageFit`
                 taffy.new_leaf_with_measure(
                     self.style.clone(),
                     MeasureFunc::Boxed(Box::new(
                         move |size: Size<Option<f32>>, available: Size<AvailableSpace>| match (
                             size.width,
+                            size.height,
                             available.width,
                         ) {
-                            (None, AvailableSpace::Definite(ava_width)) => Size {
+                            (Some(act_width), Some(act_height), _) => Size {
+                                width: act_width,
+                                height: act_height,
+                            },
+                            (None, Some(act_height), _) => Size {
+                                width: (act_height / height as f32) * width as f32,
+                                height: act_height,
+                            },
+                            (None, None, AvailableSpace::Definite(ava_width)) => Size {
                                 width: ava_width,
                                 height: (ava_width / width as f32) * height as f32,
                             },
-                            (None, AvailableSpace::MinContent) => Size::ZERO,
-                            (None, AvailableSpace::MaxContent) => Size {
+                            (None, None, AvailableSpace::MinContent) => Size::ZERO,
+                            (None, None, AvailableSpace::MaxContent) => Size {
                                 width: width as f32,
                                 height: height as f32,
                             },
-                            (Some(act_width), _) => Size {
+                            (Some(act_width), None, _) => Size {
                                 width: act_width,
                                 height: (act_width / width as f32) * height as f32,
                             },
@@ -194,156 +351,693 @@ impl StyledNode {
         Ok((node, children))
     }
 
-    fn into_draw_command(
-        &self,
-        layout: &Layout,
-        store: &mut ImageStore,
-    ) -> Option<(DrawCommand<'_>, u32)> {
+    /// The node and its children at `scale` times their size.
+    #[allow(deprecated)]
+    fn scaled(&self, scale: f32) -> StyledNode {
+        let scale_u32 = |value: u32| (value as f32 * scale).round() as u32;
+        let scale_font = |font_scale: &rusttype::Scale| rusttype::Scale {
+            x: font_scale.x * scale,
+            y: font_scale.y * scale,
+        };
+
+        let mut node = self.node.clone();
+        match &mut node {
+            Node::Text {
+                scale: font_scale,
+                line_height,
+                outline,
+                ..
+            } => {
+                *font_scale = scale_font(font_scale);
+                *line_height = scale_u32(*line_height).max(1);
+                if let Some((_, thickness)) = outline {
+                    *thickness = scale_u32(*thickness).max(1);
+                }
+            }
+            Node::TextCentered {
+                scale: font_scale,
+                line_height,
+                ..
+            } => {
+                *font_scale = scale_font(font_scale);
+                *line_height = scale_u32(*line_height).max(1);
+            }
+            Node::Image {
+                shadow: Some(shadow),
+                ..
+            } => {
+                shadow.offset = (shadow.offset.as_vec2() * scale).round().as_ivec2();
+                shadow.blur = scale_u32(shadow.blur);
+            }
+            Node::Image { shadow: None, .. } => {}
+            Node::Container(children) => {
+                *children = children.iter().map(|child| child.scaled(scale)).collect();
+            }
+        }
+
+        StyledNode {
+            node,
+            style: scaled_style(&self.style, scale),
+            opacity: self.opacity,
+            z_index: self.z_index,
+        }
+    }
+
+    /// Changes whenever anything that can move or resize the node changes, its style or what it
+    /// is measured from.
+    #[allow(deprecated)]
+    fn layout_key(&self, hasher: &mut DefaultHasher) {
+        std::mem::discriminant(&self.node).hash(hasher);
+
+        // `Style` has no `Hash`, its debug output has every field in it
+        use std::fmt::Write;
+        write!(HashWriter(hasher), "{:?}", self.style).unwrap();
+
         match &self.node {
+            Node::Text {
+                text,
+                font,
+                scale,
+                line_height,
+                word_break,
+                ..
+            }
+            | Node::TextCentered {
+                text,
+                font,
+                scale,
+                line_height,
+                word_break,
+                ..
+            } => {
+                (text, font, line_height, word_break).hash(hasher);
+                (scale.x.to_bits(), scale.y.to_bits()).hash(hasher);
+            }
+            Node::Image { image, .. } => image.hash(hasher),
+            Node::Container(_) => {}
+        }
+    }
+
+    /// Changes whenever anything about how the node is drawn at `layout` changes.
+    #[allow(deprecated)]
+    fn fingerprint(&self, layout: &Layout) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        std::mem::discriminant(&self.node).hash(&mut hasher);
+
+        match &self.node {
+            Node::Text {
+                text,
+                font,
+                scale,
+                line_height,
+                color,
+                word_break,
+                align,
+                outline,
+                auto_contrast,
+            } => {
+                (text, font, line_height, color, word_break, align, outline).hash(&mut hasher);
+                (scale.x.to_bits(), scale.y.to_bits(), auto_contrast).hash(&mut hasher);
+            }
             Node::TextCentered {
                 text,
                 font,
                 scale,
                 line_height,
                 color,
+                word_break,
+                auto_contrast,
             } => {
-                let words = text.split(' ');
-                let mut lines = Vec::default();
-
-                let mut current_line = String::default();
-                let mut current_width = 0.0;
-                for word in words {
-                    let mut temp_line = current_line.clone();
-
-                    temp_line.push_str(word);
-
-                    let width = font
-                        .layout(&temp_line, *scale, rusttype::point(0.0, 0.0))
-                        .last()
-                        .map(|g| g.position().x + g.unpositioned().h_metrics().advance_width)
-                        .unwrap_or(0.0);
-
-                    if width > layout.size.width && !current_line.is_empty() {
-                        let x_offset =
-                            (layout.size.width - current_width) / 2.0 + layout.location.x;
-                        lines.push((
+                (text, font, line_height, color, word_break, auto_contrast).hash(&mut hasher);
+                (scale.x.to_bits(), scale.y.to_bits()).hash(&mut hasher);
+            }
+            Node::Image { image, fit, shadow } => (image, fit, shadow).hash(&mut hasher),
+            Node::Container(_) => {}
+        }
+
+        (self.opacity.to_bits(), self.z_index).hash(&mut hasher);
+        for value in [
+            layout.location.x,
+            layout.location.y,
+            layout.size.width,
+            layout.size.height,
+        ] {
+            value.to_bits().hash(&mut hasher);
+        }
+
+        hasher.finish()
+    }
+
+    #[allow(deprecated)]
+    fn into_draw_command<'c>(
+        &'c self,
+        layout: &Layout,
+        store: &'c ImageStore,
+        fonts: &'c FontStore,
+    ) -> Vec<(DrawCommand<'c>, u32)> {
+        let opacity = self.opacity.clamp(0.0, 1.0);
+        if opacity == 0.0 {
+            return Vec::default();
+        }
+
+        match &self.node {
+            Node::Text {
+                text,
+                font,
+                scale,
+                line_height,
+                color,
+                word_break,
+                auto_contrast,
+                ..
+            }
+            | Node::TextCentered {
+                text,
+                font,
+                scale,
+                line_height,
+                color,
+                word_break,
+                auto_contrast,
+            } => {
+                let (align, outline) = match &self.node {
+                    Node::Text { align, outline, .. } => (*align, *outline),
+                    _ => (TextAlign::Center, None),
+                };
+
+                let font = fonts.get(font);
+                let lines = wrap_text(text, font, *scale, layout.size.width, *word_break)
+                    .into_iter()
+                    .enumerate()
+                    .map(|(idx, (line, width))| {
+                        let x_offset = match align {
+                            TextAlign::Left => 0.0,
+                            TextAlign::Center => (layout.size.width - width) / 2.0,
+                            TextAlign::Right => layout.size.width - width,
+                        } + layout.location.x;
+
+                        (
                             UVec2 {
                                 x: x_offset as u32,
-                                y: ((*line_height * lines.len() as u32) as f32 + layout.location.y)
-                                    as u32,
+                                y: ((*line_height * idx as u32) as f32 + layout.location.y) as u32,
                             },
-                            current_line,
-                        ));
-                        current_line = word.to_owned();
-                        current_width = font
-                            .layout(&current_line, *scale, rusttype::point(0.0, 0.0))
-                            .last()
-                            .map(|g| g.position().x + g.unpositioned().h_metrics().advance_width)
-                            .unwrap_or(0.0);
-                    } else {
-                        current_line = temp_line;
-                        current_width = width;
-                    }
-
-                    current_line.push(' ');
-                }
-
-                let x_offset = (layout.size.width - current_width) / 2.0 + layout.location.x;
-                lines.push((
-                    UVec2 {
-                        x: x_offset as u32,
-                        y: ((*line_height * lines.len() as u32) as f32 + layout.location.y) as u32,
-                    },
-                    current_line,
-                ));
+                            line,
+                        )
+                    })
+                    .collect();
 
-                Some((
-                    DrawCommand::TextCentered {
+                vec![(
+                    DrawCommand::Text {
                         lines,
                         font,
                         scale: *scale,
                         color: *color,
+                        outline,
+                        opacity,
+                        contrast_region: auto_contrast.then_some((
+                            UVec2 {
+                                x: layout.location.x.max(0.0) as u32,
+                                y: layout.location.y.max(0.0) as u32,
+                            },
+                            UVec2 {
+                                x: layout.size.width as u32,
+                                y: layout.size.height as u32,
+                            },
+                        )),
                     },
                     layout.order,
-                ))
-            }
-            Node::Image(img) => {
-                let img = store.get_resized(
-                    img,
-                    UVec2 {
-                        x: layout.size.width as u32,
-                        y: layout.size.height as u32,
-                    },
-                );
+                )]
+            }
+            Node::Image { image, fit, shadow } => {
+                let size = box_size(layout);
+                let img = store.resized(image, size, *fit);
 
-                Some((
-                    DrawCommand::Image {
-                        image: img.clone(),
-                        position: UVec2 {
-                            x: layout.location.x as u32,
-                            y: layout.location.y as u32,
+                let mut faded = Cow::Borrowed(img);
+                if opacity < 1.0 {
+                    for pixel in faded.to_mut().pixels_mut() {
+                        pixel.0[3] = (pixel.0[3] as f32 * opacity).round() as u8;
+                    }
+                }
+
+                let position = UVec2 {
+                    x: layout.location.x as u32 + (size.x - img.width()) / 2,
+                    y: layout.location.y as u32 + (size.y - img.height()) / 2,
+                };
+
+                // The shadow is queued first so it is drawn underneath the image
+                let mut commands = Vec::default();
+                if let Some(shadow) = shadow {
+                    commands.push((
+                        DrawCommand::Shadow {
+                            image: shadow_image(&faded, shadow),
+                            position: position.as_ivec2() + shadow.offset
+                                - IVec2::splat(shadow.blur as i32),
                         },
+                        layout.order,
+                    ));
+                }
+                commands.push((
+                    DrawCommand::Image {
+                        image: faded,
+                        position,
                     },
                     layout.order,
-                ))
+                ));
+
+                commands
             }
-            Node::Container(_) => None,
+            Node::Container(_) => Vec::default(),
         }
     }
 }
 
+/// `style` with every length in points `scale` times as long, percentages are left as they are.
+fn scaled_style(style: &Style, scale: f32) -> Style {
+    let dimension = |value: Dimension| match value {
+        Dimension::Points(points) => Dimension::Points(points * scale),
+        value => value,
+    };
+    let length = |value: LengthPercentage| match value {
+        LengthPercentage::Points(points) => LengthPercentage::Points(points * scale),
+        value => value,
+    };
+    let length_auto = |value: LengthPercentageAuto| match value {
+        LengthPercentageAuto::Points(points) => LengthPercentageAuto::Points(points * scale),
+        value => value,
+    };
+
+    Style {
+        inset: style.inset.map(length_auto),
+        size: style.size.map(dimension),
+        min_size: style.min_size.map(dimension),
+        max_size: style.max_size.map(dimension),
+        margin: style.margin.map(length_auto),
+        padding: style.padding.map(length),
+        border: style.border.map(length),
+        gap: style.gap.map(length),
+        flex_basis: dimension(style.flex_basis),
+        ..style.clone()
+    }
+}
+
+/// The size of a node's box in whole pixels.
+fn box_size(layout: &Layout) -> UVec2 {
+    UVec2 {
+        x: layout.size.width as u32,
+        y: layout.size.height as u32,
+    }
+}
+
+fn text_width(font: &rusttype::Font, scale: rusttype::Scale, text: &str) -> f32 {
+    font.layout(text, scale, rusttype::point(0.0, 0.0))
+        .last()
+        .map(|g| g.position().x + g.unpositioned().h_metrics().advance_width)
+        .unwrap_or(0.0)
+}
+
+/// Splits `text` into lines no wider than `width`, returning each line with its rendered width.
+fn wrap_text(
+    text: &str,
+    font: &rusttype::Font,
+    scale: rusttype::Scale,
+    width: f32,
+    word_break: WordBreak,
+) -> Vec<(String, f32)> {
+    text.split('\n')
+        .flat_map(|line| wrap_line(line, font, scale, width, word_break))
+        .collect()
+}
+
+fn wrap_line(
+    text: &str,
+    font: &rusttype::Font,
+    scale: rusttype::Scale,
+    width: f32,
+    word_break: WordBreak,
+) -> Vec<(String, f32)> {
+    let mut lines = Vec::default();
+
+    let mut current_line = String::default();
+    let mut current_width = 0.0;
+    for word in text.split(' ') {
+        let mut word = word.to_owned();
+
+        if word_break != WordBreak::Never && text_width(font, scale, &word) > width {
+            if !current_line.is_empty() {
+                lines.push((current_line, current_width));
+                current_line = String::default();
+                current_width = 0.0;
+            }
+
+            while let Some((piece, rest)) = split_word(&word, font, scale, width, word_break) {
+                let piece_width = text_width(font, scale, &piece);
+                lines.push((piece, piece_width));
+                word = rest;
+            }
+        }
+
+        let mut temp_line = current_line.clone();
+        if !temp_line.is_empty() {
+            temp_line.push(' ');
+        }
+        temp_line.push_str(&word);
+
+        let temp_width = text_width(font, scale, &temp_line);
+
+        if temp_width > width && !current_line.is_empty() {
+            lines.push((current_line, current_width));
+            current_width = text_width(font, scale, &word);
+            current_line = word;
+        } else {
+            current_line = temp_line;
+            current_width = temp_width;
+        }
+    }
+
+    lines.push((current_line, current_width));
+
+    lines
+}
+
+/// Splits off the longest head of an overflowing `word` that fits in `width`. Both halves keep at
+/// least two graphemes, so nothing is split when that isn't possible.
+fn split_word(
+    word: &str,
+    font: &rusttype::Font,
+    scale: rusttype::Scale,
+    width: f32,
+    word_break: WordBreak,
+) -> Option<(String, String)> {
+    if text_width(font, scale, word) <= width {
+        return None;
+    }
+
+    // Split between grapheme clusters so combining marks and emoji sequences stay together
+    let boundaries = word
+        .grapheme_indices(true)
+        .map(|(idx, _)| idx)
+        .collect::<Vec<_>>();
+
+    boundaries
+        .iter()
+        .skip(2)
+        .take(boundaries.len().saturating_sub(3))
+        .rev()
+        .map(|idx| {
+            let mut piece = word[..*idx].to_owned();
+            if word_break == WordBreak::Hyphenate {
+                piece.push('-');
+            }
+
+            (piece, word[*idx..].to_owned())
+        })
+        .find(|(piece, _)| text_width(font, scale, piece) <= width)
+}
+
 pub enum DrawCommand<'c> {
     FillBackground(Rgba<u8>),
-    TextCentered {
+    Text {
         lines: Vec<(UVec2, String)>,
         font: &'c rusttype::Font<'c>,
         scale: rusttype::Scale,
         color: Rgba<u8>,
+        outline: Option<(Rgba<u8>, u32)>,
+        opacity: f32,
+        /// Position and size of the area to pick a contrasting color against
+        contrast_region: Option<(UVec2, UVec2)>,
     },
     Image {
-        image: RgbaImage,
+        image: Cow<'c, RgbaImage>,
         position: UVec2,
     },
+    Shadow {
+        image: RgbaImage,
+        position: IVec2,
+    },
 }
 
+const OUTLINE_DIRECTIONS: [(i32, i32); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
 impl<'c> DrawCommand<'c> {
     pub fn apply(&self, frame: &mut RgbaImage) -> anyhow::Result<()> {
+        self.apply_at(frame, IVec2::ZERO)
+    }
+
+    /// Draws the command onto `frame` as if `frame` was the part of a larger frame starting at
+    /// `origin`.
+    pub fn apply_at(&self, frame: &mut RgbaImage, origin: IVec2) -> anyhow::Result<()> {
         match self {
             DrawCommand::FillBackground(color) => {
                 frame.pixels_mut().for_each(|pixel| *pixel = *color);
             }
-            DrawCommand::TextCentered {
+            DrawCommand::Text {
                 lines,
                 font,
                 scale,
                 color,
+                outline,
+                opacity,
+                contrast_region,
             } => {
+                let (color, outline) = match contrast_region {
+                    Some((position, size)) => {
+                        let position = (position.as_ivec2() - origin).max(IVec2::ZERO).as_uvec2();
+                        let (color, outline) = contrasting_text_color(frame, position, *size);
+                        (color, Some((outline, (scale.y / 30.0).max(1.0) as u32)))
+                    }
+                    None => (*color, *outline),
+                };
+
+                // Text is drawn without looking at alpha, so translucent text is drawn on a copy
+                // that is then blended back in
+                let mut faded = (*opacity < 1.0).then(|| frame.clone());
+                let original = frame;
+                let frame = faded.as_mut().unwrap_or(&mut *original);
+
                 for (position, line) in lines {
-                    imageproc::drawing::draw_text_mut(
-                        frame,
-                        *color,
-                        position.x as i32,
-                        position.y as i32,
-                        *scale,
-                        font,
-                        line,
-                    );
+                    let IVec2 { x, y } = position.as_ivec2() - origin;
+
+                    // The text is drawn shifted in all 8 directions, once per pixel of thickness,
+                    // underneath the fill
+                    if let Some((outline, thickness)) = outline {
+                        for distance in 1..=thickness as i32 {
+                            for (dx, dy) in OUTLINE_DIRECTIONS {
+                                imageproc::drawing::draw_text_mut(
+                                    frame,
+                                    outline,
+                                    x + dx * distance,
+                                    y + dy * distance,
+                                    *scale,
+                                    font,
+                                    line,
+                                );
+                            }
+                        }
+                    }
+
+                    imageproc::drawing::draw_text_mut(frame, color, x, y, *scale, font, line);
+                }
+
+                if let Some(faded) = faded {
+                    for (pixel, faded) in original.pixels_mut().zip(faded.pixels()) {
+                        *pixel = pixel.lerp(*faded, *opacity);
+                    }
                 }
             }
             DrawCommand::Image { image, position } => {
-                image::imageops::overlay(frame, image, position.x as i64, position.y as i64);
+                let IVec2 { x, y } = position.as_ivec2() - origin;
+                image::imageops::overlay(frame, image.as_ref(), x as i64, y as i64);
+            }
+            DrawCommand::Shadow { image, position } => {
+                let IVec2 { x, y } = *position - origin;
+                image::imageops::overlay(frame, image, x as i64, y as i64);
             }
         }
 
         Ok(())
     }
+
+    /// The area of a `canvas` sized frame the command can draw to.
+    pub fn area(&self, canvas: UVec2) -> DamageRect {
+        match self {
+            DrawCommand::FillBackground(_) => {
+                DamageRect::clipped(IVec2::ZERO, canvas.as_ivec2(), canvas)
+            }
+            DrawCommand::Text {
+                lines,
+                font,
+                scale,
+                outline,
+                contrast_region,
+                ..
+            } => {
+                // Glyphs can reach a little past the line they are on, on top of the outline
+                let thickness = match contrast_region {
+                    Some(_) => (scale.y / 30.0).max(1.0) as u32,
+                    None => outline.map_or(0, |(_, thickness)| thickness),
+                };
+                let pad = IVec2::splat(thickness as i32 + (scale.y / 4.0).ceil() as i32);
+
+                let mut min = IVec2::splat(i32::MAX);
+                let mut max = IVec2::splat(i32::MIN);
+                for (position, line) in lines {
+                    let size = IVec2::new(
+                        text_width(font, *scale, line).ceil() as i32,
+                        scale.y.ceil() as i32,
+                    );
+                    min = min.min(position.as_ivec2() - pad);
+                    max = max.max(position.as_ivec2() + size + pad);
+                }
+
+                DamageRect::clipped(min, max.max(min), canvas)
+            }
+            DrawCommand::Image { image, position } => {
+                let size = UVec2::new(image.width(), image.height());
+                DamageRect::clipped(position.as_ivec2(), (*position + size).as_ivec2(), canvas)
+            }
+            DrawCommand::Shadow { image, position } => {
+                let size = IVec2::new(image.width() as i32, image.height() as i32);
+                DamageRect::clipped(*position, *position + size, canvas)
+            }
+        }
+    }
+}
+
+/// The alpha of `image` blurred and tinted with the color of `shadow`, padded by the blur radius on
+/// every side so the blur isn't cut off.
+fn shadow_image(image: &RgbaImage, shadow: &Shadow) -> RgbaImage {
+    let pad = shadow.blur;
+    let mut mask = image::GrayImage::new(image.width() + 2 * pad, image.height() + 2 * pad);
+    for (x, y, pixel) in image.enumerate_pixels() {
+        mask.put_pixel(x + pad, y + pad, image::Luma([pixel.0[3]]));
+    }
+
+    if pad > 0 {
+        mask = imageproc::filter::box_filter(&mask, pad, pad);
+    }
+
+    let [r, g, b, a] = shadow.color.0;
+    RgbaImage::from_fn(mask.width(), mask.height(), |x, y| {
+        let alpha = mask.get_pixel(x, y).0[0] as u32 * a as u32 / 255;
+        Rgba([r, g, b, alpha as u8])
+    })
+}
+
+/// A blurred copy of `image` with its colors scaled by `brightness`, to sit behind text without
+/// drawing attention.
+pub fn backdrop(image: &RgbaImage, blur: f32, brightness: f32) -> RgbaImage {
+    let mut backdrop = image::imageops::blur(image, blur);
+
+    for pixel in backdrop.pixels_mut() {
+        for channel in &mut pixel.0[..3] {
+            *channel = (*channel as f32 * brightness).round() as u8;
+        }
+    }
+
+    backdrop
+}
+
+/// The text and outline colors, black or white, that stand out most against the average luminance
+/// of `frame` in the area at `position` with `size`.
+pub fn contrasting_text_color(
+    frame: &RgbaImage,
+    position: UVec2,
+    size: UVec2,
+) -> (Rgba<u8>, Rgba<u8>) {
+    let black = Rgba([0, 0, 0, 255]);
+    let white = Rgba([255, 255, 255, 255]);
+
+    let x_end = (position.x + size.x).min(frame.width());
+    let y_end = (position.y + size.y).min(frame.height());
+
+    let (mut sum, mut count) = (0.0, 0);
+    for y in position.y..y_end {
+        for x in position.x..x_end {
+            let [r, g, b, _] = frame.get_pixel(x, y).0;
+            sum += 0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32;
+            count += 1;
+        }
+    }
+
+    if count != 0 && sum / count as f32 > 140.0 {
+        (black, white)
+    } else {
+        (white, black)
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct VideoUI {
     pub children: Vec<StyledNode>,
     pub background_color: Rgba<u8>,
+    /// Covers the whole frame behind every node, on top of `background_color`
+    pub background_image: Option<ImageHandle>,
     image_store: ImageStore,
+    font_store: FontStore,
+    layout_cache: Option<LayoutCache>,
+    last_drawn: Option<DrawnFrame>,
+    /// Every node is drawn this many times its size, see [`VideoUI::with_render_scale`]
+    render_scale: f32,
+    /// `children` at `render_scale` as of the last render
+    scaled_children: Vec<StyledNode>,
+}
+
+/// An area of a frame, clipped to the frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DamageRect {
+    pub position: UVec2,
+    pub size: UVec2,
+}
+
+impl DamageRect {
+    /// The part of the area from `min` to `max` that is inside a `canvas` sized frame.
+    fn clipped(min: IVec2, max: IVec2, canvas: UVec2) -> Self {
+        let min = min.clamp(IVec2::ZERO, canvas.as_ivec2()).as_uvec2();
+        let max = max.clamp(IVec2::ZERO, canvas.as_ivec2()).as_uvec2();
+
+        DamageRect {
+            position: min,
+            size: max.max(min) - min,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.size.x == 0 || self.size.y == 0
+    }
+
+    fn intersects(&self, other: &DamageRect) -> bool {
+        let (min, max) = (self.position, self.position + self.size);
+        let (other_min, other_max) = (other.position, other.position + other.size);
+
+        !self.is_empty()
+            && !other.is_empty()
+            && min.cmplt(other_max).all()
+            && other_min.cmplt(max).all()
+    }
+}
+
+/// What the last render drew, as the hash and area of every draw command.
+#[derive(Debug, Clone)]
+struct DrawnFrame {
+    canvas: UVec2,
+    commands: Vec<(u64, DamageRect)>,
+}
+
+/// Where every node was laid out by the last render, in the order [`pre_order`] visits them.
+#[derive(Debug, Clone)]
+struct LayoutCache {
+    canvas: UVec2,
+    key: u64,
+    layouts: Vec<Layout>,
 }
 
 impl VideoUI {
@@ -351,59 +1045,322 @@ impl VideoUI {
         VideoUI {
             children,
             background_color,
+            background_image: None,
             image_store: ImageStore::default(),
+            font_store: FontStore::default(),
+            layout_cache: None,
+            last_drawn: None,
+            render_scale: 1.0,
+            scaled_children: Vec::default(),
         }
     }
 
+    /// Draws every node `scale` times its size, with its sizes, margins, text and shadows scaled
+    /// to match, so the same UI can be rendered on a smaller frame with the same layout. Updaters
+    /// keep working with the unscaled sizes.
+    pub fn with_render_scale(mut self, scale: f32) -> Self {
+        self.render_scale = scale;
+        self.mark_dirty();
+        self
+    }
+
+    /// Makes the next render lay the nodes out again. Changes to a node's style, text, font size or
+    /// image are noticed without this, it is only needed when something else moves a node, like
+    /// an image being swapped out under the same handle.
+    pub fn mark_dirty(&mut self) {
+        self.layout_cache = None;
+    }
+
     pub fn add(&mut self, img: RgbaImage) -> ImageHandle {
         self.image_store.add(img)
     }
 
+    pub fn image(&self, handle: &ImageHandle) -> &RgbaImage {
+        self.image_store.get(handle)
+    }
+
+    /// Registers `font` under `name`, registering another font with the same name replaces it for
+    /// later lookups.
+    pub fn add_font(
+        &mut self,
+        name: impl Into<String>,
+        font: rusttype::Font<'static>,
+    ) -> FontHandle {
+        self.font_store.add(name.into(), font)
+    }
+
+    pub fn font(&self, name: &str) -> Option<FontHandle> {
+        self.font_store.names.get(name).copied()
+    }
+
+    /// How tall `node` is once its text is wrapped to `width`, `None` if it isn't a text node.
+    #[allow(deprecated)]
+    pub fn text_height(&self, node: &StyledNode, width: f32) -> Option<f32> {
+        match &node.node {
+            Node::Text {
+                text,
+                font,
+                scale,
+                line_height,
+                word_break,
+                ..
+            }
+            | Node::TextCentered {
+                text,
+                font,
+                scale,
+                line_height,
+                word_break,
+                ..
+            } => {
+                let lines = wrap_text(text, self.font_store.get(font), *scale, width, *word_break);
+                Some((*line_height as usize * lines.len()) as f32)
+            }
+            _ => None,
+        }
+    }
+
+    /// Draws the whole UI onto `frame`.
     pub fn render(&mut self, frame: &mut RgbaImage) -> anyhow::Result<()> {
-        let mut taffy = taffy::Taffy::new();
+        let canvas = UVec2::new(frame.width(), frame.height());
+        let commands = self.draw_commands(canvas)?;
+        let drawn = drawn_areas(&commands, canvas);
 
-        let root = taffy.new_leaf(Style {
-            flex_direction: taffy::style::FlexDirection::Column,
-            size: Size {
-                width: taffy::style::Dimension::Points(frame.width() as f32),
-                height: taffy::style::Dimension::Points(frame.height() as f32),
-            },
-            position: Position::Absolute,
-            // align_content: Some(AlignContent::Start),
-            ..Default::default()
-        })?;
+        for (command, _) in commands {
+            command.apply(frame)?;
+        }
+
+        self.last_drawn = Some(DrawnFrame {
+            canvas,
+            commands: drawn,
+        });
+
+        Ok(())
+    }
+
+    /// Like [`VideoUI::render`], but `frame` has to still hold what the last render drew and only
+    /// the areas where something changed since are drawn again. Returns those areas.
+    pub fn render_damaged(&mut self, frame: &mut RgbaImage) -> anyhow::Result<Vec<DamageRect>> {
+        let canvas = UVec2::new(frame.width(), frame.height());
+        let whole_frame = DamageRect {
+            position: UVec2::ZERO,
+            size: canvas,
+        };
+
+        let previous = self
+            .last_drawn
+            .take()
+            .filter(|previous| previous.canvas == canvas);
+        let commands = self.draw_commands(canvas)?;
+        let drawn = drawn_areas(&commands, canvas);
+
+        let mut damage = match previous {
+            Some(previous) => changed_areas(&previous.commands, &drawn),
+            None => vec![whole_frame],
+        };
 
-        let mut elms = self
-            .children
+        // Auto contrast text is colored by everything under its whole box, so it can't be drawn
+        // again in pieces
+        let samples_damage = commands
             .iter()
-            .rev()
-            .map(|child| (root, child))
-            .collect::<Vec<_>>();
-        let mut node_map: HashMap<_, _> = HashMap::default();
+            .zip(&drawn)
+            .any(|((command, _), (_, area))| {
+                matches!(
+                    command,
+                    DrawCommand::Text {
+                        contrast_region: Some(_),
+                        ..
+                    }
+                ) && damage.iter().any(|damage| damage.intersects(area))
+            });
+        if samples_damage {
+            damage = vec![whole_frame];
+        }
+
+        for area in &damage {
+            let mut patch = RgbaImage::new(area.size.x, area.size.y);
+            let origin = area.position.as_ivec2();
+
+            for ((command, _), (_, command_area)) in commands.iter().zip(&drawn) {
+                if command_area.intersects(area) {
+                    command.apply_at(&mut patch, origin)?;
+                }
+            }
+
+            image::imageops::replace(
+                frame,
+                &patch,
+                area.position.x as i64,
+                area.position.y as i64,
+            );
+        }
+
+        drop(commands);
+        self.last_drawn = Some(DrawnFrame {
+            canvas,
+            commands: drawn,
+        });
+
+        Ok(damage)
+    }
 
-        while let Some((parent, styled_node)) = elms.pop() {
-            let (node, children) = styled_node.process(&mut taffy, parent, &self.image_store)?;
+    /// Every draw command for a `canvas` sized frame in the order they are drawn, each with a hash
+    /// that changes whenever what it draws does.
+    fn draw_commands(&mut self, canvas: UVec2) -> anyhow::Result<Vec<(DrawCommand<'_>, u64)>> {
+        // Updaters change the unscaled nodes, so they are scaled again every frame
+        let children = if self.render_scale == 1.0 {
+            &self.children
+        } else {
+            self.scaled_children = self
+                .children
+                .iter()
+                .map(|node| node.scaled(self.render_scale))
+                .collect();
+            &self.scaled_children
+        };
+        let nodes = pre_order(children);
+
+        // The layout from the last frame is reused until a node is marked dirty or anything that
+        // can move one changes, updaters are free to change the nodes directly
+        let mut hasher = DefaultHasher::new();
+        nodes.len().hash(&mut hasher);
+        for node in &nodes {
+            node.layout_key(&mut hasher);
+        }
+        let key = hasher.finish();
+
+        let cached = matches!(
+            &self.layout_cache,
+            Some(cache) if cache.canvas == canvas && cache.key == key
+        );
+        if !cached {
+            self.layout_cache = Some(LayoutCache {
+                canvas,
+                key,
+                layouts: absolute_layouts(children, &self.image_store, &self.font_store, canvas)?,
+            });
+        }
+        let layouts = &self.layout_cache.as_ref().unwrap().layouts;
+
+        // Images are resized up front so the commands can borrow them instead of copying them
+        // every frame
+        if let Some(handle) = &self.background_image {
+            self.image_store
+                .get_resized(handle, canvas, ImageFit::Cover);
+        }
+        for (node, layout) in nodes.iter().zip(layouts) {
+            if let Node::Image { image, fit, .. } = &node.node {
+                self.image_store.get_resized(image, box_size(layout), *fit);
+            }
+        }
+
+        // Commands are drawn by z-index, or layout order for nodes without one, ties are broken by
+        // tree order so that for nodes with the same order the one that comes later in the tree is
+        // drawn on top. The background is always drawn first.
+        let mut commands = vec![(
+            DrawCommand::FillBackground(self.background_color),
+            (i64::MIN, 0),
+            hash_of(self.background_color),
+        )];
+        if let Some(handle) = &self.background_image {
+            let image = self.image_store.resized(handle, canvas, ImageFit::Cover);
+
+            commands.push((
+                DrawCommand::Image {
+                    image: Cow::Borrowed(image),
+                    position: UVec2::ZERO,
+                },
+                (i64::MIN, 1),
+                hash_of(handle),
+            ));
+        }
 
-            node_map.insert(node, styled_node);
+        for (node, layout) in nodes.into_iter().zip(layouts) {
+            let fingerprint = node.fingerprint(layout);
 
-            if let Some(children) = children {
-                elms.extend(children.iter().map(|child| (node, child)).rev());
+            for (idx, (command, order)) in node
+                .into_draw_command(layout, &self.image_store, &self.font_store)
+                .into_iter()
+                .enumerate()
+            {
+                let order = node.z_index.map_or(order as i64, i64::from);
+                let tree_order = commands.len();
+                commands.push((
+                    command,
+                    (order, tree_order),
+                    hash_of((fingerprint, idx, order, tree_order)),
+                ));
             }
         }
 
-        taffy.compute_layout(root, Size::MAX_CONTENT)?;
+        commands.sort_by_key(|(_, key, _)| *key);
+
+        Ok(commands
+            .into_iter()
+            .map(|(command, _, hash)| (command, hash))
+            .collect())
+    }
+
+    /// Lays the UI out on a `canvas` sized frame and reports every node that doesn't fit, either
+    /// because it reaches past the edges of the frame or because its text doesn't fit in its box.
+    pub fn check_overflow(&self, canvas: UVec2) -> anyhow::Result<Vec<Overflow>> {
+        let (taffy, root, node_map) =
+            layout(&self.children, &self.image_store, &self.font_store, canvas)?;
 
-        let mut commands = vec![(DrawCommand::FillBackground(self.background_color), 0)];
+        let mut overflows = Vec::default();
         let mut queued = vec![(Point { x: 0.0, y: 0.0 }, root)];
 
         while let Some((location, key)) = queued.pop() {
-            let mut layout = taffy.layout(key)?.clone();
+            let mut layout = *taffy.layout(key)?;
             layout.location.x += location.x;
             layout.location.y += location.y;
 
             if let Some(node) = node_map.get(&key) {
-                if let Some(command) = node.into_draw_command(&layout, &mut self.image_store) {
-                    commands.push(command);
+                let outside_canvas = layout.location.x < 0.0
+                    || layout.location.y < 0.0
+                    || layout.location.x + layout.size.width > canvas.x as f32
+                    || layout.location.y + layout.size.height > canvas.y as f32;
+
+                #[allow(deprecated)]
+                let text_too_big = match &node.node {
+                    Node::Text {
+                        text,
+                        font,
+                        scale,
+                        line_height,
+                        word_break,
+                        ..
+                    }
+                    | Node::TextCentered {
+                        text,
+                        font,
+                        scale,
+                        line_height,
+                        word_break,
+                        ..
+                    } => {
+                        let font = self.font_store.get(font);
+                        let lines = wrap_text(text, font, *scale, layout.size.width, *word_break);
+
+                        (lines.len() as u32 * line_height) as f32 > layout.size.height
+                            || lines.iter().any(|(_, width)| *width > layout.size.width)
+                    }
+                    _ => false,
+                };
+
+                if outside_canvas || text_too_big {
+                    #[allow(deprecated)]
+                    overflows.push(Overflow {
+                        node: match &node.node {
+                            Node::Text { text, .. } | Node::TextCentered { text, .. } => {
+                                format!("text {text:?}")
+                            }
+                            Node::Image { .. } => "image".into(),
+                            Node::Container(_) => "container".into(),
+                        },
+                        location: (layout.location.x, layout.location.y),
+                        size: (layout.size.width, layout.size.height),
+                    });
                 }
             }
 
@@ -415,26 +1372,683 @@ impl VideoUI {
             );
         }
 
-        commands.sort_by_key(|(_, key)| *key);
+        Ok(overflows)
+    }
+}
 
-        for (command, _) in commands {
-            command.apply(frame)?;
+/// A node that gets clipped when rendered.
+#[derive(Debug, Clone)]
+pub struct Overflow {
+    pub node: String,
+    pub location: (f32, f32),
+    pub size: (f32, f32),
+}
+
+impl std::fmt::Display for Overflow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at {:?} with size {:?} overflows",
+            self.node, self.location, self.size
+        )
+    }
+}
+
+fn layout<'a>(
+    children: &'a [StyledNode],
+    store: &ImageStore,
+    fonts: &FontStore,
+    canvas: UVec2,
+) -> anyhow::Result<(
+    taffy::Taffy,
+    taffy::prelude::Node,
+    HashMap<taffy::prelude::Node, &'a StyledNode>,
+)> {
+    let mut taffy = taffy::Taffy::new();
+
+    let root = taffy.new_leaf(Style {
+        flex_direction: taffy::style::FlexDirection::Column,
+        size: Size {
+            width: taffy::style::Dimension::Points(canvas.x as f32),
+            height: taffy::style::Dimension::Points(canvas.y as f32),
+        },
+        position: Position::Absolute,
+        // align_content: Some(AlignContent::Start),
+        ..Default::default()
+    })?;
+
+    let mut elms = children
+        .iter()
+        .rev()
+        .map(|child| (root, child))
+        .collect::<Vec<_>>();
+    let mut node_map: HashMap<_, _> = HashMap::default();
+
+    while let Some((parent, styled_node)) = elms.pop() {
+        let (node, children) = styled_node.process(&mut taffy, parent, store, fonts)?;
+
+        node_map.insert(node, styled_node);
+
+        if let Some(children) = children {
+            elms.extend(children.iter().map(|child| (node, child)).rev());
         }
+    }
 
+    taffy.compute_layout(root, Size::MAX_CONTENT)?;
+
+    Ok((taffy, root, node_map))
+}
+
+/// Feeds formatted text straight into a hasher.
+struct HashWriter<'a>(&'a mut DefaultHasher);
+
+impl std::fmt::Write for HashWriter<'_> {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        self.0.write(s.as_bytes());
         Ok(())
     }
 }
 
+fn hash_of(value: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The area of the frame each command draws to, with its hash.
+fn drawn_areas(commands: &[(DrawCommand, u64)], canvas: UVec2) -> Vec<(u64, DamageRect)> {
+    commands
+        .iter()
+        .map(|(command, hash)| (*hash, command.area(canvas)))
+        .collect()
+}
+
+/// The areas covered by commands that were drawn last time but not this time, or the other way
+/// around. Something was added, removed, moved or changed in each of them.
+fn changed_areas(previous: &[(u64, DamageRect)], current: &[(u64, DamageRect)]) -> Vec<DamageRect> {
+    let previous_set: HashSet<_> = previous.iter().collect();
+    let current_set: HashSet<_> = current.iter().collect();
+
+    let mut areas = Vec::default();
+    for (_, area) in previous_set.symmetric_difference(&current_set) {
+        if !area.is_empty() && !areas.contains(area) {
+            areas.push(*area);
+        }
+    }
+
+    areas
+}
+
+/// Every node in the tree, each node followed by its children in the order they were declared.
+fn pre_order(children: &[StyledNode]) -> Vec<&StyledNode> {
+    let mut nodes = Vec::default();
+    let mut queued = children.iter().rev().collect::<Vec<_>>();
+
+    while let Some(node) = queued.pop() {
+        nodes.push(node);
+
+        if let Node::Container(children) = &node.node {
+            queued.extend(children.iter().rev());
+        }
+    }
+
+    nodes
+}
+
+/// Lays `children` out on a `canvas` sized frame, returning the layout of every node relative to
+/// the frame in the order [`pre_order`] visits them.
+fn absolute_layouts(
+    children: &[StyledNode],
+    store: &ImageStore,
+    fonts: &FontStore,
+    canvas: UVec2,
+) -> anyhow::Result<Vec<Layout>> {
+    let (taffy, root, node_map) = layout(children, store, fonts, canvas)?;
+
+    let mut layouts = Vec::default();
+    let mut queued = vec![(Point { x: 0.0, y: 0.0 }, root)];
+
+    while let Some((location, key)) = queued.pop() {
+        let mut layout = *taffy.layout(key)?;
+        layout.location.x += location.x;
+        layout.location.y += location.y;
+
+        if node_map.contains_key(&key) {
+            layouts.push(layout);
+        }
+
+        // Children are pushed in reverse so they are popped in the order they were declared
+        queued.extend(
+            taffy
+                .children(key)?
+                .into_iter()
+                .rev()
+                .map(|child| (layout.location, child)),
+        );
+    }
+
+    Ok(layouts)
+}
+
 impl Default for VideoUI {
     fn default() -> Self {
         Self {
             children: Default::default(),
             background_color: [0, 0, 0, 255].into(),
+            background_image: None,
             image_store: Default::default(),
+            font_store: Default::default(),
+            layout_cache: None,
+            last_drawn: None,
+            render_scale: 1.0,
+            scaled_children: Vec::default(),
         }
     }
 }
 
+/// Changes the UI before each frame is rendered. The nodes are laid out again whenever a style or
+/// what a node is measured from changes, see [`VideoUI::mark_dirty`] for anything else.
 pub trait UiUpdater: Send + Sync + 'static {
     fn update(&mut self, frame_idx: u32, ui: &mut VideoUI);
+
+    /// Moves anything animating to where it is `fraction` of the way from `frame_idx` to the next
+    /// frame, for motion blur. Only called after [`UiUpdater::update`] for the same frame, so
+    /// updaters that only change the UI on whole frames can leave it as it is.
+    fn update_between(&mut self, _frame_idx: u32, _fraction: f32, _ui: &mut VideoUI) {}
+
+    /// Forgets anything kept from earlier frames. Called before the first frame, so the same
+    /// updater can drive more than one render of a video.
+    fn restart(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FONT: &[u8] = include_bytes!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/assets/SpecialElite-Regular.ttf"
+    ));
+
+    /// A UI with one absolutely placed, left aligned text node at `top` that is `height` tall.
+    fn text_ui(text: &str, top: f32, height: f32) -> VideoUI {
+        let mut ui = VideoUI::default();
+        let font = ui.add_font("test", rusttype::Font::try_from_bytes(FONT).unwrap());
+        ui.children.push(StyledNode {
+            node: Node::Text {
+                text: text.into(),
+                font,
+                scale: rusttype::Scale::uniform(height),
+                line_height: height as u32,
+                color: Rgba([255, 255, 255, 255]),
+                word_break: WordBreak::default(),
+                align: TextAlign::Left,
+                outline: None,
+                auto_contrast: false,
+            },
+            style: Style {
+                position: Position::Absolute,
+                inset: taffy::prelude::Rect {
+                    left: LengthPercentageAuto::Points(0.0),
+                    right: LengthPercentageAuto::Auto,
+                    top: LengthPercentageAuto::Points(top),
+                    bottom: LengthPercentageAuto::Auto,
+                },
+                size: Size {
+                    width: Dimension::Percent(1.0),
+                    height: Dimension::Points(height),
+                },
+                ..Default::default()
+            },
+            opacity: 1.0,
+            z_index: None,
+        });
+        ui
+    }
+
+    #[test]
+    fn text_starts_at_the_top_of_its_box() {
+        let mut ui = text_ui("Hello", 40.0, 40.0);
+        let mut frame = RgbaImage::new(200, 120);
+        ui.render(&mut frame).unwrap();
+
+        let lit_rows = |rows: std::ops::Range<u32>| {
+            rows.filter(|&y| (0..200).any(|x| frame.get_pixel(x, y).0[0] > 0))
+                .count()
+        };
+
+        assert_eq!(lit_rows(0..40), 0);
+        assert!(lit_rows(40..50) > 0);
+        assert_eq!(lit_rows(80..120), 0);
+    }
+
+    #[test]
+    fn changing_a_style_lays_the_ui_out_again() {
+        let mut ui = text_ui("Hello", 40.0, 40.0);
+        let mut frame = RgbaImage::new(200, 120);
+        ui.render(&mut frame).unwrap();
+
+        // Changed the way updaters do, without marking the UI dirty
+        ui.children[0].style.inset.top = LengthPercentageAuto::Points(0.0);
+        let mut frame = RgbaImage::new(200, 120);
+        ui.render(&mut frame).unwrap();
+
+        let lit_rows = |rows: std::ops::Range<u32>| {
+            rows.filter(|&y| (0..200).any(|x| frame.get_pixel(x, y).0[0] > 0))
+                .count()
+        };
+        assert!(lit_rows(0..10) > 0);
+        assert_eq!(lit_rows(40..120), 0);
+    }
+
+    #[test]
+    fn damaged_renders_match_full_renders() {
+        let mut ui = text_ui("Hello", 40.0, 30.0);
+        let image = ui.add(RgbaImage::from_pixel(8, 8, Rgba([200, 30, 30, 255])));
+        ui.children.push(StyledNode {
+            node: Node::Image {
+                image,
+                fit: ImageFit::Fill,
+                shadow: None,
+            },
+            style: Style {
+                position: Position::Absolute,
+                size: Size {
+                    width: Dimension::Points(30.0),
+                    height: Dimension::Points(30.0),
+                },
+                ..Default::default()
+            },
+            opacity: 1.0,
+            z_index: None,
+        });
+
+        let mut damaged = RgbaImage::new(200, 120);
+        for frame_idx in 0..8 {
+            match frame_idx {
+                1 => ui.children[1].style.inset.left = LengthPercentageAuto::Points(100.0),
+                2 => ui.children[1].opacity = 0.5,
+                3 => {
+                    if let Node::Text { text, .. } = &mut ui.children[0].node {
+                        *text = "Goodbye".into();
+                    }
+                }
+                4 => ui.children[1].z_index = Some(-1),
+                5 => ui.background_color = Rgba([20, 40, 60, 255]),
+                6 => {
+                    ui.children.pop();
+                }
+                _ => {}
+            }
+
+            ui.render_damaged(&mut damaged).unwrap();
+            let mut full = RgbaImage::new(200, 120);
+            ui.render(&mut full).unwrap();
+
+            assert!(damaged == full, "frame {frame_idx} differs");
+        }
+    }
+
+    #[test]
+    fn long_words_break_across_lines() {
+        let font = rusttype::Font::try_from_bytes(FONT).unwrap();
+        let scale = rusttype::Scale::uniform(24.0);
+        let word = "Supercalifragilisticexpialidociousnesses";
+        assert_eq!(word.len(), 40);
+
+        let lines = wrap_line(word, &font, scale, 200.0, WordBreak::Never);
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].1 > 200.0);
+
+        let lines = wrap_line(word, &font, scale, 200.0, WordBreak::Hyphenate);
+        assert!(lines.len() > 1);
+        assert!(lines.iter().all(|(_, width)| *width <= 200.0));
+        assert!(lines[..lines.len() - 1]
+            .iter()
+            .all(|(line, _)| line.ends_with('-')));
+        let joined = lines
+            .iter()
+            .map(|(line, _)| line.trim_end_matches('-'))
+            .collect::<String>();
+        assert_eq!(joined, word);
+
+        // Splitting "WWW" anywhere would leave a single character on a line
+        let narrow = text_width(&font, scale, "WW") - 1.0;
+        assert_eq!(
+            split_word("WWW", &font, scale, narrow, WordBreak::Break),
+            None
+        );
+    }
+
+    #[test]
+    fn equal_orders_are_drawn_in_tree_order() {
+        let mut ui = VideoUI::default();
+        let mut square = |color: [u8; 4]| {
+            let image = ui.add(RgbaImage::from_pixel(4, 4, Rgba(color)));
+            let image = StyledNode {
+                node: Node::Image {
+                    image,
+                    fit: ImageFit::Fill,
+                    shadow: None,
+                },
+                style: Style {
+                    size: Size {
+                        width: Dimension::Points(20.0),
+                        height: Dimension::Points(20.0),
+                    },
+                    ..Default::default()
+                },
+                opacity: 1.0,
+                z_index: None,
+            };
+
+            // Each image is the first child of its own container, so both have the same order
+            StyledNode {
+                node: Node::Container(vec![image]),
+                style: Style {
+                    position: Position::Absolute,
+                    ..Default::default()
+                },
+                opacity: 1.0,
+                z_index: None,
+            }
+        };
+        let red = square([255, 0, 0, 255]);
+        let blue = square([0, 0, 255, 255]);
+        ui.children = vec![red, blue];
+
+        let mut frame = RgbaImage::new(40, 40);
+        ui.render(&mut frame).unwrap();
+        assert_eq!(frame.get_pixel(10, 10), &Rgba([0, 0, 255, 255]));
+
+        // A z-index puts a node above the ones after it
+        if let Node::Container(children) = &mut ui.children[0].node {
+            children[0].z_index = Some(1);
+        }
+        let mut frame = RgbaImage::new(40, 40);
+        ui.render(&mut frame).unwrap();
+        assert_eq!(frame.get_pixel(10, 10), &Rgba([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn oversized_nodes_are_flagged() {
+        let canvas = UVec2::new(200, 120);
+        assert!(text_ui("Hello", 0.0, 40.0)
+            .check_overflow(canvas)
+            .unwrap()
+            .is_empty());
+
+        let overflows = text_ui("Hello", 100.0, 40.0)
+            .check_overflow(canvas)
+            .unwrap();
+        assert_eq!(overflows.len(), 1);
+        assert_eq!(overflows[0].node, "text \"Hello\"");
+        assert_eq!(overflows[0].location, (0.0, 100.0));
+
+        // Fits on the canvas, but the text wraps to more lines than its box is tall
+        let overflows = text_ui("Far too much text for one line", 0.0, 40.0)
+            .check_overflow(canvas)
+            .unwrap();
+        assert_eq!(overflows.len(), 1);
+    }
+
+    #[test]
+    fn combining_marks_stay_with_their_letter_when_wrapping() {
+        let font = rusttype::Font::try_from_bytes(FONT).unwrap();
+        let scale = rusttype::Scale::uniform(24.0);
+        // Every "e" is followed by a combining acute accent
+        let word = "e\u{301}".repeat(30);
+
+        let lines = wrap_line(&word, &font, scale, 100.0, WordBreak::Break);
+        assert!(lines.len() > 1);
+        for (line, _) in &lines {
+            assert!(
+                line.starts_with('e') && line.ends_with('\u{301}'),
+                "{line:?}"
+            );
+        }
+        assert_eq!(
+            lines.into_iter().map(|(line, _)| line).collect::<String>(),
+            word
+        );
+    }
+
+    #[test]
+    fn nodes_share_a_registered_font() {
+        let mut ui = text_ui("Hello", 0.0, 40.0);
+        let mut second = ui.children[0].clone();
+        second.style.inset.top = LengthPercentageAuto::Points(60.0);
+        ui.children.push(second);
+
+        let font = ui.font("test").unwrap();
+        assert_eq!(ui.font_store.fonts.len(), 1);
+        assert!(ui.children.iter().all(|node| matches!(
+            &node.node,
+            Node::Text { font: node_font, .. } if *node_font == font
+        )));
+
+        let mut frame = RgbaImage::new(200, 120);
+        ui.render(&mut frame).unwrap();
+        let top = image::imageops::crop_imm(&frame, 0, 0, 200, 60).to_image();
+        let bottom = image::imageops::crop_imm(&frame, 0, 60, 200, 60).to_image();
+        assert!(top.pixels().any(|pixel| pixel.0[0] > 0));
+        assert!(top == bottom);
+    }
+
+    #[test]
+    fn cover_fills_a_tall_box_without_letterboxing() {
+        let render = |fit: ImageFit| {
+            let mut ui = VideoUI::default();
+            // Red with a green stripe down the left edge
+            let image = ui.add(RgbaImage::from_fn(32, 32, |x, _| {
+                if x < 4 {
+                    Rgba([30, 200, 30, 255])
+                } else {
+                    Rgba([200, 30, 30, 255])
+                }
+            }));
+            ui.children.push(StyledNode {
+                node: Node::Image {
+                    image,
+                    fit,
+                    shadow: None,
+                },
+                style: Style {
+                    size: Size {
+                        width: Dimension::Points(20.0),
+                        height: Dimension::Points(60.0),
+                    },
+                    ..Default::default()
+                },
+                opacity: 1.0,
+                z_index: None,
+            });
+
+            let mut frame = RgbaImage::new(20, 60);
+            ui.render(&mut frame).unwrap();
+            frame
+        };
+        let background = VideoUI::default().background_color;
+
+        // The sides of the square are cropped off, stretching it would keep the stripe
+        let red = |pixel: &Rgba<u8>| pixel.0[0] > 150 && pixel.0[1] < 100;
+        assert!(render(ImageFit::Cover).pixels().all(red));
+        assert!(!red(render(ImageFit::Fill).get_pixel(0, 30)));
+
+        // Contain keeps the whole square, leaving bars above and below it
+        let contained = render(ImageFit::Contain);
+        assert_eq!(*contained.get_pixel(10, 0), background);
+        assert!(red(contained.get_pixel(15, 30)));
+    }
+
+    #[test]
+    fn text_over_a_bright_background_is_dark() {
+        let black = Rgba([0, 0, 0, 255]);
+        let white = Rgba([255, 255, 255, 255]);
+        let (size, corner) = (UVec2::new(20, 20), UVec2::ZERO);
+
+        let bright = RgbaImage::from_pixel(20, 20, Rgba([240, 230, 200, 255]));
+        assert_eq!(
+            contrasting_text_color(&bright, corner, size),
+            (black, white)
+        );
+        let dark = RgbaImage::from_pixel(20, 20, Rgba([30, 40, 60, 255]));
+        assert_eq!(contrasting_text_color(&dark, corner, size), (white, black));
+
+        let mut ui = text_ui("Hello", 40.0, 40.0);
+        ui.background_color = Rgba([240, 230, 200, 255]);
+        if let Node::Text { auto_contrast, .. } = &mut ui.children[0].node {
+            *auto_contrast = true;
+        }
+        let mut frame = RgbaImage::new(200, 120);
+        ui.render(&mut frame).unwrap();
+        // Drawn in white without auto contrast, so there would be nothing dark
+        assert!(frame.pixels().any(|pixel| pixel.0[0] < 50));
+    }
+
+    #[test]
+    fn outlines_are_drawn_around_the_text() {
+        let render = |outline: Option<(Rgba<u8>, u32)>| {
+            let mut ui = text_ui("Hello", 40.0, 40.0);
+            if let Node::Text { outline: node, .. } = &mut ui.children[0].node {
+                *node = outline;
+            }
+            let mut frame = RgbaImage::new(200, 120);
+            ui.render(&mut frame).unwrap();
+            frame
+        };
+        let is_red = |pixel: &Rgba<u8>| *pixel == Rgba([255, 0, 0, 255]);
+
+        assert!(!render(None).pixels().any(is_red));
+
+        // The fill is drawn over the outline, so the text stays white inside a red edge
+        let outlined = render(Some((Rgba([255, 0, 0, 255]), 2)));
+        assert!(outlined.pixels().any(is_red));
+        assert!(outlined
+            .pixels()
+            .any(|pixel| *pixel == Rgba([255, 255, 255, 255])));
+    }
+
+    #[test]
+    fn opacity_fades_nodes_and_is_clamped() {
+        let square = |opacity: f32| {
+            let mut ui = VideoUI::default();
+            let image = ui.add(RgbaImage::from_pixel(4, 4, Rgba([200, 100, 0, 255])));
+            ui.children.push(StyledNode {
+                node: Node::Image {
+                    image,
+                    fit: ImageFit::Fill,
+                    shadow: None,
+                },
+                style: Style {
+                    size: Size {
+                        width: Dimension::Points(4.0),
+                        height: Dimension::Points(4.0),
+                    },
+                    ..Default::default()
+                },
+                opacity,
+                z_index: None,
+            });
+
+            let mut frame = RgbaImage::new(4, 4);
+            ui.render(&mut frame).unwrap();
+            frame.get_pixel(2, 2).0
+        };
+
+        let [r, g, b, _] = square(0.5);
+        assert!(r.abs_diff(100) <= 1 && g.abs_diff(50) <= 1 && b == 0);
+        assert_eq!(square(1.5), [200, 100, 0, 255]);
+        assert_eq!(square(0.0), [0, 0, 0, 255]);
+        assert_eq!(square(-1.0), [0, 0, 0, 255]);
+
+        // Half transparent white text is never brighter than half white
+        let mut ui = text_ui("Hello", 40.0, 40.0);
+        ui.children[0].opacity = 0.5;
+        let mut frame = RgbaImage::new(200, 120);
+        ui.render(&mut frame).unwrap();
+        let brightest = frame.pixels().map(|pixel| pixel.0[0]).max().unwrap();
+        assert!((100..=128).contains(&brightest));
+    }
+
+    #[test]
+    fn shadows_are_drawn_under_their_image() {
+        let mut ui = VideoUI::default();
+        let image = ui.add(RgbaImage::from_pixel(10, 10, Rgba([200, 30, 30, 255])));
+        ui.children.push(StyledNode {
+            node: Node::Image {
+                image,
+                fit: ImageFit::Fill,
+                shadow: Some(Shadow {
+                    offset: IVec2::new(5, 5),
+                    blur: 0,
+                    color: Rgba([0, 0, 255, 255]),
+                }),
+            },
+            style: Style {
+                size: Size {
+                    width: Dimension::Points(10.0),
+                    height: Dimension::Points(10.0),
+                },
+                ..Default::default()
+            },
+            opacity: 1.0,
+            z_index: None,
+        });
+
+        let mut frame = RgbaImage::new(20, 20);
+        ui.render(&mut frame).unwrap();
+        // Covered by the image where they overlap, showing past it where they don't
+        assert_eq!(*frame.get_pixel(7, 7), Rgba([200, 30, 30, 255]));
+        assert_eq!(*frame.get_pixel(12, 12), Rgba([0, 0, 255, 255]));
+        assert_eq!(*frame.get_pixel(16, 2), Rgba([0, 0, 0, 255]));
+
+        // A blurred shadow fades out at its edges
+        let blurred = shadow_image(
+            &RgbaImage::from_pixel(10, 10, Rgba([255, 255, 255, 255])),
+            &Shadow {
+                offset: IVec2::ZERO,
+                blur: 2,
+                color: Rgba([0, 0, 255, 255]),
+            },
+        );
+        assert_eq!(blurred.dimensions(), (14, 14));
+        assert_eq!(blurred.get_pixel(7, 7).0[3], 255);
+        assert!((1..255).contains(&blurred.get_pixel(2, 7).0[3]));
+        assert!(blurred.get_pixel(0, 0).0[3] < blurred.get_pixel(2, 7).0[3]);
+    }
+
+    #[test]
+    fn z_index_draws_text_over_a_later_image() {
+        let render = |z_index: Option<i32>| {
+            let mut ui = text_ui("Hello", 40.0, 40.0);
+            ui.children[0].z_index = z_index;
+            let image = ui.add(RgbaImage::from_pixel(4, 4, Rgba([0, 0, 200, 255])));
+            ui.children.push(StyledNode {
+                node: Node::Image {
+                    image,
+                    fit: ImageFit::Fill,
+                    shadow: None,
+                },
+                style: Style {
+                    position: Position::Absolute,
+                    size: Size {
+                        width: Dimension::Percent(1.0),
+                        height: Dimension::Percent(1.0),
+                    },
+                    ..Default::default()
+                },
+                opacity: 1.0,
+                z_index: None,
+            });
+
+            let mut frame = RgbaImage::new(200, 120);
+            ui.render(&mut frame).unwrap();
+            frame
+        };
+        let has_text = |frame: &RgbaImage| frame.pixels().any(|pixel| pixel.0[0] > 200);
+
+        assert!(!has_text(&render(None)));
+        assert!(has_text(&render(Some(10))));
+    }
 }